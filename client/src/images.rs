@@ -10,7 +10,6 @@ use strum::Display;
 #[derive(Display)]
 #[strum(serialize_all = "kebab-case")]
 pub enum Image {
-    #[allow(unused)]
     Placeholder,
     Dwarf(u64),
     FemaleDwarf(u64),
@@ -378,6 +377,9 @@ impl From<Item> for Image {
             Item::Fairy => Image::Fairy,
             Item::Dog => Image::Dog,
             Item::Wildcat => Image::Wildcat,
+            // These reuse existing artwork until dedicated images exist.
+            // No artwork yet.
+            Item::Herbs | Item::Bandage | Item::Medicine => Image::Placeholder,
         }
     }
 }
@@ -431,6 +433,8 @@ impl From<Occupation> for Image {
             Occupation::Gathering => Image::Gathering,
             Occupation::Fighting => Image::Fighting,
             Occupation::Exploring => Image::Exploring,
+            // No artwork yet.
+            Occupation::Healing => Image::Placeholder,
         }
     }
 }
@@ -4,17 +4,18 @@ use engine_client::{ClientState, EventWrapper, Msg as EngineMsg};
 use engine_shared::{utils::custom_map::CustomMap, GameId};
 use images::Image;
 use itertools::Itertools;
-use rand::RngCore;
 use rustrict::CensorStr;
 use seed::{prelude::*, *};
 use shared::{
-    Bundle, ClientEvent, Craftable, Dwarf, DwarfId, Health, Item, ItemRarity, ItemType, LogMsg, Occupation, Player, Popup, QuestId, QuestType, RewardMode, RewardType, Stats, Territory, Time, TradeType, TribeId, TutorialRequirement, TutorialReward, TutorialStep, UserId, WorldEvent, DISMANTLING_DIVIDER, JOIN_TRIBE_LEVEL, MAX_EFFECTIVENESS, MAX_HEALTH, SPEED, TRADE_MONEY_MULTIPLIER, WINNER_NUM_PREMIUM_DAYS, WINNER_TRIBE_NUM_PREMIUM_DAYS
+    Bundle, Chat, ClientEvent, Craftable, Dwarf, DwarfId, Health, Item, ItemRarity, ItemType, LogMsg, Money, Occupation, Player, Popup, QuestId, QuestType, RewardMode, RewardType, Stats, Territory, Time, TradeType, TribeId, TutorialRequirement, TutorialReward, TutorialStep, UserId, WorldEvent, DISMANTLING_DIVIDER, MAX_EFFECTIVENESS, MAX_HEALTH, TRADE_MONEY_MULTIPLIER, TradeDeal, MAX_FOOD_GOAL_DAYS, MAX_PRODUCTION_GOAL, AUCTION_MAX_DURATION, AUCTION_MIN_DURATION, ONE_HOUR, PlayerStats, ONE_DAY, CraftingJob, MAX_CRAFTING_QUEUE, AutoAction, AutoRule, Perk, EXPERIENCE_BONUS_PERCENT, BATTLE_DURATION, DECLARE_ATTACK_COST, DEFENCE_BONUS_PERCENT, MAX_BATTLE_DWARFS, TribeRole, MAX_TRIBE_NAME_LENGTH, MAX_TRIBE_MOTD_LENGTH, MAX_TRIBE_OFFICERS, KICK_INACTIVE_TIME, TribeTransaction, MAX_TRIBE_TAX_PERCENT
 };
 use std::str::FromStr;
 use strum::Display;
 use time::{macros::datetime, Duration};
 use web_sys::js_sys::Date;
 
+const MAX_BUY_ORDER_QTY: u64 = 1000;
+
 //const ENTER_KEY: u32 = 13;
 //const ESC_KEY: u32 = 27;
 
@@ -45,6 +46,9 @@ enum Icon {
     ChatUnread,
     Manager,
     Tribe,
+    Mail,
+    MailUnread,
+    Healing,
 }
 
 impl Icon {
@@ -74,6 +78,9 @@ impl Icon {
             Icon::ChatUnread => "mark_chat_unread",
             Icon::Manager => "history_edu",
             Icon::Tribe => "handshake",
+            Icon::Mail => "mail",
+            Icon::MailUnread => "mark_email_unread",
+            Icon::Healing => "healing",
         }
     }
 
@@ -124,7 +131,9 @@ pub enum Page {
     Trading,
     Manager,
     Tribe,
-    Visit(Option<UserId>)
+    Visit(Option<UserId>),
+    Stats(Option<UserId>),
+    Messages(Option<UserId>),
 }
 
 impl Page {
@@ -146,6 +155,8 @@ impl Page {
             Some("trading") => Page::Trading,
             Some("manager") => Page::Manager,
             Some("tribe") => Page::Tribe,
+            Some("messages") => Page::Messages(url.next_path_part().map(|id| UserId(id.parse().unwrap()))),
+            Some("stats") => Page::Stats(url.next_path_part().map(|id| UserId(id.parse().unwrap()))),
             _ => Page::Base,
         };
 
@@ -175,6 +186,7 @@ pub enum DwarfsSelect {
     Quest(QuestId, usize),
     Mentor(DwarfId),
     Apprentice(DwarfId),
+    Partner(DwarfId),
 }
 
 pub struct InventoryFilter {
@@ -251,41 +263,65 @@ pub struct QuestsFilter {
 }
 
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ChatTab {
+    World,
+    Tribe,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum SliderType {
     Craft,
     Sell,
     Store,
     Dismantle,
+    Auction,
+    AuctionPrice,
+    AuctionDuration,
+    BuyOrder,
+    BuyOrderPrice,
+    BuyOrderDuration,
+    TribeDeposit,
 }
 
 pub struct Model {
     state: ClientState<shared::State>,
     page: Page,
     message: String,
+    direct_message: String,
     chat_visible: bool,
+    chat_tab: ChatTab,
     history_visible: bool,
     inventory_filter: InventoryFilter,
     trade_filter: TradeFilter,
     dwarfs_filter: DwarfsFilter,
     quests_filter: QuestsFilter,
     map_time: (Time, u64),
+    speed: u64,
     game_id: GameId,
     show_tutorial: bool,
     custom_name: Option<String>,
+    tribe_name: Option<String>,
+    tribe_motd: Option<String>,
+    tribe_money: Money,
+    tribe_recipient: Option<UserId>,
     ad_loaded: bool,
     confirm: Option<ClientEvent>,
     slider: CustomMap<(Item, SliderType), u64>,
+    stats: Option<(Option<UserId>, Vec<PlayerStats>)>,
+    tribe_chat: Chat,
+    conversations: Vec<(UserId, Chat)>,
 }
 
 impl Model {
-    fn sync_timestamp_millis_now(&mut self, time: Time) {
+    fn sync_timestamp_millis_now(&mut self, time: Time, speed: u64) {
         self.map_time.0 = time;
         self.map_time.1 = Date::now() as u64;
+        self.speed = speed;
     }
 
     fn get_timestamp_millis_of(&self, time: Time) -> u64 {
-        (self.map_time.1 as i64 + (time as i64 - self.map_time.0 as i64) * 1000 / SPEED as i64)
+        (self.map_time.1 as i64 + (time as i64 - self.map_time.0 as i64) * 1000 / self.speed as i64)
             as u64
     }
 
@@ -316,23 +352,40 @@ fn init(url: Url, orders: &mut impl Orders<Msg>) -> Model {
 
     let (game_id, page) = Page::from_url(url);
 
+    if let Page::Stats(stats_id) = page {
+        orders.send_msg(Msg::FetchStats(stats_id));
+    }
+    if let Page::Messages(_) = page {
+        orders.send_msg(Msg::FetchConversations);
+    }
+
     Model {
         state: ClientState::init(orders, format!("{WS_PROTOCOL}://{HOST}/game/{game_id}/ws")),
         page,
         message: String::new(),
+        direct_message: String::new(),
         chat_visible: false,
+        chat_tab: ChatTab::World,
         history_visible: false,
         inventory_filter: InventoryFilter::default(),
         dwarfs_filter: DwarfsFilter::default(),
         quests_filter: QuestsFilter::default(),
         trade_filter: TradeFilter::default(),
         map_time: (0, 0),
+        speed: 1,
         game_id,
         show_tutorial: false,
         custom_name: None,
+        tribe_name: None,
+        tribe_motd: None,
+        tribe_money: 0,
+        tribe_recipient: None,
         ad_loaded: false,
         confirm: None,
         slider: CustomMap::new(),
+        stats: None,
+        tribe_chat: Chat::default(),
+        conversations: Vec::new(),
     }
 }
 
@@ -342,12 +395,16 @@ pub enum Msg {
     ChangePage(Page),
     ChangeMessage(String),
     SubmitMessage,
+    ChangeDirectMessage(String),
+    SubmitDirectMessage(UserId),
     ToggleChat,
+    ChangeChatTab(ChatTab),
     ToggleHistory,
     ChangeEquipment(DwarfId, ItemType, Option<Item>),
     AssignToQuest(QuestId, usize, Option<DwarfId>),
     AssignMentor(DwarfId, Option<DwarfId>),
     AssignApprentice(DwarfId, Option<DwarfId>),
+    AssignPartner(DwarfId, Option<DwarfId>),
     InventoryFilterOwned,
     InventoryFilterCraftable,
     InventoryFilterByType(ItemType),
@@ -369,11 +426,25 @@ pub enum Msg {
     ToggleTutorial,
     UpdateName(Option<String>),
     SetName(DwarfId, Option<String>),
+    UpdateTribeName(Option<String>),
+    SetTribeName(String),
+    UpdateTribeMotd(Option<String>),
+    SetTribeMotd(String),
+    SetTribeMoney(Money),
+    SetTribeRecipient(Option<UserId>),
     AdLoaded,
     Confirm(ClientEvent),
     ConfirmYes,
     ConfirmNo,
     SetSlider(Item, SliderType, u64),
+    FetchStats(Option<UserId>),
+    StatsFetched(Option<UserId>, Vec<PlayerStats>),
+    FetchTribeChat,
+    TribeChatFetched(Chat),
+    TribeMessageSent,
+    FetchConversations,
+    ConversationsFetched(Vec<(UserId, Chat)>),
+    DirectMessageSent(UserId),
 }
 
 impl EngineMsg<shared::State> for Msg {}
@@ -389,6 +460,68 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
         Msg::SetSlider(item, ty, value) => {
             model.slider.insert((item, ty), value);
         }
+        Msg::FetchStats(stats_id) => {
+            let url = match stats_id {
+                Some(stats_id) => format!("{}/history/{}", model.base_path(), stats_id.0),
+                None => format!("{}/history", model.base_path()),
+            };
+            orders.perform_cmd(async move {
+                let history = fetch(url)
+                    .await
+                    .ok()?
+                    .check_status()
+                    .ok()?
+                    .json::<Vec<PlayerStats>>()
+                    .await
+                    .ok()?;
+                Some(Msg::StatsFetched(stats_id, history))
+            });
+        }
+        Msg::StatsFetched(stats_id, history) => {
+            model.stats = Some((stats_id, history));
+        }
+        Msg::FetchTribeChat => {
+            let url = format!("{}/tribe-chat", model.base_path());
+            orders.perform_cmd(async move {
+                let chat = fetch(url)
+                    .await
+                    .ok()?
+                    .check_status()
+                    .ok()?
+                    .json::<Chat>()
+                    .await
+                    .ok()?;
+                Some(Msg::TribeChatFetched(chat))
+            });
+        }
+        Msg::TribeChatFetched(chat) => {
+            model.tribe_chat = chat;
+        }
+        Msg::TribeMessageSent => {
+            orders.send_msg(Msg::send_event(ClientEvent::TribeMessage));
+            orders.send_msg(Msg::FetchTribeChat);
+        }
+        Msg::FetchConversations => {
+            let url = format!("{}/conversations", model.base_path());
+            orders.perform_cmd(async move {
+                let conversations = fetch(url)
+                    .await
+                    .ok()?
+                    .check_status()
+                    .ok()?
+                    .json::<Vec<(UserId, Chat)>>()
+                    .await
+                    .ok()?;
+                Some(Msg::ConversationsFetched(conversations))
+            });
+        }
+        Msg::ConversationsFetched(conversations) => {
+            model.conversations = conversations;
+        }
+        Msg::DirectMessageSent(other_id) => {
+            orders.send_msg(Msg::send_event(ClientEvent::DirectMessage(other_id)));
+            orders.send_msg(Msg::FetchConversations);
+        }
         Msg::Confirm(ev) => {
             model.confirm = Some(ev);
         }
@@ -413,6 +546,26 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             )));
             model.custom_name = None;
         }
+        Msg::UpdateTribeName(name) => {
+            model.tribe_name = name;
+        }
+        Msg::SetTribeName(name) => {
+            orders.send_msg(Msg::send_event(ClientEvent::SetTribeName(name)));
+            model.tribe_name = None;
+        }
+        Msg::UpdateTribeMotd(motd) => {
+            model.tribe_motd = motd;
+        }
+        Msg::SetTribeMotd(motd) => {
+            orders.send_msg(Msg::send_event(ClientEvent::SetTribeMotd(motd)));
+            model.tribe_motd = None;
+        }
+        Msg::SetTribeMoney(money) => {
+            model.tribe_money = money;
+        }
+        Msg::SetTribeRecipient(recipient_id) => {
+            model.tribe_recipient = recipient_id;
+        }
         Msg::GameStateEvent(ev) => {
             model.state.update(ev.clone(), orders);
 
@@ -445,12 +598,53 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
 
                 if let EventWrapper::ReceiveGameEvent(ev) = &ev {
                     if let engine_shared::Event::ServerEvent(shared::ServerEvent::Tick) = ev.event {
-                        model.sync_timestamp_millis_now(state.time);
+                        model.sync_timestamp_millis_now(state.time, state.config.speed);
+                    }
+
+                    // The tribe chat is fetched again when a member has written a message.
+                    if let engine_shared::Event::ClientEvent(ClientEvent::TribeMessage, sender_id) = &ev.event {
+                        let own_tribe = model
+                            .state
+                            .get_user_id()
+                            .and_then(|user_id| state.players.get(user_id))
+                            .and_then(|player| player.tribe);
+                        let sender_tribe = state.players.get(sender_id).and_then(|player| player.tribe);
+                        if model.chat_visible
+                            && model.chat_tab == ChatTab::Tribe
+                            && own_tribe.is_some()
+                            && own_tribe == sender_tribe
+                        {
+                            orders.send_msg(Msg::FetchTribeChat);
+                        }
+                    }
+
+                    // New messages are fetched on the messages page, messages in the open
+                    // conversation are read immediately.
+                    if let engine_shared::Event::ClientEvent(ClientEvent::DirectMessage(other_id), user_id) = &ev.event {
+                        if Some(other_id) == model.state.get_user_id() {
+                            if let Page::Messages(open_id) = model.page {
+                                orders.send_msg(Msg::FetchConversations);
+                                if open_id == Some(*user_id) {
+                                    orders.send_msg(Msg::send_event(ClientEvent::ReadDirectMessages(*user_id)));
+                                }
+                            }
+                        }
                     }
                 }
             }
         }
         Msg::ChangePage(page) => {
+            if let Page::Stats(stats_id) = page {
+                if model.page != page {
+                    orders.send_msg(Msg::FetchStats(stats_id));
+                }
+            }
+            if let Page::Messages(other_id) = page {
+                orders.send_msg(Msg::FetchConversations);
+                if let Some(other_id) = other_id {
+                    orders.send_msg(Msg::send_event(ClientEvent::ReadDirectMessages(other_id)));
+                }
+            }
             model.page = page;
             model.custom_name = None;
             web_sys::window().unwrap().scroll_to_with_x_and_y(0.0, 0.0);
@@ -460,15 +654,73 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
         }
         Msg::SubmitMessage => {
             //send(ClientEvent::Message(model.message.clone()));
-            orders.send_msg(Msg::send_event(ClientEvent::Message(model.message.clone())));
+            match model.chat_tab {
+                ChatTab::World => {
+                    orders.send_msg(Msg::send_event(ClientEvent::Message(model.message.clone())));
+                }
+                ChatTab::Tribe => {
+                    // Tribe messages go to the server only, not to every player of the world.
+                    let url = format!("{}/tribe-chat", model.base_path());
+                    let message = model.message.clone();
+                    orders.perform_cmd(async move {
+                        Request::new(url)
+                            .method(Method::Post)
+                            .json(&message)
+                            .ok()?
+                            .fetch()
+                            .await
+                            .ok()?
+                            .check_status()
+                            .ok()?;
+                        Some(Msg::TribeMessageSent)
+                    });
+                }
+            }
             model.message.clear();
         }
+        Msg::ChangeDirectMessage(message) => {
+            model.direct_message = message;
+        }
+        Msg::SubmitDirectMessage(other_id) => {
+            // Direct messages go to the server only, not to every player of the world.
+            let url = format!("{}/conversations/{}", model.base_path(), other_id.0);
+            let message = model.direct_message.clone();
+            orders.perform_cmd(async move {
+                Request::new(url)
+                    .method(Method::Post)
+                    .json(&message)
+                    .ok()?
+                    .fetch()
+                    .await
+                    .ok()?
+                    .check_status()
+                    .ok()?;
+                Some(Msg::DirectMessageSent(other_id))
+            });
+            model.direct_message.clear();
+        }
         Msg::ToggleChat => {
             model.chat_visible = !model.chat_visible;
             if model.chat_visible {
                 model.history_visible = false;
+                if model.chat_tab == ChatTab::Tribe {
+                    orders.send_msg(Msg::FetchTribeChat);
+                }
+            }
+            orders.send_msg(Msg::send_event(match model.chat_tab {
+                ChatTab::World => ClientEvent::ReadChat,
+                ChatTab::Tribe => ClientEvent::ReadTribeChat,
+            }));
+        }
+        Msg::ChangeChatTab(chat_tab) => {
+            model.chat_tab = chat_tab;
+            if model.chat_tab == ChatTab::Tribe {
+                orders.send_msg(Msg::FetchTribeChat);
             }
-            orders.send_msg(Msg::send_event(ClientEvent::ReadChat));
+            orders.send_msg(Msg::send_event(match model.chat_tab {
+                ChatTab::World => ClientEvent::ReadChat,
+                ChatTab::Tribe => ClientEvent::ReadTribeChat,
+            }));
         }
         Msg::ToggleHistory => {
             model.history_visible = !model.history_visible;
@@ -582,6 +834,14 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             }
             orders.send_msg(Msg::send_event(ClientEvent::SetMentor(dwarf_id, mentor_id)));
         }
+        Msg::AssignPartner(dwarf_id, partner_id) => {
+            if partner_id.is_some() {
+                orders.notify(subs::UrlRequested::new(
+                    Url::from_str(&format!("{}/dwarfs/{}", model.base_path(), dwarf_id)).unwrap(),
+                ));
+            }
+            orders.send_msg(Msg::send_event(ClientEvent::SetPartner(dwarf_id, partner_id)));
+        }
         Msg::ChangeEquipment(dwarf_id, item_type, item) => {
             if item.is_some() {
                 orders.notify(subs::UrlRequested::new(
@@ -646,6 +906,8 @@ fn view(model: &Model) -> Node<Msg> {
                     Page::Trading => trades(model, state, user_id),
                     Page::Manager => manager(model, state, user_id),
                     Page::Tribe => tribe(model, client_state, state, user_id),
+                    Page::Stats(stats_id) => stats(model, user_id, stats_id),
+                    Page::Messages(other_id) => messages(model, state, user_id, other_id),
 
                 }],
                 chat(model, state, user_id, client_state),
@@ -966,7 +1228,13 @@ fn confirm(model: &Model, _state: &shared::State, _user_id: &shared::UserId) ->
                         ClientEvent::ReleaseDwarf(..) =>
                             p!["Do you really want to release this dwarf?"],
                         ClientEvent::Sell(..) =>
-                            p!["Do you really want to sell this item on the market?"],
+                            p!["Do you really want to put this item up for auction at its market value?"],
+                        ClientEvent::SetTribeRole(..) =>
+                            p!["Do you really want to hand over the leadership of your tribe?"],
+                        ClientEvent::KickTribeMember(..) =>
+                            p!["Do you really want to kick this member out of your tribe?"],
+                        ClientEvent::SwitchTribe(..) =>
+                            p!["Do you really want to leave your tribe? Your dwarfs will be withdrawn from all battles."],
                         _ => p![],
                     },
                     button![ev(Ev::Click, move |_| Msg::ConfirmYes), "Yes"],
@@ -1003,12 +1271,21 @@ fn name(model: &Model, user_id: &shared::UserId, include_online_status: bool) ->
                 C!["username"],
                 format!(
                     "{}",
-                    client_state
-                        .get_user_data(user_id)
-                        .map(|data| data.username.clone().censor())
-                        .unwrap_or_default()
+                    if let Some(bot_kind) = state.bots.get(user_id) {
+                        format!("{} {}", bot_kind, -user_id.0)
+                    } else {
+                        client_state
+                            .get_user_data(user_id)
+                            .map(|data| data.username.clone().censor())
+                            .unwrap_or_default()
+                    }
                 )
             ],
+            if state.is_bot(user_id) {
+                span![C!["nametag", "bot"], "Bot"]
+            } else {
+                Node::Empty
+            },
             if is_dev {
                 span![C!["nametag", "developer"], "Developer"]
             } else {
@@ -1040,7 +1317,7 @@ fn name(model: &Model, user_id: &shared::UserId, include_online_status: bool) ->
                 span![
                     C![
                         "symbols",
-                        if player.is_online(state.time) {
+                        if player.is_online(state.time, state.config.speed) {
                             "online"
                         } else {
                             "offline"
@@ -1057,11 +1334,15 @@ fn name(model: &Model, user_id: &shared::UserId, include_online_status: bool) ->
     }
 }
 
-fn tribe_name(tribe: TribeId, game_id: GameId) -> Node<Msg> {
-    let mut rng = Image::rng_from_str(&format!("{}-{}", tribe, game_id));
-    let tribe_name = format!("{} Tribe", Dwarf::name(&mut rng));
-    let tribe_color = ((rng.next_u32() % 192) as u8, (rng.next_u32() % 192) as u8, (rng.next_u32() % 192) as u8);
-    span![style![ St::Color => format!("rgb({}, {}, {})", tribe_color.0, tribe_color.1, tribe_color.2) ], tribe_name]
+fn tribe_name(tribe: TribeId, state: &shared::State, game_id: GameId) -> Node<Msg> {
+    let (tribe_name, tribe_color) = shared::default_tribe_name(tribe, game_id);
+    let tribe_name = state
+        .tribes
+        .get(&tribe)
+        .and_then(|tribe| tribe.name.clone())
+        .map(|name| name.censor())
+        .unwrap_or(tribe_name);
+    span![style![ St::Color => tribe_color ], tribe_name]
 }
 
 fn ranking(
@@ -1074,7 +1355,7 @@ fn ranking(
         .players
         .iter()
         .filter(|(user_id, player)| {
-            player.is_active(state.time) && client_state.get_user_data(user_id).is_some()
+            player.is_active(state.time, state.config.speed) && client_state.get_user_data(user_id).is_some()
         })
         .collect();
     players.sort_by_key(|(_, p)| -(p.base.curr_level as i64));
@@ -1102,7 +1383,7 @@ fn ranking(
     
 
         h2!["Ranking"],
-        p![format!("To win this game, you need to meet two conditions. First, expand your settlement until you reach level 100. Second, become the king of this world. If both conditions are met, the game will be over and you will be the winner. As a reward, you get gifted a free premium account for {} days.", WINNER_NUM_PREMIUM_DAYS)],
+        p![format!("To win this game, you need to meet two conditions. First, expand your settlement until you reach level {}. Second, become the king of this world. If both conditions are met, the game will be over and you will be the winner. As a reward, you get gifted a free premium account for {} days.", state.config.max_level, state.config.winner_num_premium_days)],
 
         table![
             C!["ranking"],
@@ -1125,6 +1406,7 @@ fn ranking(
                     if let Some(tribe) = player.tribe.as_ref() {
                         td![tribe_name(
                             *tribe,
+                            state,
                             model.game_id
                         )]
                     } else {
@@ -1142,18 +1424,299 @@ fn ranking(
                             ]
                         } else {
                             Node::Empty
-                        }
+                        },
+                        if !current_user && !state.is_bot(user_id) {
+                            a![
+                                C!["button", "inline"],
+                                attrs! { At::Href => format!("{}/messages/{}", model.base_path(), user_id.0) },
+                                "Message",
+                            ]
+                        } else {
+                            Node::Empty
+                        },
+                        a![
+                            C!["button", "inline"],
+                            attrs! { At::Href => format!("{}/stats/{}", model.base_path(), user_id.0) },
+                            "Stats",
+                        ]
+                    ]
+                ]
+            })
+        ]
+    ]
+}
 
-                        
+fn messages(
+    model: &Model,
+    state: &shared::State,
+    user_id: &shared::UserId,
+    other_id: Option<UserId>,
+) -> Node<Msg> {
+    let Some(player) = state.players.get(user_id) else {
+        return Node::Empty;
+    };
+
+    if let Some(other_id) = other_id {
+        let direct_message = model.direct_message.clone();
+
+        div![
+            C!["content"],
+            h2!["Messages with ", name(model, &other_id, true)],
+            a![
+                C!["button"],
+                attrs! { At::Href => format!("{}/messages", model.base_path()) },
+                "All Conversations",
+            ],
+            div![
+                C!["messages", "conversation"],
+                model
+                    .conversations
+                    .iter()
+                    .find(|(conversation_id, _)| *conversation_id == other_id)
+                    .map(|(_, conversation)| {
+                        conversation
+                            .messages
+                            .iter()
+                            .map(|(sender_id, message, time)| {
+                                p![
+                                    C!["message"],
+                                    span![C!["time"], format!("{} ago, ", fmt_time(state.time - time, false, state.config.speed))],
+                                    span![C!["username"], name(model, sender_id, false), ":"],
+                                    span![C!["message"], format!(" {}", message.censor())]
+                                ]
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_else(|| vec![p!["No messages yet."]]),
+            ],
+            if state.players.contains_key(&other_id) {
+                div![
+                    C!["conversation-input"],
+                    input![
+                        attrs! {At::Type => "text", At::Value => model.direct_message, At::Placeholder => "Type your message here ..."},
+                        input_ev(Ev::Input, Msg::ChangeDirectMessage)
+                    ],
+                    button![
+                        if direct_message.is_empty() {
+                            attrs! {At::Disabled => "true"}
+                        } else {
+                            attrs! {}
+                        },
+                        ev(Ev::Click, move |_| Msg::SubmitDirectMessage(other_id)),
+                        "Send",
                     ]
                 ]
+            } else {
+                Node::Empty
+            }
+        ]
+    } else {
+        let mut conversations = model
+            .conversations
+            .iter()
+            .filter_map(|(other_id, conversation)| {
+                let last_message = conversation.messages.back()?;
+                Some((*other_id, last_message))
+            })
+            .collect::<Vec<_>>();
+        conversations.sort_by_key(|(_, (_, _, time))| std::cmp::Reverse(*time));
+
+        div![
+            C!["content"],
+            h2!["Messages"],
+            p!["Send private messages to other players of this world from the ranking."],
+            if conversations.is_empty() {
+                p!["You don't have any conversations yet."]
+            } else {
+                table![
+                    tr![
+                        th![],
+                        th!["Username"],
+                        th!["Last Message"],
+                        th![]
+                    ],
+                    conversations.iter().map(|(other_id, (_, message, time))| {
+                        let unread = player.unread_conversations.contains(other_id);
+                        tr![
+                            td![if unread { Icon::MailUnread.draw() } else { Icon::Mail.draw() }],
+                            td![name(model, other_id, true)],
+                            td![
+                                if unread { C!["unread"] } else { C![] },
+                                format!("{} ago: {}", fmt_time(state.time - time, false, state.config.speed), message.censor())
+                            ],
+                            td![a![
+                                C!["button", "inline"],
+                                attrs! { At::Href => format!("{}/messages/{}", model.base_path(), other_id.0) },
+                                "Open",
+                            ]]
+                        ]
+                    })
+                ]
+            }
+        ]
+    }
+}
+
+fn stats(model: &Model, user_id: &shared::UserId, stats_id: Option<UserId>) -> Node<Msg> {
+    let history = match &model.stats {
+        Some((fetched_id, history)) if *fetched_id == stats_id => history,
+        _ => {
+            return div![C!["content"], h2!["Statistics"], p!["Loading..."]];
+        }
+    };
+
+    let series = |f: fn(&PlayerStats) -> u64| {
+        history
+            .iter()
+            .map(|stats| (stats.time, f(stats)))
+            .collect::<Vec<_>>()
+    };
+
+    // The samples contain the total number of produced items, the chart shows how many
+    // items were produced per hour between two samples.
+    let produced = enum_iterator::all::<Occupation>()
+        .filter_map(|occupation| {
+            let total = |stats: &PlayerStats| {
+                stats
+                    .produced
+                    .iter()
+                    .find(|(o, _)| *o == occupation)
+                    .map(|(_, qty)| *qty)
+                    .unwrap_or(0)
+            };
+            let points = history
+                .iter()
+                .tuple_windows()
+                .filter(|(prev, next)| next.time > prev.time)
+                .map(|(prev, next)| {
+                    (
+                        next.time,
+                        total(next).saturating_sub(total(prev)) * ONE_HOUR / (next.time - prev.time),
+                    )
+                })
+                .collect::<Vec<_>>();
+            if points.iter().any(|(_, qty)| *qty > 0) {
+                Some((occupation.to_string(), points))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    div![
+        C!["content"],
+        h2![
+            "Statistics",
+            if let Some(stats_id) = stats_id.filter(|stats_id| stats_id != user_id) {
+                span![" of ", name(model, &stats_id, false)]
+            } else {
+                Node::Empty
+            }
+        ],
+        if history.is_empty() {
+            p!["There are no statistics yet, they are recorded once per hour."]
+        } else {
+            div![
+                chart("Level", vec![("Level".to_owned(), series(|stats| stats.level))]),
+                chart("Population", vec![("Dwarfs".to_owned(), series(|stats| stats.dwarfs))]),
+                chart("Food", vec![("Food".to_owned(), series(|stats| stats.food))]),
+                chart("Coins", vec![("Coins".to_owned(), series(|stats| stats.money))]),
+                chart("Average Efficiency", vec![("Efficiency".to_owned(), series(|stats| stats.efficiency))]),
+                chart("Items Produced per Hour", produced),
+            ]
+        }
+    ]
+}
+
+fn chart(title: &str, series: Vec<(String, Vec<(Time, u64)>)>) -> Node<Msg> {
+    const WIDTH: f64 = 1000.0;
+    const HEIGHT: f64 = 300.0;
+
+    let points = series.iter().flat_map(|(_, points)| points.iter());
+    let min_time = points.clone().map(|(time, _)| *time).min().unwrap_or(0);
+    let max_time = points.clone().map(|(time, _)| *time).max().unwrap_or(0);
+    let max_value = points.map(|(_, value)| *value).max().unwrap_or(0).max(1);
+    let time_span = (max_time - min_time).max(1);
+
+    let color = |idx: usize| format!("hsl({}, 60%, 45%)", idx * 360 / series.len().max(1));
+
+    div![
+        C!["chart"],
+        h3![title],
+        svg![
+            attrs! {At::ViewBox => format!("0 0 {WIDTH} {HEIGHT}"), At::PreserveAspectRatio => "none"},
+            series.iter().enumerate().map(|(idx, (_, points))| {
+                polyline![attrs! {
+                    At::Points => points
+                        .iter()
+                        .map(|(time, value)| format!(
+                            "{:.1},{:.1}",
+                            (time - min_time) as f64 / time_span as f64 * WIDTH,
+                            HEIGHT - *value as f64 / max_value as f64 * HEIGHT
+                        ))
+                        .join(" "),
+                    At::Fill => "none",
+                    At::Stroke => color(idx),
+                    At::StrokeWidth => 2,
+                }]
+            })
+        ],
+        div![
+            C!["chart-labels"],
+            span![format!("Day {:.1}", min_time as f64 / ONE_DAY as f64)],
+            span![format!("Max {}", big_number(max_value))],
+            span![format!("Day {:.1}", max_time as f64 / ONE_DAY as f64)],
+        ],
+        if series.len() > 1 {
+            div![
+                C!["chart-legend"],
+                series.iter().enumerate().map(|(idx, (label, _))| {
+                    span![style! {St::Color => color(idx)}, label]
+                })
+            ]
+        } else {
+            Node::Empty
+        }
+    ]
+}
+
+fn production_forecast(player: &Player, state: &shared::State) -> Node<Msg> {
+    let mut forecast = player
+        .production_forecast(state.event.as_ref())
+        .into_iter()
+        .collect::<Vec<_>>();
+    forecast.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    if forecast.is_empty() {
+        return Node::Empty;
+    }
+
+    div![
+        h3!["Production"],
+        p!["The number of items your dwarfs are expected to produce per hour with their current occupations and equipment."],
+        table![
+            tr![th!["Item"], th!["Per Hour"], th!["Per Day"]],
+            forecast.iter().map(|(item, rate)| {
+                tr![
+                    td![format!("{item}")],
+                    td![fmt_rate(*rate)],
+                    td![fmt_rate(*rate * 24.0)],
+                ]
             })
         ]
     ]
 }
 
-fn fmt_time(mut time: u64, precise: bool) -> String {
-    time /= SPEED;
+fn fmt_rate(rate: f64) -> String {
+    if rate >= 10.0 {
+        big_number(rate.round() as u64)
+    } else {
+        format!("{:.1}", rate)
+    }
+}
+
+fn fmt_time(mut time: u64, precise: bool, speed: u64) -> String {
+    time /= speed;
     
     /*if time >= 60 {
         time /= 60;
@@ -1359,7 +1922,17 @@ fn tribe_bar(curr: u64, max: u64, rank: usize, max_rank: usize, markers: Vec<u64
 
 fn dwarf_occupation(dwarf: &Dwarf, player: &Player) -> Node<Msg> {
     if dwarf.is_adult() {
-        if let Some((quest_type, _, _)) = dwarf.participates_in_quest {
+        if dwarf.participates_in_battle.is_some() {
+            div![
+                if dwarf.auto_idle {
+                    span!["Auto-idling, returning to the battle shortly."]
+                } else {
+                    span!["Fighting in a battle for your tribe."]
+                },
+                br![],
+                stars_occupation(dwarf, Occupation::Fighting)
+            ]
+        } else if let Some((quest_type, _, _)) = dwarf.participates_in_quest {
             div![
                 if dwarf.auto_idle {
                     span![format!(
@@ -1490,6 +2063,14 @@ fn dwarf_details(dwarf: Option<&Dwarf>, player: &Player, visit_mode: bool) -> Ve
                 } else {
                     String::new()
                 },
+                dwarf.traits.iter().map(|dwarf_trait| {
+                    span![C!["short-info"], format!("{dwarf_trait}")]
+                }),
+                if let Some(ailment) = dwarf.ailment {
+                    span![C!["short-info", "ailment"], format!("{ailment}")]
+                } else {
+                    Node::Empty
+                },
                 if visit_mode {
                     Vec::new()
                 } else {
@@ -1598,9 +2179,12 @@ fn dwarfs(
             let mut dwarfs = player
                 .dwarfs
                 .iter()
-                .filter(|(_, dwarf)| match mode {
+                .filter(|(dwarf_id, dwarf)| match mode {
                     DwarfsMode::Select(DwarfsSelect::Mentor(_)) => dwarf.is_adult(),
                     DwarfsMode::Select(DwarfsSelect::Apprentice(_)) => !dwarf.is_adult(),
+                    DwarfsMode::Select(DwarfsSelect::Partner(partner_id)) => {
+                        player.can_be_partners(**dwarf_id, partner_id)
+                    }
                     _ => true,
                 })
                 .collect::<Vec<_>>();
@@ -1740,6 +2324,15 @@ fn dwarfs(
                                         ),
                                     ]]
                                 }
+                                DwarfsMode::Select(DwarfsSelect::Partner(partner_id)) => {
+                                    div![button![
+                                        ev(Ev::Click, move |_| Msg::AssignPartner(
+                                            partner_id,
+                                            Some(id)
+                                        )),
+                                        "Assign as Partner",
+                                    ]]
+                                }
                                 DwarfsMode::Select(DwarfsSelect::Apprentice(mentor_id)) => {
                                     div![button![
                                         if dwarf.is_adult() {
@@ -1809,6 +2402,23 @@ fn dwarf(
                         dwarf_occupation(dwarf, player),
                         ],
                         health_bar(dwarf.health, MAX_HEALTH),
+                        if let Some(ailment) = dwarf.ailment {
+                            p![C!["ailment"], strong![format!("{ailment}: ")], ailment.description()]
+                        } else {
+                            Node::Empty
+                        },
+                        dwarf_experience(dwarf, state),
+                        if dwarf.traits.is_empty() {
+                            Node::Empty
+                        } else {
+                            div![
+                                C!["dwarf-traits"],
+                                h3!["Traits"],
+                                ul![dwarf.traits.iter().map(|dwarf_trait| {
+                                    li![strong![format!("{dwarf_trait}: ")], dwarf_trait.description()]
+                                })]
+                            ]
+                        },
 
                         p![
                             if let Some(custom_name) = model.custom_name.as_ref().cloned() {
@@ -1979,7 +2589,16 @@ fn dwarf(
                     C!["occupation"],
                     h3!["Work"],
                     if dwarf.is_adult() {
-                        if let Some((quest_type, quest_idx, dwarf_idx)) = dwarf.participates_in_quest {
+                        if let Some(battle_id) = dwarf.participates_in_battle {
+                            div![
+                                div![button![
+                                    ev(Ev::Click, move |_| Msg::send_event(
+                                        ClientEvent::CommitToBattle(battle_id, dwarf_id, false)
+                                    )),
+                                    "Withdraw from Battle"
+                                ]]
+                            ]
+                        } else if let Some((quest_type, quest_idx, dwarf_idx)) = dwarf.participates_in_quest {
                             div![
                                 div![button![
                                     ev(Ev::Click, move |_| Msg::AssignToQuest(
@@ -2007,6 +2626,7 @@ fn dwarf(
                                                 button![
                                                     if occupation == dwarf.occupation
                                                         || dwarf.participates_in_quest.is_some()
+                                                        || dwarf.participates_in_battle.is_some()
                                                         || !dwarf.is_adult()
                                                     {
                                                         attrs! {At::Disabled => "true"}
@@ -2125,8 +2745,8 @@ fn dwarf(
                             ]
                             
                         }
-                    ]
-                    
+                    ],
+                    family(model, player, dwarf_id, dwarf),
 
                 ]
             ]
@@ -2146,59 +2766,211 @@ fn dwarf(
     }
 }
 
-fn quests(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Node<Msg> {
-    let player = state.players.get(user_id).unwrap();
+fn dwarf_experience(dwarf: &Dwarf, state: &shared::State) -> Node<Msg> {
+    let occupations = enum_iterator::all::<Occupation>()
+        .filter(|occupation| dwarf.experience(*occupation) > 0)
+        .collect::<Vec<_>>();
 
-    let mut quests = state.quests.iter().collect::<Vec<_>>();
-    quests.sort_by_key(|(_, quest)| quest.time_left);
+    if occupations.is_empty() {
+        return Node::Empty;
+    }
 
     div![
-        div![
-            C!["filter"],
-            div![
-                div![
-                    input![
-                        id!["participating"],
-                        attrs! {At::Type => "checkbox", At::Checked => model.quests_filter.participating.as_at_value()},
-                        ev(Ev::Click, |_| Msg::QuestsFilterParticipating),
-                    ],
-                    label![attrs! {At::For => "participating"}, "Participating"]
-                ],
-                div![
-                    input![
-                        id!["none-participating"],
-                        attrs! {At::Type => "checkbox", At::Checked => model.quests_filter.none_participating.as_at_value()},
-                        ev(Ev::Click, |_| Msg::QuestsFilterNoneParticipating),
-                    ],
-                    label![attrs! {At::For => "none-participating"}, "No Participants"]
-                ],
-            ],
-            div![
-                button![
-                    ev(Ev::Click, move |_| Msg::QuestsFilterReset),
-                    "Reset Filter",
-                ],
-            ]
-
-        ],
+        C!["dwarf-experience"],
+        h3!["Experience"],
+        p![format!(
+            "Dwarfs gain experience while working. Every level increases the effectiveness in the occupation by {}%.",
+            EXPERIENCE_BONUS_PERCENT
+        )],
         table![
-            C!["quests", "list"],
-            quests.iter().filter(|(_, quest)| {
-                (if model.quests_filter.participating {
-                    quest.contestants.contains_key(user_id)
-                } else {
-                    true
-                }) && (if model.quests_filter.none_participating {
-                    quest.contestants.is_empty()
-                } else {
-                    true
-                }) && ((player.base.curr_level <= quest.max_level && player.base.curr_level >= quest.min_level) || quest.contestants.contains_key(user_id))
-            }).map(|(quest_id, quest)| {
+            tr![th!["Occupation"], th!["Level"], th!["Next Level"], th!["Perks"]],
+            occupations.into_iter().map(|occupation| {
                 tr![
-                    C!["list-item-row", match quest.quest_type.reward_mode().reward_type() {
-                        RewardType::Fair => "reward-mode-fair",
-                        RewardType::Best => "reward-mode-best",
-                        RewardType::Chance => "reward-mode-chance",
+                    td![format!("{occupation}")],
+                    td![dwarf.experience_level(occupation)],
+                    td![if let Some(next) = dwarf.next_experience_level(occupation) {
+                        format!(
+                            "{} left",
+                            fmt_time(next - dwarf.experience(occupation), false, state.config.speed)
+                        )
+                    } else {
+                        "Maximum reached".to_owned()
+                    }],
+                    td![Perk::all()
+                        .into_iter()
+                        .filter(|perk| dwarf.has_perk(occupation, *perk))
+                        .map(|perk| {
+                            span![
+                                C!["short-info"],
+                                attrs! {At::Title => perk.description()},
+                                format!("{perk}")
+                            ]
+                        })],
+                ]
+            })
+        ]
+    ]
+}
+
+fn family(model: &Model, player: &Player, dwarf_id: DwarfId, dwarf: &Dwarf) -> Node<Msg> {
+    let partner = dwarf.partner.and_then(|partner| player.dwarfs.get(&partner));
+
+    div![
+        h3!["Family"],
+        if dwarf.is_adult() {
+            div![
+                p!["Idle couples can get babies that inherit their stats. Dwarfs without a partner find a random one."],
+                table![C!["list"],
+                    tr![
+                        C!["list-item-row"],
+                        dwarf_image(partner, player),
+                        td![
+                            C!["list-item-content", "grow"],
+                            dwarf_details(partner, player, false),
+                            button![
+                                ev(Ev::Click, move |_| Msg::ChangePage(Page::Dwarfs(DwarfsMode::Select(DwarfsSelect::Partner(dwarf_id))))),
+                                if partner.is_some() {
+                                    "Change Partner"
+                                } else {
+                                    "Select Partner"
+                                }
+                            ],
+                            if partner.is_some() {
+                                vec![
+                                    button![
+                                        ev(Ev::Click, move |_| Msg::AssignPartner(dwarf_id, None)),
+                                        "Remove Partner"
+                                    ],
+                                    a![
+                                        C!["button"],
+                                        attrs! { At::Href => format!("{}/dwarfs/{}", model.base_path(), dwarf.partner.unwrap()) },
+                                        "Dwarf Details"
+                                    ]
+                                ]
+                            } else {
+                                Vec::new()
+                            }
+                        ]
+                    ]
+                ]
+            ]
+        } else {
+            Node::Empty
+        },
+        h4!["Family Tree"],
+        div![
+            C!["family-tree"],
+            if let Some((mother_id, father_id)) = dwarf.parents {
+                div![
+                    C!["family-tree-generation"],
+                    [mother_id, father_id].into_iter().map(|parent_id| {
+                        let grandparents = player
+                            .dwarfs
+                            .get(&parent_id)
+                            .and_then(|parent| parent.parents);
+                        div![
+                            C!["family-tree-member"],
+                            family_member(model, player, parent_id),
+                            if let Some((grandmother_id, grandfather_id)) = grandparents {
+                                div![
+                                    C!["family-tree-grandparents"],
+                                    family_member(model, player, grandmother_id),
+                                    family_member(model, player, grandfather_id),
+                                ]
+                            } else {
+                                Node::Empty
+                            }
+                        ]
+                    })
+                ]
+            } else {
+                p!["The parents of this dwarf are unknown."]
+            },
+            div![
+                C!["family-tree-generation", "family-tree-self"],
+                strong![dwarf.actual_name()],
+            ],
+            if dwarf.children.is_empty() {
+                p!["This dwarf has no children."]
+            } else {
+                div![
+                    C!["family-tree-generation"],
+                    dwarf.children.iter().map(|child_id| family_member(model, player, *child_id))
+                ]
+            },
+        ]
+    ]
+}
+
+fn family_member(model: &Model, player: &Player, dwarf_id: DwarfId) -> Node<Msg> {
+    if let Some(dwarf) = player.dwarfs.get(&dwarf_id) {
+        a![
+            attrs! { At::Href => format!("{}/dwarfs/{}", model.base_path(), dwarf_id) },
+            format!(
+                "{} ({}, {})",
+                dwarf.actual_name(),
+                if dwarf.is_female { "F" } else { "M" },
+                dwarf.age_years()
+            )
+        ]
+    } else {
+        span![C!["unavailable"], "No longer in your settlement"]
+    }
+}
+
+fn quests(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Node<Msg> {
+    let player = state.players.get(user_id).unwrap();
+
+    let mut quests = state.quests.iter().collect::<Vec<_>>();
+    quests.sort_by_key(|(_, quest)| quest.time_left);
+
+    div![
+        div![
+            C!["filter"],
+            div![
+                div![
+                    input![
+                        id!["participating"],
+                        attrs! {At::Type => "checkbox", At::Checked => model.quests_filter.participating.as_at_value()},
+                        ev(Ev::Click, |_| Msg::QuestsFilterParticipating),
+                    ],
+                    label![attrs! {At::For => "participating"}, "Participating"]
+                ],
+                div![
+                    input![
+                        id!["none-participating"],
+                        attrs! {At::Type => "checkbox", At::Checked => model.quests_filter.none_participating.as_at_value()},
+                        ev(Ev::Click, |_| Msg::QuestsFilterNoneParticipating),
+                    ],
+                    label![attrs! {At::For => "none-participating"}, "No Participants"]
+                ],
+            ],
+            div![
+                button![
+                    ev(Ev::Click, move |_| Msg::QuestsFilterReset),
+                    "Reset Filter",
+                ],
+            ]
+
+        ],
+        table![
+            C!["quests", "list"],
+            quests.iter().filter(|(_, quest)| {
+                (if model.quests_filter.participating {
+                    quest.contestants.contains_key(user_id)
+                } else {
+                    true
+                }) && (if model.quests_filter.none_participating {
+                    quest.contestants.is_empty()
+                } else {
+                    true
+                }) && ((player.base.curr_level <= quest.max_level && player.base.curr_level >= quest.min_level) || quest.contestants.contains_key(user_id))
+            }).map(|(quest_id, quest)| {
+                tr![
+                    C!["list-item-row", match quest.quest_type.reward_mode().reward_type() {
+                        RewardType::Fair => "reward-mode-fair",
+                        RewardType::Best => "reward-mode-best",
+                        RewardType::Chance => "reward-mode-chance",
                     }],
                     td![img![
                         C!["list-item-image"],
@@ -2209,7 +2981,7 @@ fn quests(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Nod
                         h3![C!["title"], format!("{}", quest.quest_type)],
                         p![
                             C!["subtitle"],
-                            format!("{} remaining |  Requires {} | Level {} - {}", fmt_time(quest.time_left, true), quest.quest_type.occupation(), quest.min_level, quest.max_level)
+                            format!("{} remaining |  Requires {} | Level {} - {}", fmt_time(quest.time_left, true, state.config.speed), quest.quest_type.occupation(), quest.min_level, quest.max_level)
                         ],
                         if let Some(contestant) = quest.contestants.get(user_id) {
                             let rank = quest
@@ -2261,7 +3033,7 @@ fn quest(
                 div![C!["image-aside"],
                     img![attrs! {At::Src => Image::from(quest.quest_type).as_at_value()}],
                     div![
-                        p![C!["subtitle"], format!("{} remaining.", fmt_time(quest.time_left, true))],
+                        p![C!["subtitle"], format!("{} remaining.", fmt_time(quest.time_left, true, state.config.speed))],
                         if let Some(contestant) = quest.contestants.get(user_id) {
                             let rank = quest.contestants.values().filter(|c| c.achieved_score >= contestant.achieved_score).count();
                             let mut contestants = quest.contestants.values().map(|c| c.achieved_score).collect::<Vec<_>>();
@@ -2516,7 +3288,7 @@ fn base(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Node<
                         div![
                             p![format!(
                                 "You are currently using a guest account that expires in {}. Set your username and password to keep access to your account and play from multiple devices.",
-                                fmt_time((joined.saturating_add(Duration::days(30)).assume_utc().unix_timestamp() - (Date::now() / 1000.0) as i64).max(0) as u64 * SPEED, true)
+                                fmt_time((joined.saturating_add(Duration::days(30)).assume_utc().unix_timestamp() - (Date::now() / 1000.0) as i64).max(0) as u64 * state.config.speed, true, state.config.speed)
                             )],
                             a![
                                 C!["button"],
@@ -2564,7 +3336,7 @@ fn base(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Node<
                 Node::Empty
             },
             */
-            if player.remaining_time_until_starvation(state) <= 60 * 60 * 12 * SPEED {
+            if player.remaining_time_until_starvation(state) <= 60 * 60 * 12 * state.config.speed {
                 div![
                     C!["important"],
                     strong![format!("Your Dwarfs will Starve Soon")],
@@ -2572,7 +3344,7 @@ fn base(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Node<
                         C!["image-aside", "small"],
                         img![attrs! {At::Src => Image::Starvation.as_at_value()}],
                         div![
-                            p![format!("Your dwarfs will start to die of starvation in {}. Make sure that you have enough food to feed your dwarfs.", fmt_time(player.remaining_time_until_starvation(state), true))],
+                            p![format!("Your dwarfs will start to die of starvation in {}. Make sure that you have enough food to feed your dwarfs.", fmt_time(player.remaining_time_until_starvation(state), true, state.config.speed))],
                         ]
                     ]
                 ]
@@ -2590,8 +3362,8 @@ fn base(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Node<
                             match event {
                                 WorldEvent::Drought => p!["There is a drought happening. The drought makes it harder to farm, gather and hunt. Make sure that your dwarfs don't starve!"],
                                 WorldEvent::Flood => p!["A flood has occurred. The flood makes it harder to farm, gather and fish. Make sure that your dwarfs don't starve!"],
-                                WorldEvent::Plague => p!["A plague is happening. During a plague, the health of your dwarfs decreases much faster. Settlements with more dwarfs are affected more than settlements with fewer dwarfs. Make sure that your dwarfs don't die!"],
-                                WorldEvent::Earthquake => p!["An earthquake has occurred. The earthquake makes it harder to mine, rockhound and log. Be aware of your resource production! There is also a higher chance that new dwarfs arrive in your settlement during this event."],
+                                WorldEvent::Plague => p!["A plague is happening. During a plague, the health of your dwarfs decreases much faster. Settlements with more dwarfs are affected more than settlements with fewer dwarfs. Dwarfs also get infected and infect each other until a healer treats them with medicine. Make sure that your dwarfs don't die!"],
+                                WorldEvent::Earthquake => p!["An earthquake has occurred. The earthquake makes it harder to mine, rockhound and log, and miners can get injured. Be aware of your resource production! There is also a higher chance that new dwarfs arrive in your settlement during this event."],
                                 WorldEvent::Tornado => p!["Tornadoes sweep accross the forbidden lands. The tornadoes makes it harder to log, gather and farm. Be aware of your resource production! There is also a higher chance that new dwarfs arrive in your settlement during this event."],
                                 WorldEvent::Carnival => p!["The carnival is in town! During the carnival, the dwarfs are in a festive mood and have less time to work. The carnival also increases the chance of new dwarfs arriving in your settlement."],
                                 WorldEvent::FullMoon => p!["The full moon is shining bright. During the full moon, the dwarfs can't sleep and thus work less efficiently. As they won't sleep well anyway, there is a chance for more children being born."],
@@ -2617,11 +3389,12 @@ fn base(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Node<
                 tr![th!["Money"], td![format!("{} coins", player.money)]],
                 tr![th!["Food"], td![format!("{} food", player.base.food)]],
             ],
+            production_forecast(player, state),
             h3!["Upgrade Settlement"],
             div![
                 C!["image-aside"],
                 img![attrs! {At::Src => Image::from(player.base.village_type()).as_at_value()}],
-                if let Some(requires) = player.base.upgrade_cost() {
+                if let Some(requires) = player.base.upgrade_cost(state.config.max_level) {
                     div![
                         p!["Upgrade your settlement to increase the maximum population and unlock new occupations for your dwarfs. New dwarfs can be collected by doing quests, or they can simply wander into to your settlement from time to time."],
                         h4!["Next Unlocks"],
@@ -2644,11 +3417,16 @@ fn base(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Node<
                         ],
                         h4!["Requires"],
                         bundle(&requires, player, true),
+                        match player.time_until_upgrade_affordable(state.event.as_ref(), state.config.max_level) {
+                            Some(0) => Node::Empty,
+                            Some(time) => p![format!("With your current production, you can afford the next upgrade in about {}.", fmt_time(time, false, state.config.speed))],
+                            None => p!["With your current production, you can't afford the next upgrade. Assign your dwarfs to the occupations that produce the required items."],
+                        },
                         if player.base.build_time > 0 {
                             button![
                                 attrs! {At::Disabled => "true"},
                                 ev(Ev::Click, move |_| Msg::send_event(ClientEvent::UpgradeBase)),
-                                format!("Upgrading ({} remaining)", fmt_time(player.base.build_time, true)),
+                                format!("Upgrading ({} remaining)", fmt_time(player.base.build_time, true, state.config.speed)),
                             ]
                         } else {
                             button![
@@ -2854,12 +3632,292 @@ fn manager(model: &Model, state: &shared::State, user_id: &shared::UserId) -> No
                     ]
                 ],
             ],
+            production_goals(player, is_premium),
         ]
     } else {
         Node::Empty
     }
 }
 
+fn production_goals(player: &Player, is_premium: bool) -> Node<Msg> {
+    let goals = &player.production_goals;
+
+    let mut available = enum_iterator::all::<Item>()
+        .filter(|item| {
+            !goals.items.contains_key(item)
+                && (enum_iterator::all::<Occupation>().any(|occupation| {
+                    occupation.unlocked_at_level() <= player.base.curr_level
+                        && item.item_probability(occupation).is_some()
+                }) || item
+                    .requires()
+                    .map(|(level, _)| level <= player.base.curr_level)
+                    .unwrap_or(false))
+        })
+        .collect::<Vec<_>>();
+    available.sort_by_key(|item| item.to_string());
+
+    div![
+        h2!["Production Goals"],
+        p!["Instead of setting the number of dwarfs for each occupation, you can also set how many items you want to produce per hour. Once every hour, the manager then assigns the dwarfs to the occupations that are needed to reach these goals. The dwarfs that are not needed stay idle."],
+        p!["Goals for items that need to be crafted are planned using the ingredients of the recipe. Enable auto-crafting for these items in your inventory to craft them automatically. Food only counts towards the food goal if it is stored."],
+        p![
+            if is_premium {
+                button![
+                    ev(Ev::Click, move |_| Msg::send_event(ClientEvent::ToggleProductionGoals)),
+                    if goals.enabled { "Disable Production Goals" } else { "Enable Production Goals" },
+                ]
+            } else {
+                a![
+                    C!["premium-feature", "button"],
+                    "Enable Production Goals",
+                    attrs! { At::Href => format!("/store") },
+                ]
+            }
+        ],
+        table![
+            tr![
+                th!["Goal"],
+                th!["Target"],
+                th![],
+            ],
+            tr![
+                td!["Keep Food in Storage for at Least (Days)"],
+                td![input![
+                    attrs! {
+                        At::Type => "number",
+                        At::Min => "0",
+                        At::Max => MAX_FOOD_GOAL_DAYS,
+                        At::Step => "1",
+                        At::Value => goals.food_days,
+                        At::Disabled => (!is_premium).as_at_value(),
+                    },
+                    input_ev(Ev::Input, move |str| {
+                        Msg::send_event(ClientEvent::SetFoodGoal(str.parse().unwrap_or(0)))
+                    })
+                ]],
+                td![],
+            ],
+            goals.items.iter().map(|(item, rate)| {
+                let item = *item;
+                tr![
+                    td![format!("{item} per Hour")],
+                    td![input![
+                        attrs! {
+                            At::Type => "number",
+                            At::Min => "0",
+                            At::Max => MAX_PRODUCTION_GOAL,
+                            At::Step => "1",
+                            At::Value => rate,
+                            At::Disabled => (!is_premium).as_at_value(),
+                        },
+                        input_ev(Ev::Input, move |str| {
+                            Msg::send_event(ClientEvent::SetProductionGoal(item, str.parse().unwrap_or(0)))
+                        })
+                    ]],
+                    td![button![
+                        C!["inline"],
+                        if is_premium { attrs! {} } else { attrs! {At::Disabled => "true"} },
+                        ev(Ev::Click, move |_| Msg::send_event(ClientEvent::SetProductionGoal(item, 0))),
+                        "Remove",
+                    ]],
+                ]
+            }),
+        ],
+        if is_premium {
+            p![select![
+                option![attrs! {At::Value => "", At::Selected => true.as_at_value()}, "Add a production goal ..."],
+                available.iter().map(|item| option![attrs! {At::Value => item.to_string()}, item.to_string()]),
+                input_ev(Ev::Change, |value| {
+                    enum_iterator::all::<Item>()
+                        .find(|item| item.to_string() == value)
+                        .map(|item| Msg::send_event(ClientEvent::SetProductionGoal(item, 10)))
+                })
+            ]]
+        } else {
+            Node::Empty
+        },
+    ]
+}
+
+fn crafting_queue(player: &Player, state: &shared::State) -> Node<Msg> {
+    if player.crafting_queue.is_empty() {
+        return Node::Empty;
+    }
+
+    div![
+        C!["crafting-queue"],
+        h3![format!(
+            "Crafting Queue ({}/{})",
+            player.crafting_queue.len(),
+            MAX_CRAFTING_QUEUE
+        )],
+        table![
+            tr![th!["Item"], th!["Status"], th![]],
+            player.crafting_queue.iter().enumerate().map(|(idx, job)| {
+                tr![
+                    td![format!("{}x {}", job.qty, job.item)],
+                    td![if job.started {
+                        format!("{} left", fmt_time(job.time_left, true, state.config.speed))
+                    } else {
+                        format!("Queued, takes {}", fmt_time(job.time_left, true, state.config.speed))
+                    }],
+                    td![button![
+                        ev(Ev::Click, move |_| Msg::send_event(ClientEvent::CancelCraft(idx))),
+                        "Cancel",
+                    ]],
+                ]
+            })
+        ]
+    ]
+}
+
+fn auto_rules(player: &Player, state: &shared::State, is_premium: bool) -> Node<Msg> {
+    let rules = &player.auto_functions.rules;
+    if rules.is_empty() {
+        return Node::Empty;
+    }
+
+    // The preview shows what the rules do with the items produced in the next hour.
+    let next_hour = player
+        .production_forecast(state.event.as_ref())
+        .into_iter()
+        .map(|(item, rate)| (item, rate.round() as u64))
+        .collect::<Bundle<Item>>();
+    let preview = player.preview_auto_rules(next_hour);
+
+    let items_text = |items: &Bundle<Item>| {
+        items
+            .clone()
+            .sorted_by_rarity()
+            .into_iter()
+            .map(|(item, n)| format!("{n}x {item}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    div![
+        C!["auto-rules"],
+        h3!["Automation Rules"],
+        p!["The rules are applied from top to bottom whenever you receive items. Use the inventory to add new rules."],
+        table![
+            tr![
+                th!["Rule"],
+                th!["Keep at Least"],
+                th!["Only While Food Lasts Less Than (Days)"],
+                th!["Next Hour"],
+                th![],
+            ],
+            rules.iter().enumerate().map(|(idx, rule)| {
+                let keep_rule = rule.clone();
+                let food_rule = rule.clone();
+                tr![
+                    td![format!("{} {}", rule.action, rule.item)],
+                    td![input![
+                        attrs! {
+                            At::Type => "number",
+                            At::Min => "0",
+                            At::Step => "1",
+                            At::Value => rule.keep,
+                            At::Disabled => (!is_premium).as_at_value(),
+                        },
+                        input_ev(Ev::Input, move |str| {
+                            Msg::send_event(ClientEvent::UpdateAutoRule(
+                                idx,
+                                AutoRule {
+                                    keep: str.parse().unwrap_or(0),
+                                    ..keep_rule
+                                },
+                            ))
+                        })
+                    ]],
+                    td![input![
+                        attrs! {
+                            At::Type => "number",
+                            At::Min => "1",
+                            At::Step => "1",
+                            At::Value => rule.max_food_days.map(|days| days.to_string()).unwrap_or_default(),
+                            At::Placeholder => "Always",
+                            At::Disabled => (!is_premium).as_at_value(),
+                        },
+                        input_ev(Ev::Input, move |str| {
+                            Msg::send_event(ClientEvent::UpdateAutoRule(
+                                idx,
+                                AutoRule {
+                                    max_food_days: str.parse().ok(),
+                                    ..food_rule
+                                },
+                            ))
+                        })
+                    ]],
+                    td![preview.get(idx).map(|outcome| {
+                        if outcome.consumed.is_empty() {
+                            "Nothing".to_owned()
+                        } else if rule.action == AutoAction::Store {
+                            format!("Stores {} for {} food", items_text(&outcome.consumed), outcome.food)
+                        } else {
+                            format!(
+                                "Uses {} for {}",
+                                items_text(&outcome.consumed),
+                                items_text(&outcome.produced)
+                            )
+                        }
+                    })],
+                    td![
+                        button![
+                            C!["inline"],
+                            attrs! {At::Disabled => (idx == 0 || !is_premium).as_at_value()},
+                            ev(Ev::Click, move |_| Msg::send_event(ClientEvent::MoveAutoRuleUp(idx))),
+                            "Up",
+                        ],
+                        button![
+                            C!["inline"],
+                            attrs! {At::Disabled => (!is_premium).as_at_value()},
+                            ev(Ev::Click, move |_| Msg::send_event(ClientEvent::RemoveAutoRule(idx))),
+                            "Remove",
+                        ],
+                    ],
+                ]
+            })
+        ]
+    ]
+}
+
+fn crafting_plan(model: &Model, player: &Player, item: Item) -> Node<Msg> {
+    let qty = model
+        .slider
+        .get(&(item, SliderType::Craft))
+        .copied()
+        .unwrap_or_default()
+        .max(1);
+    let target = Bundle::new().add(item, qty);
+    let plan = player.inventory.plan_crafting(&target);
+
+    // Nothing to plan if the item can be crafted directly.
+    if plan.steps.len() <= 1 && plan.missing.is_empty() {
+        return Node::Empty;
+    }
+
+    div![
+        C!["crafting-plan"],
+        h4![format!("Crafting Plan for {qty}x {item}")],
+        ol![plan
+            .steps
+            .iter()
+            .map(|(item, n)| li![format!("Craft {n}x {item}")])],
+        if plan.missing.is_empty() {
+            button![
+                ev(Ev::Click, move |_| Msg::send_event(ClientEvent::CraftPlan(target))),
+                "Queue All Steps",
+            ]
+        } else {
+            div![
+                p!["Missing raw materials:"],
+                bundle(&plan.missing, player, false),
+            ]
+        },
+    ]
+}
+
 fn inventory_options(
     model: &Model,
     player: &Player,
@@ -2883,8 +3941,12 @@ fn inventory_options(
                     player,
                     true,
                 ),
+                p![format!(
+                    "Crafting takes {} per item.",
+                    fmt_time(CraftingJob::duration(item, 1), true, model.speed)
+                )],
                 if player.base.curr_level >= level {
-                    if player.auto_functions.auto_craft.contains(&item) && is_premium {
+                    if player.auto_functions.has_rule(AutoAction::Craft, item) && is_premium {
                         button![
                             ev(Ev::Click, move |_| Msg::send_event(
                                 ClientEvent::ToggleAutoCraft(item)
@@ -2905,7 +3967,7 @@ fn inventory_options(
                                 model,
                                 item,
                                 SliderType::Craft,
-                                |_| "Craft".to_owned(),
+                                |_| "Queue".to_owned(),
                                 max.min(1),
                                 max,
                                 ClientEvent::Craft,
@@ -2930,6 +3992,7 @@ fn inventory_options(
                 } else {
                     p!["Unlocked at level ", level]
                 },
+                crafting_plan(model, player, item),
             ]
         } else {
             Vec::new()
@@ -2945,7 +4008,7 @@ fn inventory_options(
             if matches!(
                 item.item_type(),
                 Some(ItemType::Tool | ItemType::Jewelry | ItemType::Clothing)
-            ) && (max > 0 || player.auto_functions.auto_dismantle.contains(&item))
+            ) && (max > 0 || player.auto_functions.has_rule(AutoAction::Dismantle, item))
             {
                 vec![
                     h4!["Dismantle Item"],
@@ -2964,7 +4027,7 @@ fn inventory_options(
                         player,
                         false,
                     ),
-                    if player.auto_functions.auto_dismantle.contains(&item) && is_premium {
+                    if player.auto_functions.has_rule(AutoAction::Dismantle, item) && is_premium {
                         button![
                             ev(Ev::Click, move |_| Msg::send_event(
                                 ClientEvent::ToggleAutoDismantle(item)
@@ -3007,7 +4070,7 @@ fn inventory_options(
         if item.nutritional_value().is_some() {
             vec![
                 h4!["Food Storage"],
-                if player.auto_functions.auto_store.contains(&item) && is_premium {
+                if player.auto_functions.has_rule(AutoAction::Store, item) && is_premium {
                     button![
                         ev(Ev::Click, move |_| Msg::send_event(
                             ClientEvent::ToggleAutoStore(item)
@@ -3044,35 +4107,195 @@ fn inventory_options(
         } else {
             Vec::new()
         },
-        vec![
-            h4!["Sell Item"],
-            slider(
-                model,
-                item,
-                SliderType::Sell,
-                move |n| {
-                    format!(
-                        "Sell ({} coins)",
-                        item.money_value(n) * TRADE_MONEY_MULTIPLIER
-                    )
-                },
-                n.min(1),
-                n,
-                ClientEvent::Sell,
-                move |n| n > 0 && item.money_value(n) * TRADE_MONEY_MULTIPLIER == 0,
-                None,
-            ),
-        ],
+        if player.tribe.is_some() {
+            vec![
+                h4!["Tribe Storehouse"],
+                slider(
+                    model,
+                    item,
+                    SliderType::TribeDeposit,
+                    |_| "Deposit".to_owned(),
+                    n.min(1),
+                    n,
+                    ClientEvent::DepositTribeItems,
+                    |n| n == 0,
+                    None,
+                ),
+            ]
+        } else {
+            Vec::new()
+        },
+        {
+            let money = player.money;
+
+            vec![
+                h4!["Sell Item"],
+                p![format!(
+                    "Put your items up for auction at their market value for {}. The listing fee is not refunded if nobody buys them.",
+                    fmt_time(AUCTION_MIN_DURATION, false, model.speed)
+                )],
+                slider(
+                    model,
+                    item,
+                    SliderType::Sell,
+                    move |n| {
+                        format!(
+                            "Sell ({} coins, fee {})",
+                            TradeDeal::market_price(item, n),
+                            TradeDeal::listing_fee(TradeDeal::market_price(item, n))
+                        )
+                    },
+                    n.min(1),
+                    n,
+                    ClientEvent::Sell,
+                    move |n| n == 0 || TradeDeal::listing_fee(TradeDeal::market_price(item, n)) > money,
+                    None,
+                ),
+            ]
+        },
+        if n > 0 {
+            let reserve_price = model
+                .slider
+                .get(&(item, SliderType::AuctionPrice))
+                .copied()
+                .unwrap_or((item.money_value(1) * TRADE_MONEY_MULTIPLIER).max(2));
+            let duration = model
+                .slider
+                .get(&(item, SliderType::AuctionDuration))
+                .copied()
+                .unwrap_or(AUCTION_MIN_DURATION);
+            let listing_fee = TradeDeal::listing_fee(reserve_price);
+            let money = player.money;
+
+            vec![
+                h4!["Auction Item"],
+                p![format!(
+                    "List your items on the market and let other players bid on them. The listing fee of {} coins is not refunded if nobody meets your reserve price.",
+                    listing_fee
+                )],
+                trade_options(
+                    item,
+                    "Reserve Price ",
+                    SliderType::AuctionPrice,
+                    reserve_price,
+                    SliderType::AuctionDuration,
+                    duration,
+                ),
+                slider(
+                    model,
+                    item,
+                    SliderType::Auction,
+                    move |n| format!("Auction {}x for at least {} coins", n, reserve_price),
+                    n.min(1),
+                    n,
+                    move |item, n| ClientEvent::Auction(item, n, reserve_price, duration),
+                    move |n| n == 0 || reserve_price <= 1 || money < listing_fee,
+                    None,
+                ),
+            ]
+        } else {
+            Vec::new()
+        },
+        if item.item_type().is_some()
+            && item
+                .requires()
+                .map(|(level, _)| player.base.curr_level >= level)
+                .unwrap_or(true)
+        {
+            let max_price = model
+                .slider
+                .get(&(item, SliderType::BuyOrderPrice))
+                .copied()
+                .unwrap_or((item.money_value(1) * TRADE_MONEY_MULTIPLIER).max(2));
+            let duration = model
+                .slider
+                .get(&(item, SliderType::BuyOrderDuration))
+                .copied()
+                .unwrap_or(AUCTION_MIN_DURATION);
+            let listing_fee = TradeDeal::listing_fee(max_price);
+            let money = player.money;
+
+            vec![
+                h4!["Buy Order"],
+                p![format!(
+                    "Ask other players to sell you this item. Your coins are kept until the deal ends, the listing fee of {} coins is not refunded.",
+                    listing_fee
+                )],
+                trade_options(
+                    item,
+                    "Maximum Price ",
+                    SliderType::BuyOrderPrice,
+                    max_price,
+                    SliderType::BuyOrderDuration,
+                    duration,
+                ),
+                slider(
+                    model,
+                    item,
+                    SliderType::BuyOrder,
+                    move |n| format!("Buy {}x for at most {} coins", n, max_price),
+                    1,
+                    MAX_BUY_ORDER_QTY,
+                    move |item, n| ClientEvent::BuyOrder(item, n, max_price, duration),
+                    move |n| n == 0 || max_price <= 1 || money < max_price + listing_fee,
+                    None,
+                ),
+            ]
+        } else {
+            Vec::new()
+        },
     ]
     .into_iter()
     .flatten()
     .collect()
 }
 
-fn inventory(
-    model: &Model,
-    state: &shared::State,
-    user_id: &shared::UserId,
+fn trade_options(
+    item: Item,
+    price_label: &str,
+    price_slider: SliderType,
+    price: Money,
+    duration_slider: SliderType,
+    duration: Time,
+) -> Node<Msg> {
+    div![
+        C!["slider"],
+        label![price_label],
+        input![
+            C!["slider-number"],
+            attrs! {
+                At::Type => "number",
+                At::Min => "2",
+                At::Value => price,
+            },
+            input_ev(Ev::Input, move |v| {
+                Msg::SetSlider(item, price_slider, v.parse().unwrap_or(2).max(2))
+            }),
+        ],
+        label![" Duration "],
+        select![
+            (AUCTION_MIN_DURATION / ONE_HOUR..=AUCTION_MAX_DURATION / ONE_HOUR)
+                .filter(|hours| *hours == 1 || *hours % 4 == 0)
+                .map(|hours| {
+                    option![
+                        attrs! {
+                            At::Value => hours * ONE_HOUR,
+                            At::Selected => (hours * ONE_HOUR == duration).as_at_value(),
+                        },
+                        format!("{} hours", hours)
+                    ]
+                }),
+            input_ev(Ev::Change, move |v| {
+                Msg::SetSlider(item, duration_slider, v.parse().unwrap_or(AUCTION_MIN_DURATION))
+            }),
+        ],
+    ]
+}
+
+fn inventory(
+    model: &Model,
+    state: &shared::State,
+    user_id: &shared::UserId,
     mode: InventoryMode,
 ) -> Node<Msg> {
     if let Some(player) = state.players.get(user_id) {
@@ -3086,7 +4309,14 @@ fn inventory(
             .map(|t| (t, player.inventory.items.get(&t).copied().unwrap_or(0)))
             .collect();
 
+        let forecast = player.production_forecast(state.event.as_ref());
+
         div![
+            if let InventoryMode::Overview = mode {
+                vec![crafting_queue(player, state), auto_rules(player, state, is_premium)]
+            } else {
+                Vec::new()
+            },
             div![
                 C!["filter"],
                 div![
@@ -3180,10 +4410,7 @@ fn inventory(
                             && !model.inventory_filter.craftable))
                         && (
                             if model.inventory_filter.auto {
-                                player.auto_functions.auto_craft.contains(item)
-                                || player.auto_functions.auto_sell.contains(item)
-                                || player.auto_functions.auto_store.contains(item)
-                                || player.auto_functions.auto_dismantle.contains(item)
+                                player.auto_functions.has_any_rule(*item)
                             } else {
                                 true
                             }
@@ -3235,6 +4462,11 @@ fn inventory(
                     } else {
                         td![
                             C!["list-item-content"],
+                            if let Some(rate) = forecast.get(&item) {
+                                p![C!["production-forecast"], format!("Expected production: {} per hour", fmt_rate(*rate))]
+                            } else {
+                                Node::Empty
+                            },
                             inventory_options(model, player, item, n, is_premium)
                         ]
                     }
@@ -3300,16 +4532,19 @@ fn trades(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Nod
                 trades 
                 .into_iter()
                 .filter(|(_, trade_deal)| {
-                        trade_deal.user_trade_type == TradeType::Buy &&
+                        let can_afford = match trade_deal.user_trade_type {
+                            TradeType::Buy => player.money >= trade_deal.next_bid,
+                            TradeType::Sell => player.inventory.items.check_remove(&trade_deal.items),
+                        };
                         ((if model.trade_filter.can_afford {
-                            player.money >= trade_deal.next_bid
+                            can_afford
                         } else {
                             false
                         }) || (if model.trade_filter.my_bids {
                             if let Some((highest_bidder_user_id, _)) = trade_deal.highest_bidder {
-                                highest_bidder_user_id == *user_id
+                                highest_bidder_user_id == *user_id || trade_deal.creator == Some(*user_id)
                             } else {
-                                false
+                                trade_deal.creator == Some(*user_id)
                             }
                         } else {
                             false
@@ -3332,7 +4567,10 @@ fn trades(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Nod
                     } else {
                         false
                     };
-                    let can_afford = player.money >= trade_deal.next_bid;
+                    let can_afford = match trade_deal.user_trade_type {
+                        TradeType::Buy => player.money >= trade_deal.next_bid,
+                        TradeType::Sell => player.inventory.items.check_remove(&trade_deal.items),
+                    };
 
                     tr![
 
@@ -3348,34 +4586,75 @@ fn trades(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Nod
                     item_details(item, n),
                     td![
                         C!["list-item-content"],
-                        h4![C!["title"], "Cost" ],
+                        match trade_deal.user_trade_type {
+                            TradeType::Buy => h4![C!["title"], "Cost" ],
+                            TradeType::Sell => h4![C!["title"], "Wanted" ],
+                        },
                         p![C!["subtitle"], format!("{} coins", trade_deal.next_bid)],
-                        p![format!("Deal ends in {}.", fmt_time(trade_deal.time_left, true))],
+                        p![format!("Deal ends in {}.", fmt_time(trade_deal.time_left, true, state.config.speed))],
                         if trade_deal.creator == Some(*user_id) {
-                            vec![p![format!("You created this deal.")]]
-                        } else {
                             vec![
-                                if !can_afford && !highest_bidder_is_you {
-                                    p![format!("You can't afford this deal.")]
-                                } else {
-                                    Node::Empty
-                                },
-                                if let Some((highest_bidder_user_id, highest_bidder_money)) = trade_deal.highest_bidder {
-                                    if highest_bidder_user_id == *user_id {
-                                        p![format!("You are the highest bidder with {} coins.", highest_bidder_money)]
-                                    } else {
+                                p![format!("You created this deal.")],
+                                match (trade_deal.user_trade_type, trade_deal.highest_bidder) {
+                                    (TradeType::Buy, Some((_, highest_bidder_money))) => {
                                         p![format!("Highest bidder has offered {} coins.", highest_bidder_money)]
                                     }
-                                } else {
-                                    Node::Empty
-                                },                                                 
-                                button![
-                                    attrs! { At::Disabled => (highest_bidder_is_you || !can_afford).as_at_value() },
-                                    ev(Ev::Click, move |_| Msg::send_event(ClientEvent::Bid(trade_id))),
-                                    format!("Bid {} coins", trade_deal.next_bid)
-                                ]
+                                    (TradeType::Buy, None) => {
+                                        p![format!("Nobody has met your reserve price yet.")]
+                                    }
+                                    (TradeType::Sell, Some((_, lowest_seller_money))) => {
+                                        p![format!("Lowest seller has offered the items for {} coins.", lowest_seller_money)]
+                                    }
+                                    (TradeType::Sell, None) => {
+                                        p![format!("Nobody has offered the items yet.")]
+                                    }
+                                },
                             ]
-                            
+                        } else {
+                            match trade_deal.user_trade_type {
+                                TradeType::Buy => vec![
+                                    if !can_afford && !highest_bidder_is_you {
+                                        p![format!("You can't afford this deal.")]
+                                    } else {
+                                        Node::Empty
+                                    },
+                                    if let Some((highest_bidder_user_id, highest_bidder_money)) = trade_deal.highest_bidder {
+                                        if highest_bidder_user_id == *user_id {
+                                            p![format!("You are the highest bidder with {} coins.", highest_bidder_money)]
+                                        } else {
+                                            p![format!("Highest bidder has offered {} coins.", highest_bidder_money)]
+                                        }
+                                    } else {
+                                        Node::Empty
+                                    },                                                 
+                                    button![
+                                        attrs! { At::Disabled => (highest_bidder_is_you || !can_afford).as_at_value() },
+                                        ev(Ev::Click, move |_| Msg::send_event(ClientEvent::Bid(trade_id))),
+                                        format!("Bid {} coins", trade_deal.next_bid)
+                                    ]
+                                ],
+                                TradeType::Sell => vec![
+                                    if !can_afford && !highest_bidder_is_you {
+                                        p![format!("You don't have the items for this deal.")]
+                                    } else {
+                                        Node::Empty
+                                    },
+                                    if let Some((lowest_seller_user_id, lowest_seller_money)) = trade_deal.highest_bidder {
+                                        if lowest_seller_user_id == *user_id {
+                                            p![format!("You are the lowest seller with {} coins.", lowest_seller_money)]
+                                        } else {
+                                            p![format!("Lowest seller has offered the items for {} coins.", lowest_seller_money)]
+                                        }
+                                    } else {
+                                        Node::Empty
+                                    },
+                                    button![
+                                        attrs! { At::Disabled => (highest_bidder_is_you || !can_afford || trade_deal.next_bid == 0).as_at_value() },
+                                        ev(Ev::Click, move |_| Msg::send_event(ClientEvent::Bid(trade_id))),
+                                        format!("Sell for {} coins", trade_deal.next_bid)
+                                    ]
+                                ],
+                            }
                         },
  
                     ]
@@ -3391,7 +4670,7 @@ fn trades(model: &Model, state: &shared::State, user_id: &shared::UserId) -> Nod
 fn tribe(model: &Model, client_state: &ClientState<shared::State>, state: &shared::State, user_id: &shared::UserId) -> Node<Msg> {
     if let Some(player) = state.players.get(user_id) {
         if let Some(tribe_id) = player.tribe {
-            //let tribe = state.tribes.get(&tribe_id).unwrap();
+            let tribe = state.tribes.get(&tribe_id).unwrap();
             let username = &client_state
                     .get_user_data(user_id)
                     .map(|data| data.username.clone().censor())
@@ -3454,11 +4733,16 @@ fn tribe(model: &Model, client_state: &ClientState<shared::State>, state: &share
             
                 h2!["Your Tribe"],
                 p!["Spend your fame points for your tribe to conquer territories. Controlling territories rewards you with powerful dwarfs that join your settlement more frequently. You can earn tribe points by winning quests that have a single winner with the most XP collected (red quests)."],
-                p![format!("If the winner of this world is from your tribe, you will earn a free premium account for {} days, so make sure to support your tribe members.", WINNER_TRIBE_NUM_PREMIUM_DAYS)],
+                p![format!("Tribes can also declare an attack on a territory they don't control. During the battle, the members of both tribes can send their dwarfs to fight for {}. The tribe with the higher fighting score takes control of the territory, while the defenders get a bonus of {}%. Be careful, all fighters get hurt in the battle, and the losers much more.", fmt_time(BATTLE_DURATION, false, state.config.speed), DEFENCE_BONUS_PERCENT)],
+                p![format!("If the winner of this world is from your tribe, you will earn a free premium account for {} days, so make sure to support your tribe members.", state.config.winner_tribe_num_premium_days)],
                 p![strong!["You are member of the ", tribe_name(
                     tribe_id,
+                    state,
                     model.game_id
                 ), "."]],
+                tribe_governance(model, state, player, user_id, tribe_id),
+                tribe_treasury(model, state, player, user_id, tribe_id),
+                tribe_switch(model, state, user_id, tribe_id),
                 p![strong![format!("Your Fame Points: {} FP", player.tribe_points)]],
                 table![C!["list"],
                 enum_iterator::all::<Territory>()
@@ -3492,7 +4776,7 @@ fn tribe(model: &Model, client_state: &ClientState<shared::State>, state: &share
                                 p![C!["subtitle"],
                                     format!("Provides additional dwarfs with maxed out {}.", stats_simple(&territory.provides_stats()))
                                 ],
-                                if rank == 1 {
+                                if territory.controller(&state.tribes) == Some(tribe_id) {
                                     p!["Your tribe controls this territory."]
                                 } else {
                                     p!["Your tribe does not control this territory."]
@@ -3509,6 +4793,37 @@ fn tribe(model: &Model, client_state: &ClientState<shared::State>, state: &share
                                     )),
                                     "Spend One Fame Point"
                                 ],
+                                if tribe.role(*user_id).is_leader() {
+                                    button![
+                                        if tribe.points > 0 {
+                                            attrs! {}
+                                        } else {
+                                            attrs! {At::Disabled => "true"}
+                                        },
+                                        ev(Ev::Click, move |_| Msg::send_event(
+                                            ClientEvent::SpendSharedTribePoints(territory, 1)
+                                        )),
+                                        "Spend One Shared Point"
+                                    ]
+                                } else {
+                                    Node::Empty
+                                },
+                                if tribe.territory_votes.get(user_id) == Some(&territory) {
+                                    button![
+                                        ev(Ev::Click, move |_| Msg::send_event(
+                                            ClientEvent::VoteTerritory(None)
+                                        )),
+                                        format!("Remove Vote ({})", tribe.territory_votes_for(territory))
+                                    ]
+                                } else {
+                                    button![
+                                        ev(Ev::Click, move |_| Msg::send_event(
+                                            ClientEvent::VoteTerritory(Some(territory))
+                                        )),
+                                        format!("Vote ({})", tribe.territory_votes_for(territory))
+                                    ]
+                                },
+                                battle(model, state, player, user_id, tribe_id, territory),
                             ],
                         ]
                     })
@@ -3517,7 +4832,7 @@ fn tribe(model: &Model, client_state: &ClientState<shared::State>, state: &share
         } else {
             div![C!["content"],
                 h2!["Your Tribe"],
-                p![format!("You are not a member of a tribe. You will be assigned a tribe at level {}.", JOIN_TRIBE_LEVEL)],
+                p![format!("You are not a member of a tribe. You will be assigned a tribe at level {}.", state.config.join_tribe_level)],
             ]
         }
     } else {
@@ -3525,6 +4840,471 @@ fn tribe(model: &Model, client_state: &ClientState<shared::State>, state: &share
     }
 }
 
+fn tribe_governance(
+    model: &Model,
+    state: &shared::State,
+    player: &Player,
+    user_id: &shared::UserId,
+    tribe_id: TribeId,
+) -> Node<Msg> {
+    let tribe = state.tribes.get(&tribe_id).unwrap();
+    let role = tribe.role(*user_id);
+    let mut members = state.tribe_members(tribe_id);
+    members.sort_by_key(|member_id| {
+        let level = state.players.get(member_id).map(|p| p.base.curr_level).unwrap_or_default();
+        (tribe.role(*member_id) != TribeRole::Chief, tribe.role(*member_id) != TribeRole::Officer, std::cmp::Reverse(level))
+    });
+    let user_id = *user_id;
+
+    div![C!["tribe-governance"],
+        if !tribe.motd.is_empty() {
+            div![C!["tribe-motd"], strong!["Message of the Day: "], tribe.motd.clone().censor()]
+        } else {
+            Node::Empty
+        },
+        if role == TribeRole::Chief {
+            if let Some(tribe_name) = model.tribe_name.clone() {
+                div![
+                    label!["Tribe Name"],
+                    input![
+                        attrs! {At::Value => tribe_name, At::MaxLength => MAX_TRIBE_NAME_LENGTH},
+                        input_ev(Ev::Input, move |name| Msg::UpdateTribeName(Some(name))),
+                    ],
+                    button![
+                        ev(Ev::Click, move |_| Msg::SetTribeName(tribe_name)),
+                        "Save Name"
+                    ],
+                    button![
+                        ev(Ev::Click, move |_| Msg::SetTribeName(String::new())),
+                        "Reset Name"
+                    ]
+                ]
+            } else {
+                let tribe_name = tribe.name.clone().unwrap_or_default();
+                button![
+                    ev(Ev::Click, move |_| Msg::UpdateTribeName(Some(tribe_name))),
+                    "Edit Tribe Name"
+                ]
+            }
+        } else {
+            Node::Empty
+        },
+        if role.is_leader() {
+            if let Some(motd) = model.tribe_motd.clone() {
+                div![
+                    label!["Message of the Day"],
+                    textarea![
+                        attrs! {At::Value => motd, At::MaxLength => MAX_TRIBE_MOTD_LENGTH},
+                        input_ev(Ev::Input, move |motd| Msg::UpdateTribeMotd(Some(motd))),
+                    ],
+                    button![
+                        ev(Ev::Click, move |_| Msg::SetTribeMotd(motd)),
+                        "Save Message"
+                    ]
+                ]
+            } else {
+                let motd = tribe.motd.clone();
+                button![
+                    ev(Ev::Click, move |_| Msg::UpdateTribeMotd(Some(motd))),
+                    "Edit Message of the Day"
+                ]
+            }
+        } else {
+            Node::Empty
+        },
+        p![
+            strong![format!("Shared Fame Points: {} FP", tribe.points)],
+            " ",
+            button![
+                if player.tribe_points > 0 {
+                    attrs! {}
+                } else {
+                    attrs! {At::Disabled => "true"}
+                },
+                ev(Ev::Click, move |_| Msg::send_event(ClientEvent::DonateTribePoints(1))),
+                "Donate One Fame Point"
+            ],
+        ],
+        p!["The chief and the officers can spend the shared fame points. Once a day, the shared fame points that are left are spent on the territory with the most votes."],
+        h3!["Members"],
+        p![format!("Vote for a chief of your tribe. A tribe without a chief gets the member with the most votes, otherwise a majority of the members is needed to replace the chief. The chief appoints up to {} officers. Members that haven't been online for {} can be kicked by the leaders.", MAX_TRIBE_OFFICERS, fmt_time(KICK_INACTIVE_TIME * state.config.speed, false, state.config.speed))],
+        table![C!["tribe-members"],
+            tr![
+                th!["Member"],
+                th!["Level"],
+                th!["Role"],
+                th!["Votes"],
+                th![]
+            ],
+            members.iter().map(|member_id| {
+                let member_id = *member_id;
+                let member = state.players.get(&member_id).unwrap();
+                let member_role = tribe.role(member_id);
+                let inactive = (state.time - member.last_online) / state.config.speed >= KICK_INACTIVE_TIME;
+
+                tr![
+                    td![name(model, &member_id, true)],
+                    td![member.base.curr_level],
+                    td![member_role.to_string()],
+                    td![tribe.chief_votes_for(member_id)],
+                    td![
+                        if tribe.chief_votes.get(&user_id) == Some(&member_id) {
+                            button![
+                                ev(Ev::Click, move |_| Msg::send_event(ClientEvent::VoteChief(None))),
+                                "Remove Vote"
+                            ]
+                        } else {
+                            button![
+                                ev(Ev::Click, move |_| Msg::send_event(ClientEvent::VoteChief(Some(member_id)))),
+                                "Vote as Chief"
+                            ]
+                        },
+                        if role == TribeRole::Chief && member_id != user_id {
+                            vec![
+                                match member_role {
+                                    TribeRole::Officer => button![
+                                        ev(Ev::Click, move |_| Msg::send_event(ClientEvent::SetTribeRole(member_id, TribeRole::Member))),
+                                        "Dismiss Officer"
+                                    ],
+                                    _ => button![
+                                        if tribe.officers.len() < MAX_TRIBE_OFFICERS {
+                                            attrs! {}
+                                        } else {
+                                            attrs! {At::Disabled => "true"}
+                                        },
+                                        ev(Ev::Click, move |_| Msg::send_event(ClientEvent::SetTribeRole(member_id, TribeRole::Officer))),
+                                        "Appoint Officer"
+                                    ],
+                                },
+                                button![
+                                    ev(Ev::Click, move |_| Msg::Confirm(ClientEvent::SetTribeRole(member_id, TribeRole::Chief))),
+                                    "Hand Over Chief"
+                                ],
+                            ]
+                        } else {
+                            Vec::new()
+                        },
+                        if inactive
+                            && member_id != user_id
+                            && (member_role == TribeRole::Member
+                                || (role == TribeRole::Chief && member_role == TribeRole::Officer))
+                            && role.is_leader()
+                        {
+                            button![
+                                ev(Ev::Click, move |_| Msg::Confirm(ClientEvent::KickTribeMember(member_id))),
+                                "Kick"
+                            ]
+                        } else {
+                            Node::Empty
+                        },
+                    ]
+                ]
+            })
+        ]
+    ]
+}
+
+fn tribe_treasury(
+    model: &Model,
+    state: &shared::State,
+    player: &Player,
+    user_id: &shared::UserId,
+    tribe_id: TribeId,
+) -> Node<Msg> {
+    let tribe = state.tribes.get(&tribe_id).unwrap();
+    let role = tribe.role(*user_id);
+    let money = model.tribe_money;
+    let recipient_id = model
+        .tribe_recipient
+        .filter(|recipient_id| state.players.get(recipient_id).and_then(|p| p.tribe) == Some(tribe_id))
+        .unwrap_or(*user_id);
+
+    div![C!["tribe-treasury"],
+        h3!["Treasury"],
+        p![format!("Members can deposit coins and items for the tribe. Only the chief can withdraw them, either for themselves or for another member. The chief can also set a tax of up to {}% on the coins that the members earn in quests.", MAX_TRIBE_TAX_PERCENT)],
+        p![strong![format!("Treasury: {} coins", big_number(tribe.treasury))]],
+        p![strong![format!("Tax: {}%", tribe.tax_percent)]],
+        if role == TribeRole::Chief {
+            p![
+                label!["Set Tax "],
+                select![
+                    (0..=MAX_TRIBE_TAX_PERCENT).step_by(5).map(|percent| {
+                        option![
+                            attrs! {
+                                At::Value => percent,
+                                At::Selected => (percent == tribe.tax_percent).as_at_value(),
+                            },
+                            format!("{}%", percent)
+                        ]
+                    }),
+                    input_ev(Ev::Change, |value| {
+                        value.parse::<u64>().ok().map(|percent| {
+                            Msg::send_event(ClientEvent::SetTribeTax(percent))
+                        })
+                    })
+                ]
+            ]
+        } else {
+            Node::Empty
+        },
+        div![
+            input![
+                attrs! {At::Type => "number", At::Min => 0, At::Value => money},
+                input_ev(Ev::Input, |value| Msg::SetTribeMoney(value.parse().unwrap_or_default())),
+            ],
+            button![
+                if money > 0 && player.money >= money {
+                    attrs! {}
+                } else {
+                    attrs! {At::Disabled => "true"}
+                },
+                ev(Ev::Click, move |_| Msg::send_event(ClientEvent::DepositTribeMoney(money))),
+                "Deposit Coins"
+            ],
+            if role == TribeRole::Chief {
+                button![
+                    if money > 0 && tribe.treasury >= money {
+                        attrs! {}
+                    } else {
+                        attrs! {At::Disabled => "true"}
+                    },
+                    ev(Ev::Click, move |_| Msg::send_event(ClientEvent::WithdrawTribeMoney(recipient_id, money))),
+                    "Withdraw Coins"
+                ]
+            } else {
+                Node::Empty
+            },
+        ],
+        if role == TribeRole::Chief {
+            p![
+                label!["Withdraw for "],
+                select![
+                    state.tribe_members(tribe_id).into_iter().map(|member_id| {
+                        option![
+                            attrs! {
+                                At::Value => member_id.0,
+                                At::Selected => (member_id == recipient_id).as_at_value(),
+                            },
+                            model
+                                .state
+                                .get_user_data(&member_id)
+                                .map(|data| data.username.clone().censor())
+                                .unwrap_or_default()
+                        ]
+                    }),
+                    input_ev(Ev::Change, |value| {
+                        Msg::SetTribeRecipient(value.parse::<i64>().ok().map(UserId))
+                    })
+                ]
+            ]
+        } else {
+            Node::Empty
+        },
+        h3!["Storehouse"],
+        if tribe.storehouse.is_empty() {
+            p!["The storehouse is empty. You can deposit items from your inventory."]
+        } else {
+            table![C!["list"],
+                tribe.storehouse.iter().map(|(item, qty)| {
+                    let item = *item;
+                    let qty = *qty;
+                    tr![C!["list-item-row"],
+                        td![img![C!["list-item-image"], attrs! { At::Src => Image::from(item).as_at_value() } ]],
+                        td![C!["list-item-content"],
+                            h3![C!["title"], format!("{}x {}", big_number(qty), item)],
+                            if role == TribeRole::Chief {
+                                div![
+                                    button![
+                                        ev(Ev::Click, move |_| Msg::send_event(ClientEvent::WithdrawTribeItems(recipient_id, item, 1))),
+                                        "Withdraw One"
+                                    ],
+                                    button![
+                                        ev(Ev::Click, move |_| Msg::send_event(ClientEvent::WithdrawTribeItems(recipient_id, item, qty))),
+                                        "Withdraw All"
+                                    ],
+                                ]
+                            } else {
+                                Node::Empty
+                            }
+                        ]
+                    ]
+                })
+            ]
+        },
+        h3!["Ledger"],
+        if tribe.ledger.is_empty() {
+            p!["There are no transactions yet."]
+        } else {
+            table![C!["tribe-ledger"],
+                tribe.ledger.iter().rev().map(|(time, member_id, transaction)| {
+                    tr![
+                        td![format!("{} ago", fmt_time(state.time - time, false, state.config.speed))],
+                        td![name(model, member_id, false)],
+                        td![match transaction {
+                            TribeTransaction::DepositMoney(money) => span![format!("deposited {} coins.", big_number(*money))],
+                            TribeTransaction::WithdrawMoney(recipient_id, money) => span![
+                                format!("withdrew {} coins for ", big_number(*money)),
+                                name(model, recipient_id, false),
+                                "."
+                            ],
+                            TribeTransaction::DepositItems(items) => span!["deposited ", bundle(items, player, false), "."],
+                            TribeTransaction::WithdrawItems(recipient_id, items) => span![
+                                "withdrew ",
+                                bundle(items, player, false),
+                                " for ",
+                                name(model, recipient_id, false),
+                                "."
+                            ],
+                            TribeTransaction::Tax(money) => span![format!("paid {} coins in taxes.", big_number(*money))],
+                            TribeTransaction::TaxChanged(percent) => span![format!("set the tax to {}%.", percent)],
+                        }],
+                    ]
+                })
+            ]
+        },
+    ]
+}
+
+fn tribe_switch(
+    model: &Model,
+    state: &shared::State,
+    user_id: &shared::UserId,
+    tribe_id: TribeId,
+) -> Node<Msg> {
+    let time_left = state.tribe_switch_time_left(*user_id).unwrap_or_default();
+
+    div![C!["tribe-switch"],
+        h3!["Switch Tribe"],
+        p![format!("You can switch to another tribe {} after joining your current one. To keep the tribes balanced, you can only join a tribe whose active members don't have more levels in total than the members of your current tribe.", fmt_time(state.config.tribe_switch_cooldown * state.config.speed, false, state.config.speed))],
+        if time_left > 0 {
+            p![format!("You can switch your tribe in {}.", fmt_time(time_left, false, state.config.speed))]
+        } else {
+            Node::Empty
+        },
+        table![C!["list"],
+            state.tribes.keys().filter(|other_id| **other_id != tribe_id).map(|other_id| {
+                let other_id = *other_id;
+                tr![
+                    td![tribe_name(other_id, state, model.game_id)],
+                    td![format!("{} members", state.tribe_members(other_id).len())],
+                    td![format!("{} levels", state.tribe_strength(other_id))],
+                    td![
+                        button![
+                            if state.can_switch_tribe(*user_id, other_id) {
+                                attrs! {}
+                            } else {
+                                attrs! {At::Disabled => "true"}
+                            },
+                            ev(Ev::Click, move |_| Msg::Confirm(ClientEvent::SwitchTribe(other_id))),
+                            "Join"
+                        ]
+                    ],
+                ]
+            })
+        ]
+    ]
+}
+
+fn battle(
+    model: &Model,
+    state: &shared::State,
+    player: &Player,
+    user_id: &shared::UserId,
+    tribe_id: TribeId,
+    territory: Territory,
+) -> Node<Msg> {
+    let Some((battle_id, battle)) = state
+        .battles
+        .iter()
+        .find(|(_, battle)| battle.territory == territory)
+        .map(|(battle_id, battle)| (*battle_id, battle))
+    else {
+        return if territory.controller(&state.tribes) != Some(tribe_id) {
+            button![
+                if player.tribe_points >= DECLARE_ATTACK_COST {
+                    attrs! {}
+                } else {
+                    attrs! {At::Disabled => "true"}
+                },
+                ev(Ev::Click, move |_| Msg::send_event(
+                    ClientEvent::DeclareAttack(territory)
+                )),
+                format!("Declare Attack ({} FP)", DECLARE_ATTACK_COST)
+            ]
+        } else {
+            Node::Empty
+        };
+    };
+
+    let fighters = battle.fighters.get(user_id).cloned().unwrap_or_default();
+
+    div![C!["battle"],
+        h4!["Battle"],
+        p![
+            tribe_name(battle.attacker, state, model.game_id),
+            " attacks ",
+            if let Some(defender) = battle.defender {
+                tribe_name(defender, state, model.game_id)
+            } else {
+                span!["an unclaimed territory"]
+            },
+            format!(", {} left.", fmt_time(battle.time_left, false, state.config.speed)),
+        ],
+        p![format!(
+            "Attack: {} points, Defence: {} points (including a defence bonus of {}%).",
+            big_number(battle.attack_score),
+            big_number(battle.defence_score),
+            DEFENCE_BONUS_PERCENT
+        )],
+        if battle.can_join(Some(tribe_id)) {
+            div![
+                p![format!("Your fighters ({}/{}):", fighters.len(), MAX_BATTLE_DWARFS)],
+                ul![fighters.iter().filter_map(|dwarf_id| {
+                    let dwarf_id = *dwarf_id;
+                    let dwarf = player.dwarfs.get(&dwarf_id)?;
+                    Some(li![
+                        format!("{} ", dwarf.actual_name()),
+                        button![
+                            ev(Ev::Click, move |_| Msg::send_event(
+                                ClientEvent::CommitToBattle(battle_id, dwarf_id, false)
+                            )),
+                            "Withdraw"
+                        ]
+                    ])
+                })],
+                if fighters.len() < MAX_BATTLE_DWARFS {
+                    select![
+                        option![attrs! {At::Value => "", At::Selected => true.as_at_value()}, "Send a dwarf into battle ..."],
+                        player.dwarfs.iter()
+                            .filter(|(_, dwarf)| {
+                                dwarf.is_adult()
+                                    && dwarf.participates_in_quest.is_none()
+                                    && dwarf.participates_in_battle.is_none()
+                            })
+                            .map(|(dwarf_id, dwarf)| option![
+                                attrs! {At::Value => dwarf_id},
+                                format!(
+                                    "{} (Fighting {}%)",
+                                    dwarf.actual_name(),
+                                    dwarf.effectiveness_percent(Occupation::Fighting)
+                                )
+                            ]),
+                        input_ev(Ev::Change, move |value| {
+                            value.parse::<DwarfId>().ok().map(|dwarf_id| {
+                                Msg::send_event(ClientEvent::CommitToBattle(battle_id, dwarf_id, true))
+                            })
+                        })
+                    ]
+                } else {
+                    Node::Empty
+                }
+            ]
+        } else {
+            p!["Your tribe is not involved in this battle."]
+        }
+    ]
+}
+
 fn chat(
     model: &Model,
     state: &shared::State,
@@ -3534,6 +5314,14 @@ fn chat(
     let message = model.message.clone();
 
     if let Some(player) = state.players.get(user_id) {
+        let in_tribe = player.tribe.is_some();
+        let tribe_tab = model.chat_tab == ChatTab::Tribe && in_tribe;
+        let chat = if tribe_tab {
+            &model.tribe_chat
+        } else {
+            &state.chat
+        };
+
         div![
             id!["chat"],
             if model.chat_visible {
@@ -3544,16 +5332,35 @@ fn chat(
             if model.chat_visible {
                 div![
                     C!["togglable"],
+                    if in_tribe {
+                        div![
+                            C!["chat-tabs"],
+                            button![
+                                C![if !tribe_tab { "active" } else { "" }],
+                                ev(Ev::Click, move |_| Msg::ChangeChatTab(ChatTab::World)),
+                                if player.chat_unread { Icon::ChatUnread.draw() } else { Icon::Chat.draw() },
+                                " World",
+                            ],
+                            button![
+                                C![if tribe_tab { "active" } else { "" }],
+                                ev(Ev::Click, move |_| Msg::ChangeChatTab(ChatTab::Tribe)),
+                                if player.tribe_chat_unread { Icon::ChatUnread.draw() } else { Icon::Chat.draw() },
+                                " Tribe",
+                            ],
+                        ]
+                    } else {
+                        Node::Empty
+                    },
                     div![
                         C!["messages"],
-                        state.chat.messages.iter().map(|(user_id, message, time)| {
+                        chat.messages.iter().map(|(user_id, message, time)| {
                             let username = &client_state
                                 .get_user_data(user_id)
                                 .map(|data| data.username.clone().censor())
                                 .unwrap_or_default();
                             p![
                                 C!["message"],
-                                span![C!["time"], format!("{} ago, ", fmt_time(state.time - time, false))],
+                                span![C!["time"], format!("{} ago, ", fmt_time(state.time - time, false, state.config.speed))],
                                 span![C!["username"], format!("{username}:")],
                                 span![C!["message"], format!("{}", message.censor())]
                             ]
@@ -3562,7 +5369,7 @@ fn chat(
                     div![
                         input![
                             id!["chat-input"],
-                            attrs! {At::Type => "text", At::Value => model.message, At::Placeholder => "Type your message here ..."},
+                            attrs! {At::Type => "text", At::Value => model.message, At::Placeholder => if tribe_tab { "Message your tribe ..." } else { "Type your message here ..." }},
                             input_ev(Ev::Input, Msg::ChangeMessage)
                         ],
                         button![
@@ -3587,7 +5394,7 @@ fn chat(
                     ev(Ev::Click, move |_| Msg::ToggleChat),
                     span![
                         attrs! {At::AriaHidden => "true"},
-                        if player.chat_unread {
+                        if player.chat_unread || player.tribe_chat_unread {
                             Icon::ChatUnread.draw()
                         } else {
                             Icon::Chat.draw()
@@ -3640,11 +5447,34 @@ fn history(
                                 LogMsg::BidWon(..) => Icon::Trade,
                                 LogMsg::ItemSold(..) => Icon::Trade,
                                 LogMsg::ItemNotSold(..) => Icon::Trade,
+                                LogMsg::ItemNotBought(..) => Icon::Trade,
+                                LogMsg::Crafted(..) => Icon::Inventory,
+                                LogMsg::DwarfInjured(_) => Icon::Healing,
+                                LogMsg::DwarfInfected(_) => Icon::Healing,
+                                LogMsg::DwarfCured(_) => Icon::Healing,
+                                LogMsg::BattleWon(_) => Icon::Tribe,
+                                LogMsg::BattleLost(_) => Icon::Tribe,
+                                LogMsg::TribeTax(_) => Icon::Coins,
+                                LogMsg::CraftingFailed(_) => Icon::Inventory,
+                                LogMsg::AutoSellRemoved(_) => Icon::Trade,
                             }.draw()],
                             span![" "],
-                            span![C!["time"], format!("{} ago: ", fmt_time(state.time - time, false))],
+                            span![C!["time"], format!("{} ago: ", fmt_time(state.time - time, false, state.config.speed))],
                             match msg {
-                                LogMsg::Overbid(items, money, _) => {
+                                LogMsg::Overbid(items, money, TradeType::Sell) => {
+                                    span![format!(
+                                        "Another player offered {} for {} coins, your items have been returned.",
+                                        items
+                                            .clone()
+                                            .sorted_by_rarity()
+                                            .into_iter()
+                                            .map(|(item, n)| format!("{n}x {item}"))
+                                            .collect::<Vec<_>>()
+                                            .join(", "),
+                                        money
+                                    )]
+                                }
+                                LogMsg::Overbid(items, money, TradeType::Buy) => {
                                     span![format!(
                                         "You have been overbid on {} for {} coins.",
                                         items
@@ -3696,6 +5526,31 @@ fn history(
                                         money
                                     )]
                                 }
+                                LogMsg::ItemNotBought(items, money) => {
+                                    span![format!(
+                                        "Nobody sold you {} and your {} coins have been returned.",
+                                        items
+                                            .clone()
+                                            .sorted_by_rarity()
+                                            .into_iter()
+                                            .map(|(item, n)| format!("{n}x {item}"))
+                                            .collect::<Vec<_>>()
+                                            .join(", "),
+                                        money
+                                    )]
+                                }
+                                LogMsg::Crafted(items) => {
+                                    span![format!(
+                                        "You have finished crafting {}.",
+                                        items
+                                            .clone()
+                                            .sorted_by_rarity()
+                                            .into_iter()
+                                            .map(|(item, n)| format!("{n}x {item}"))
+                                            .collect::<Vec<_>>()
+                                            .join(", ")
+                                    )]
+                                }
                                 LogMsg::DwarfUpgrade(name, stat) => {
                                     span![format!(
                                         "Your dwarf {} has improved his {} stat while working.",
@@ -3734,6 +5589,46 @@ fn history(
                                 LogMsg::DwarfDied(name) => {
                                     span![format!("Your dwarf {} has died.", name)]
                                 }
+                                LogMsg::DwarfInjured(name) => {
+                                    span![format!("Your dwarf {} got injured while working.", name)]
+                                }
+                                LogMsg::DwarfInfected(name) => {
+                                    span![format!("Your dwarf {} got infected by the plague.", name)]
+                                }
+                                LogMsg::DwarfCured(name) => {
+                                    span![format!("Your dwarf {} has been treated by a healer.", name)]
+                                }
+                                LogMsg::BattleWon(territory) => {
+                                    span![format!("Your tribe won the battle for the {}.", territory)]
+                                }
+                                LogMsg::BattleLost(territory) => {
+                                    span![format!("Your tribe lost the battle for the {}.", territory)]
+                                }
+                                LogMsg::TribeTax(money) => {
+                                    span![format!("You paid {} coins in taxes to your tribe.", money)]
+                                }
+                                LogMsg::AutoSellRemoved(items) => {
+                                    span![format!(
+                                        "Items are no longer sold automatically, put {} up for auction in the market instead.",
+                                        items
+                                            .iter()
+                                            .map(|item| item.to_string())
+                                            .collect::<Vec<_>>()
+                                            .join(", ")
+                                    )]
+                                }
+                                LogMsg::CraftingFailed(items) => {
+                                    span![format!(
+                                        "You could not craft {} because the ingredients were gone, the reserved ingredients were returned.",
+                                        items
+                                            .clone()
+                                            .sorted_by_rarity()
+                                            .into_iter()
+                                            .map(|(item, n)| format!("{n}x {item}"))
+                                            .collect::<Vec<_>>()
+                                            .join(", ")
+                                    )]
+                                }
                                 LogMsg::QuestCompletedItems(quest, items) => {
                                     if let Some(items) = items {
                                         span![format!(
@@ -4028,6 +5923,14 @@ fn nav(model: &Model) -> Node<Msg> {
     ]]
     */
 
+    let unread_messages = model
+        .state
+        .get_state()
+        .zip(model.state.get_user_id())
+        .and_then(|(state, user_id)| state.players.get(user_id))
+        .map(|player| !player.unread_conversations.is_empty())
+        .unwrap_or(false);
+
     nav![
         C!["ingame"],
         /*div![
@@ -4137,6 +6040,19 @@ fn nav(model: &Model) -> Node<Msg> {
                 span![C!["nav-image"], Icon::Tribe.draw()],
                 span![C!["nav-description"], " Tribe"]
             ],
+            a![
+                C![
+                    "button",
+                    if let Page::Messages(_) = model.page {
+                        "active disabled"
+                    } else {
+                        ""
+                    }
+                ],
+                attrs! {At::Href => format!("{}/messages", model.base_path()), At::AriaLabel => "Messages"},
+                span![C!["nav-image"], if unread_messages { Icon::MailUnread.draw() } else { Icon::Mail.draw() }],
+                span![C!["nav-description"], " Messages"]
+            ],
             a![
                 C![
                     "button",
@@ -1,10 +1,16 @@
-use crate::{game::GameState, ServerError};
+use crate::{
+    game::{self, GameState, GameStore},
+    save, snapshot, stats, ServerError,
+};
 use askama::Template;
 use askama_axum::Response;
 use axum::{
+    extract::Path,
     response::{IntoResponse, Redirect},
     Extension, Form,
 };
+use engine_shared::GameId;
+use shared::{WorldConfig, ONE_DAY, ONE_HOUR};
 use bcrypt::hash;
 use serde::Deserialize;
 use sqlx::SqlitePool;
@@ -23,6 +29,52 @@ pub struct Settings {
     free_premium: i64,
 }
 
+// Durations are entered in days and hours, the game counts in seconds.
+#[derive(Debug, Deserialize, Default)]
+pub struct WorldConfigForm {
+    num_tribes: u64,
+    speed: u64,
+    max_level: u64,
+    join_tribe_level: u64,
+    improvement_days: u64,
+    winner_num_premium_days: i64,
+    winner_tribe_num_premium_days: i64,
+    tribe_switch_cooldown_hours: u64,
+    bot_fill_players: usize,
+}
+
+impl From<WorldConfig> for WorldConfigForm {
+    fn from(config: WorldConfig) -> Self {
+        WorldConfigForm {
+            num_tribes: config.num_tribes,
+            speed: config.speed,
+            max_level: config.max_level,
+            join_tribe_level: config.join_tribe_level,
+            improvement_days: config.improvement_duration as u64 / ONE_DAY,
+            winner_num_premium_days: config.winner_num_premium_days,
+            winner_tribe_num_premium_days: config.winner_tribe_num_premium_days,
+            tribe_switch_cooldown_hours: config.tribe_switch_cooldown / ONE_HOUR,
+            bot_fill_players: config.bot_fill_players,
+        }
+    }
+}
+
+impl From<WorldConfigForm> for WorldConfig {
+    fn from(form: WorldConfigForm) -> Self {
+        WorldConfig {
+            num_tribes: form.num_tribes.max(1),
+            tribe_switch_cooldown: form.tribe_switch_cooldown_hours * ONE_HOUR,
+            speed: form.speed.max(1),
+            max_level: form.max_level.max(1),
+            join_tribe_level: form.join_tribe_level,
+            improvement_duration: (form.improvement_days * ONE_DAY) as u32,
+            winner_num_premium_days: form.winner_num_premium_days,
+            winner_tribe_num_premium_days: form.winner_tribe_num_premium_days,
+            bot_fill_players: form.bot_fill_players,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Default)]
 struct User {
     user_id: i64,
@@ -40,6 +92,7 @@ struct Game {
 #[template(path = "admin.html")]
 pub struct AdminTemplate {
     settings: Settings,
+    world_config: WorldConfigForm,
     users: Vec<User>,
     games: Vec<Game>,
 }
@@ -49,10 +102,28 @@ pub struct AddPremium {
     add_premium: i64,
 }
 
-pub async fn get_admin(
-    session: Session,
-    Extension(pool): Extension<SqlitePool>,
-) -> Result<Response, ServerError> {
+#[derive(Debug, Deserialize)]
+pub struct RestoreSnapshot {
+    snapshot_id: i64,
+}
+
+#[derive(Debug)]
+struct Snapshot {
+    id: i64,
+    kind: String,
+    ingame_time: String,
+    created: time::PrimitiveDateTime,
+}
+
+#[derive(Template)]
+#[template(path = "admin-snapshots.html")]
+pub struct AdminSnapshotsTemplate {
+    game_id: GameId,
+    snapshots: Vec<Snapshot>,
+}
+
+// Returns the id of the logged in user if they are an admin.
+async fn require_admin(session: &Session, pool: &SqlitePool) -> Result<i64, ServerError> {
     let user_id = session
         .get::<i64>(crate::USER_ID_KEY)
         .await?
@@ -66,7 +137,7 @@ pub async fn get_admin(
             "#,
     )
     .bind(user_id)
-    .fetch_one(&pool)
+    .fetch_one(pool)
     .await?;
 
     let admin = result.0 == 1;
@@ -75,6 +146,15 @@ pub async fn get_admin(
         return Err(ServerError::NoAdminPermissions);
     }
 
+    Ok(user_id)
+}
+
+pub async fn get_admin(
+    session: Session,
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<Response, ServerError> {
+    let user_id = require_admin(&session, &pool).await?;
+
     let (free_premium,): (i64,) = sqlx::query_as(
         r#"
                 SELECT free_premium
@@ -87,6 +167,8 @@ pub async fn get_admin(
 
     let settings = Settings { free_premium };
 
+    let world_config = game::load_world_config(&pool).await?.into();
+
     let users = sqlx::query_as(
         r#"
                 SELECT user_id, username, premium
@@ -120,6 +202,7 @@ pub async fn get_admin(
     Ok(AdminTemplate {
         users,
         settings,
+        world_config,
         games,
     }
     .into_response())
@@ -131,27 +214,7 @@ pub async fn post_manage_user(
     Extension(game_state): Extension<GameState>,
     Form(manage_user): Form<ManageUser>,
 ) -> Result<Response, ServerError> {
-    let user_id = session
-        .get::<i64>(crate::USER_ID_KEY)
-        .await?
-        .ok_or(ServerError::InvalidSession)?;
-
-    let result: (i64,) = sqlx::query_as(
-        r#"
-                SELECT admin
-                FROM users
-                WHERE user_id = $1
-            "#,
-    )
-    .bind(user_id)
-    .fetch_one(&pool)
-    .await?;
-
-    let admin = result.0 == 1;
-
-    if !admin {
-        return Err(ServerError::NoAdminPermissions);
-    }
+    require_admin(&session, &pool).await?;
 
     let mut tx = pool.begin().await?;
 
@@ -216,27 +279,7 @@ pub async fn post_add_premium(
     Extension(game_state): Extension<GameState>,
     Form(add_premium): Form<AddPremium>,
 ) -> Result<Response, ServerError> {
-    let user_id = session
-        .get::<i64>(crate::USER_ID_KEY)
-        .await?
-        .ok_or(ServerError::InvalidSession)?;
-
-    let result: (i64,) = sqlx::query_as(
-        r#"
-                SELECT admin
-                FROM users
-                WHERE user_id = $1
-            "#,
-    )
-    .bind(user_id)
-    .fetch_one(&pool)
-    .await?;
-
-    let admin = result.0 == 1;
-
-    if !admin {
-        return Err(ServerError::NoAdminPermissions);
-    }
+    require_admin(&session, &pool).await?;
 
     if add_premium.add_premium > 0 {
         sqlx::query(
@@ -259,28 +302,34 @@ pub async fn post_create_world(
     session: Session,
     Extension(pool): Extension<SqlitePool>,
     Extension(game_state): Extension<GameState>,
+    Form(world_config): Form<WorldConfigForm>,
 ) -> Result<Response, ServerError> {
-    let user_id = session
-        .get::<i64>(crate::USER_ID_KEY)
-        .await?
-        .ok_or(ServerError::InvalidSession)?;
+    require_admin(&session, &pool).await?;
 
-    let result: (i64,) = sqlx::query_as(
+    // Worlds that are started automatically later use the same config.
+    let config = rmp_serde::to_vec(&WorldConfig::from(world_config))?;
+
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query(
         r#"
-                SELECT admin
-                FROM users
-                WHERE user_id = $1
+                DELETE FROM world_config
             "#,
     )
-    .bind(user_id)
-    .fetch_one(&pool)
+    .execute(&mut *transaction)
     .await?;
 
-    let admin = result.0 == 1;
+    sqlx::query(
+        r#"
+                INSERT INTO world_config (config)
+                VALUES ($1)
+            "#,
+    )
+    .bind(config)
+    .execute(&mut *transaction)
+    .await?;
 
-    if !admin {
-        return Err(ServerError::NoAdminPermissions);
-    }
+    transaction.commit().await?;
 
     game_state.create().await?;
 
@@ -292,27 +341,7 @@ pub async fn post_update_settings(
     Extension(pool): Extension<SqlitePool>,
     Form(settings): Form<Settings>,
 ) -> Result<Response, ServerError> {
-    let user_id = session
-        .get::<i64>(crate::USER_ID_KEY)
-        .await?
-        .ok_or(ServerError::InvalidSession)?;
-
-    let result: (i64,) = sqlx::query_as(
-        r#"
-                SELECT admin
-                FROM users
-                WHERE user_id = $1
-            "#,
-    )
-    .bind(user_id)
-    .fetch_one(&pool)
-    .await?;
-
-    let admin = result.0 == 1;
-
-    if !admin {
-        return Err(ServerError::NoAdminPermissions);
-    }
+    require_admin(&session, &pool).await?;
 
     sqlx::query(
         r#"
@@ -326,3 +355,95 @@ pub async fn post_update_settings(
 
     Ok(Redirect::to("/admin").into_response())
 }
+
+pub async fn get_snapshots(
+    session: Session,
+    Path(game_id): Path<GameId>,
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<Response, ServerError> {
+    require_admin(&session, &pool).await?;
+
+    let snapshots = sqlx::query_as(
+        r#"
+                SELECT id, kind, ingame_time, created
+                FROM game_snapshots
+                WHERE game_id = $1
+                ORDER BY id DESC
+            "#,
+    )
+    .bind(game_id)
+    .fetch_all(&pool)
+    .await?
+    .into_iter()
+    .map(|(id, kind, ingame_time, created): (i64, String, i64, time::PrimitiveDateTime)| {
+        let ingame_time = ingame_time as u64;
+        Snapshot {
+            id,
+            kind,
+            ingame_time: format!(
+                "{}d {}h",
+                ingame_time / ONE_DAY,
+                ingame_time % ONE_DAY / ONE_HOUR
+            ),
+            created,
+        }
+    })
+    .collect();
+
+    Ok(AdminSnapshotsTemplate { game_id, snapshots }.into_response())
+}
+
+pub async fn post_restore_snapshot(
+    session: Session,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(game_state): Extension<GameState>,
+    Extension(game_store): Extension<GameStore>,
+    Form(restore_snapshot): Form<RestoreSnapshot>,
+) -> Result<Response, ServerError> {
+    require_admin(&session, &pool).await?;
+
+    let (game_id, data): (GameId, Vec<u8>) = sqlx::query_as(
+        r#"
+                SELECT game_id, data
+                FROM game_snapshots
+                WHERE id = $1
+            "#,
+    )
+    .bind(restore_snapshot.snapshot_id)
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(ServerError::SnapshotNotFound(restore_snapshot.snapshot_id))?;
+
+    // Make sure the snapshot can be loaded before replacing the world with it.
+    let state = save::decode(&data)?;
+    let restored_time = state.time;
+
+    // Keep the current state around in case the restore has to be undone.
+    let current: Option<(Option<Vec<u8>>,)> = sqlx::query_as(
+        r#"
+                SELECT data
+                FROM games
+                WHERE id = $1
+                AND closed = 0
+            "#,
+    )
+    .bind(game_id)
+    .fetch_optional(&pool)
+    .await?;
+
+    let (current,) = current.ok_or(ServerError::GameNotFound(game_id))?;
+
+    if let Some(current) = current {
+        let current_time = save::decode(&current).map(|state| state.time).unwrap_or_default();
+        snapshot::take_before_restore(&pool, game_id, current_time, &current).await?;
+    }
+
+    game_store.restore(&game_state, game_id, state).await?;
+
+    // After loading, so that the replaced world can't sample anymore.
+    stats::discard_after(&pool, game_id, restored_time).await?;
+
+    tracing::info!("game {} restored from snapshot {}", game_id, restore_snapshot.snapshot_id);
+
+    Ok(Redirect::to(&format!("/admin/snapshots/{}", game_id)).into_response())
+}
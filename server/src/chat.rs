@@ -0,0 +1,240 @@
+use crate::{
+    game::{self, GameState},
+    ServerError,
+};
+use axum::{extract::Path, Extension, Json};
+use engine_shared::{utils::custom_map::CustomMap, GameId};
+use shared::{Chat, TribeId, UserId};
+use sqlx::SqlitePool;
+use tower_sessions::Session;
+
+// Tribe chats and direct messages are kept out of the world state, which is sent to every
+// player, and only sent to the players who may read them. Only the newest messages of every
+// chat and conversation are kept.
+const MAX_MESSAGES: i64 = 100;
+
+pub async fn get_tribe_chat(
+    Path(game_id): Path<GameId>,
+    session: Session,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(game_state): Extension<GameState>,
+) -> Result<Json<Chat>, ServerError> {
+    let user_id = session
+        .get::<i64>(crate::USER_ID_KEY)
+        .await?
+        .ok_or(ServerError::InvalidSession)?;
+
+    let (tribe_id, _) = tribe_of(&game_state, game_id, UserId(user_id)).await?;
+
+    let rows: Vec<(i64, String, i64)> = sqlx::query_as(
+        r#"
+            SELECT user_id, message, ingame_time
+            FROM (
+                SELECT id, user_id, message, ingame_time
+                FROM tribe_messages
+                WHERE game_id = $1
+                AND tribe_id = $2
+                ORDER BY id DESC
+                LIMIT $3
+            )
+            ORDER BY id
+        "#,
+    )
+    .bind(game_id)
+    .bind(tribe_id as i64)
+    .bind(MAX_MESSAGES)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(Chat {
+        messages: rows
+            .into_iter()
+            .map(|(user_id, message, time)| (UserId(user_id), message, time as u64))
+            .collect(),
+    }))
+}
+
+pub async fn post_tribe_message(
+    Path(game_id): Path<GameId>,
+    session: Session,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(game_state): Extension<GameState>,
+    Json(message): Json<String>,
+) -> Result<(), ServerError> {
+    let user_id = session
+        .get::<i64>(crate::USER_ID_KEY)
+        .await?
+        .ok_or(ServerError::InvalidSession)?;
+
+    let (tribe_id, time) = tribe_of(&game_state, game_id, UserId(user_id)).await?;
+
+    sqlx::query(
+        r#"
+            INSERT INTO tribe_messages (game_id, tribe_id, user_id, message, ingame_time)
+            VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(game_id)
+    .bind(tribe_id as i64)
+    .bind(user_id)
+    .bind(message)
+    .bind(time as i64)
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+            DELETE FROM tribe_messages
+            WHERE game_id = $1
+            AND tribe_id = $2
+            AND id NOT IN (
+                SELECT id
+                FROM tribe_messages
+                WHERE game_id = $1
+                AND tribe_id = $2
+                ORDER BY id DESC
+                LIMIT $3
+            )
+        "#,
+    )
+    .bind(game_id)
+    .bind(tribe_id as i64)
+    .bind(MAX_MESSAGES)
+    .execute(&pool)
+    .await?;
+
+    Ok(())
+}
+
+// Returns the tribe of the player and the current ingame time of the world.
+async fn tribe_of(
+    game_state: &GameState,
+    game_id: GameId,
+    user_id: UserId,
+) -> Result<(TribeId, u64), ServerError> {
+    let tribe = game::read_game(game_state, game_id, |state| {
+        state
+            .players
+            .get(&user_id)
+            .map(|player| (player.tribe, state.time))
+    })
+    .await
+    .flatten()
+    .ok_or(ServerError::NotInGame(game_id))?;
+
+    match tribe {
+        (Some(tribe_id), time) => Ok((tribe_id, time)),
+        (None, _) => Err(ServerError::NotInTribe),
+    }
+}
+
+// All conversations of the player in this world, by the other player.
+pub async fn get_conversations(
+    Path(game_id): Path<GameId>,
+    session: Session,
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<Json<Vec<(UserId, Chat)>>, ServerError> {
+    let user_id = session
+        .get::<i64>(crate::USER_ID_KEY)
+        .await?
+        .ok_or(ServerError::InvalidSession)?;
+
+    let rows: Vec<(i64, i64, String, i64)> = sqlx::query_as(
+        r#"
+            SELECT sender_id, recipient_id, message, ingame_time
+            FROM direct_messages
+            WHERE game_id = $1
+            AND (sender_id = $2 OR recipient_id = $2)
+            ORDER BY id
+        "#,
+    )
+    .bind(game_id)
+    .bind(user_id)
+    .fetch_all(&pool)
+    .await?;
+
+    let mut conversations: CustomMap<UserId, Chat> = CustomMap::new();
+    for (sender_id, recipient_id, message, time) in rows {
+        let other_id = if sender_id == user_id {
+            recipient_id
+        } else {
+            sender_id
+        };
+        conversations
+            .entry(UserId(other_id))
+            .or_default()
+            .messages
+            .push_back((UserId(sender_id), message, time as u64));
+    }
+
+    Ok(Json(
+        conversations
+            .iter()
+            .map(|(other_id, conversation)| (*other_id, conversation.clone()))
+            .collect(),
+    ))
+}
+
+pub async fn post_direct_message(
+    Path((game_id, recipient_id)): Path<(GameId, i64)>,
+    session: Session,
+    Extension(pool): Extension<SqlitePool>,
+    Extension(game_state): Extension<GameState>,
+    Json(message): Json<String>,
+) -> Result<(), ServerError> {
+    let user_id = session
+        .get::<i64>(crate::USER_ID_KEY)
+        .await?
+        .ok_or(ServerError::InvalidSession)?;
+
+    // Both have to play in this world, bots don't read their messages.
+    let time = game::read_game(&game_state, game_id, |state| {
+        let recipient = UserId(recipient_id);
+        (state.players.contains_key(&UserId(user_id))
+            && state.players.contains_key(&recipient)
+            && !state.is_bot(&recipient)
+            && recipient_id != user_id)
+            .then_some(state.time)
+    })
+    .await
+    .flatten()
+    .ok_or(ServerError::NotInGame(game_id))?;
+
+    sqlx::query(
+        r#"
+            INSERT INTO direct_messages (game_id, sender_id, recipient_id, message, ingame_time)
+            VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(game_id)
+    .bind(user_id)
+    .bind(recipient_id)
+    .bind(message)
+    .bind(time as i64)
+    .execute(&pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+            DELETE FROM direct_messages
+            WHERE game_id = $1
+            AND ((sender_id = $2 AND recipient_id = $3) OR (sender_id = $3 AND recipient_id = $2))
+            AND id NOT IN (
+                SELECT id
+                FROM direct_messages
+                WHERE game_id = $1
+                AND ((sender_id = $2 AND recipient_id = $3) OR (sender_id = $3 AND recipient_id = $2))
+                ORDER BY id DESC
+                LIMIT $4
+            )
+        "#,
+    )
+    .bind(game_id)
+    .bind(user_id)
+    .bind(recipient_id)
+    .bind(MAX_MESSAGES)
+    .execute(&pool)
+    .await?;
+
+    Ok(())
+}
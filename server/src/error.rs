@@ -3,6 +3,7 @@ use axum::{
     http::StatusCode,
     response::{Redirect, Response},
 };
+use engine_shared::GameId;
 use stripe::StripeError;
 use thiserror::Error;
 
@@ -34,6 +35,22 @@ pub enum ServerError {
     GuestAccountError,
     #[error("encoding error: {0}")]
     EncodingError(#[from] rmp_serde::encode::Error),
+    #[error("decoding error: {0}")]
+    DecodingError(#[from] rmp_serde::decode::Error),
+    #[error("json error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("corrupt save")]
+    CorruptSave,
+    #[error("unsupported save version {0}")]
+    UnsupportedSaveVersion(u32),
+    #[error("game {0} not found")]
+    GameNotFound(GameId),
+    #[error("snapshot {0} not found")]
+    SnapshotNotFound(i64),
+    #[error("not a player of game {0}")]
+    NotInGame(GameId),
+    #[error("not a member of a tribe")]
+    NotInTribe,
 }
 
 impl IntoResponse for ServerError {
@@ -45,6 +62,9 @@ impl IntoResponse for ServerError {
             ServerError::NoAdminPermissions => {
                 (StatusCode::UNAUTHORIZED, format!("{self}")).into_response()
             }
+            ServerError::NotInGame(_) | ServerError::NotInTribe => {
+                (StatusCode::FORBIDDEN, format!("{self}")).into_response()
+            }
             ServerError::ValidationError(_) => {
                 (StatusCode::BAD_REQUEST, format!("{self}")).into_response()
             }
@@ -11,8 +11,12 @@ use axum::{
 use engine_shared::{utils::custom_map::CustomMap, GameId};
 use futures_util::{sink::SinkExt, stream::StreamExt};
 use serde::{Deserialize, Serialize};
-use shared::{ClientEvent, UserData, UserId};
+use shared::{ClientEvent, TribeId, UserData, UserId, WorldConfig, WorldSummary, ONE_DAY};
 use sqlx::SqlitePool;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
 use tower_sessions::Session;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -21,18 +25,23 @@ pub struct PartialEventData {
     pub user_id: Option<UserId>,
 }
 
-use crate::ServerError;
+use crate::{save, snapshot, stats, ServerError};
 
 pub type GameState = engine_server::ServerState<shared::State, GameStore>;
 
 #[derive(Clone)]
 pub struct GameStore {
     db: SqlitePool,
+    // States that replace a world the next time it is loaded.
+    restores: Arc<Mutex<CustomMap<GameId, shared::State>>>,
 }
 
 impl GameStore {
     pub fn new(db: SqlitePool) -> Self {
-        Self { db }
+        Self {
+            db,
+            restores: Arc::new(Mutex::new(CustomMap::new())),
+        }
     }
 
     pub async fn load_all(self) -> Result<GameState, ServerError> {
@@ -50,31 +59,121 @@ impl GameStore {
         let game_state = GameState::new(self);
 
         for (id,) in open_worlds {
-            let game_finished = game_state.load(id).await?;
-            let game_state_clone = game_state.clone();
-            let pool_clone = pool.clone();
-
-            tokio::task::spawn(async move {
-                game_finished.notified().await;
-
-                let result: Result<(i64,), _> = sqlx::query_as(
-                    r#"
-                        SELECT auto_start_world
-                        FROM settings
-                        LIMIT 1
-                    "#,
-                )
-                .fetch_one(&pool_clone)
-                .await;
-
-                if result.map(|result| result.0 != 0).unwrap_or(false) {
-                    game_state_clone.create().await.unwrap();
+            // A world that can't be loaded shouldn't keep the others from starting.
+            let game_finished = match game_state.load(id).await {
+                Ok(game_finished) => game_finished,
+                Err(err) => {
+                    tracing::error!("failed to load game {}: {}", id, err);
+                    continue;
                 }
+            };
+
+            on_finished(game_state.clone(), pool.clone(), async move {
+                game_finished.notified().await
             });
         }
 
         Ok(game_state)
     }
+
+    // Replaces the running world with the given state. The world is loaded again from that
+    // state instead of the database, so a save of the replaced world can't interfere.
+    pub async fn restore(
+        &self,
+        game_state: &GameState,
+        game_id: GameId,
+        state: shared::State,
+    ) -> Result<(), ServerError> {
+        let data = save::encode(&state)?;
+
+        self.restores.lock().unwrap().insert(game_id, state);
+        let game_finished = game_state.load(game_id).await;
+        self.restores.lock().unwrap().swap_remove(&game_id);
+        let game_finished = game_finished?;
+
+        sqlx::query(
+            r#"
+                    UPDATE games
+                    SET data = $2
+                    WHERE id = $1
+                "#,
+        )
+        .bind(game_id)
+        .bind(data)
+        .execute(&self.db)
+        .await?;
+
+        on_finished(game_state.clone(), self.db.clone(), async move {
+            game_finished.notified().await
+        });
+
+        Ok(())
+    }
+}
+
+// Starts a new world when a world has finished, if enabled in the settings.
+fn on_finished(
+    game_state: GameState,
+    pool: SqlitePool,
+    game_finished: impl Future<Output = ()> + Send + 'static,
+) {
+    tokio::task::spawn(async move {
+        game_finished.await;
+
+        let result: Result<(i64,), _> = sqlx::query_as(
+            r#"
+                SELECT auto_start_world
+                FROM settings
+                LIMIT 1
+            "#,
+        )
+        .fetch_one(&pool)
+        .await;
+
+        if result.map(|result| result.0 != 0).unwrap_or(false) {
+            game_state.create().await.unwrap();
+        }
+    });
+}
+
+// Reads the running world with the given id.
+pub async fn read_game<T>(
+    game_state: &GameState,
+    game_id: GameId,
+    f: impl FnOnce(&shared::State) -> T,
+) -> Option<T> {
+    let mut f = Some(f);
+    let mut result = None;
+
+    game_state
+        .read_games(|game| {
+            if game.game_id == game_id {
+                if let Some(f) = f.take() {
+                    result = Some(f(game));
+                }
+            }
+        })
+        .await;
+
+    result
+}
+
+// The config for new worlds, as last chosen by an admin.
+pub async fn load_world_config(db: &SqlitePool) -> Result<WorldConfig, ServerError> {
+    let result: Option<(Vec<u8>,)> = sqlx::query_as(
+        r#"
+            SELECT config
+            FROM world_config
+            LIMIT 1
+        "#,
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(match result {
+        Some((config,)) => rmp_serde::from_slice(&config)?,
+        None => WorldConfig::default(),
+    })
 }
 
 #[async_trait::async_trait]
@@ -82,13 +181,17 @@ impl engine_server::BackendStore<shared::State> for GameStore {
     type Error = ServerError;
 
     async fn create_game(&self) -> Result<GameId, Self::Error> {
+        let config = load_world_config(&self.db).await?;
+        let data = save::encode(&shared::State::new(config))?;
+
         let (id,): (i64,) = sqlx::query_as(
             r#"
                 INSERT INTO games (data, winner)
-                VALUES (NULL, NULL)
+                VALUES ($1, NULL)
                 RETURNING id
             "#,
         )
+        .bind(data)
         .fetch_one(&self.db)
         .await?;
 
@@ -96,6 +199,12 @@ impl engine_server::BackendStore<shared::State> for GameStore {
     }
 
     async fn load_game(&self, game_id: GameId) -> Result<shared::State, Self::Error> {
+        let restored = self.restores.lock().unwrap().swap_remove(&game_id);
+        if let Some(mut state) = restored {
+            state.game_id = game_id;
+            return Ok(state);
+        }
+
         let result: Option<(Option<Vec<u8>>,)> = sqlx::query_as(
             r#"
                     SELECT data
@@ -107,12 +216,14 @@ impl engine_server::BackendStore<shared::State> for GameStore {
         .fetch_optional(&self.db)
         .await?;
 
-        let state: shared::State = result
-            .map(|(data,)| {
-                data.map(|data| rmp_serde::from_slice(&data[..]).unwrap())
-                    .unwrap_or_default()
-            })
-            .unwrap();
+        let (data,) = result.ok_or(ServerError::GameNotFound(game_id))?;
+
+        let mut state = if let Some(data) = data {
+            save::decode(&data[..])?
+        } else {
+            shared::State::default()
+        };
+        state.game_id = game_id;
 
         Ok(state)
     }
@@ -154,10 +265,11 @@ impl engine_server::BackendStore<shared::State> for GameStore {
 
     async fn save_game(&self, game_id: GameId, state: &shared::State) -> Result<(), Self::Error> {
         if let Some(winner) = state.winner() {
+            // Finished worlds are archived with their final state and a summary for Valhalla.
             sqlx::query(
                 r#"
                         UPDATE games
-                        SET data = NULL,
+                        SET data = $3,
                         winner = $2,
                         closed = 1
                         WHERE id = $1
@@ -165,6 +277,18 @@ impl engine_server::BackendStore<shared::State> for GameStore {
             )
             .bind(game_id)
             .bind(winner.0)
+            .bind(save::encode(state)?)
+            .execute(&self.db)
+            .await?;
+
+            sqlx::query(
+                r#"
+                        INSERT OR REPLACE INTO game_summaries (game_id, summary)
+                        VALUES ($1, $2)
+                    "#,
+            )
+            .bind(game_id)
+            .bind(rmp_serde::to_vec(&state.summary())?)
             .execute(&self.db)
             .await?;
 
@@ -185,6 +309,8 @@ impl engine_server::BackendStore<shared::State> for GameStore {
             }
             
         } else {
+            let data = save::encode(state)?;
+
             sqlx::query(
                 r#"
                         UPDATE games
@@ -193,11 +319,14 @@ impl engine_server::BackendStore<shared::State> for GameStore {
                     "#,
             )
             .bind(game_id)
-            .bind(rmp_serde::to_vec(&state)?)
+            .bind(&data)
             .execute(&self.db)
             .await?;
 
             tracing::info!("game {} saved, ingame time {}", game_id, state.time);
+
+            snapshot::take(&self.db, game_id, state.time, &data).await?;
+            stats::sample(&self.db, game_id, state).await?;
         }
 
         Ok(())
@@ -314,6 +443,47 @@ pub async fn get_game_select(
 #[template(path = "valhalla.html")]
 pub struct ValhallaTemplate {
     users: Vec<UserData>,
+    worlds: Vec<FinishedWorld>,
+}
+
+struct FinishedWorld {
+    game_id: GameId,
+    winner: String,
+    finished: time::PrimitiveDateTime,
+}
+
+struct WorldPlayer {
+    username: String,
+    level: u64,
+    dwarfs: usize,
+    money: u64,
+    tribe: Option<(String, String)>,
+    quests_won: u64,
+    winner: bool,
+    king: bool,
+}
+
+struct WorldTribe {
+    name: String,
+    color: String,
+    members: usize,
+    score: u64,
+    territories: Vec<(String, u64)>,
+}
+
+#[derive(Template)]
+#[template(path = "valhalla-world.html")]
+pub struct ValhallaWorldTemplate {
+    game_id: GameId,
+    days: u64,
+    players: Vec<WorldPlayer>,
+    tribes: Vec<WorldTribe>,
+}
+
+// Same name and color as the tribes are shown with in the game.
+fn tribe_name(tribe_id: TribeId, game_id: GameId, custom_name: Option<&str>) -> (String, String) {
+    let (name, color) = shared::default_tribe_name(tribe_id, game_id);
+    (custom_name.map(str::to_owned).unwrap_or(name), color)
 }
 
 pub async fn get_valhalla(Extension(pool): Extension<SqlitePool>) -> Result<Response, ServerError> {
@@ -353,5 +523,115 @@ pub async fn get_valhalla(Extension(pool): Extension<SqlitePool>) -> Result<Resp
         )
     });
 
-    Ok(ValhallaTemplate { users }.into_response())
+    let worlds = sqlx::query_as(
+        r#"
+                SELECT game_id, username, finished
+                FROM game_summaries
+                JOIN games ON game_id = id
+                LEFT JOIN users ON winner = user_id
+                ORDER BY finished DESC
+            "#,
+    )
+    .fetch_all(&pool)
+    .await?
+    .into_iter()
+    .map(|(game_id, winner, finished): (GameId, Option<String>, time::PrimitiveDateTime)| FinishedWorld {
+        game_id,
+        winner: winner.unwrap_or_default(),
+        finished,
+    })
+    .collect();
+
+    Ok(ValhallaTemplate { users, worlds }.into_response())
+}
+
+pub async fn get_valhalla_world(
+    Path(game_id): Path<GameId>,
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<Response, ServerError> {
+    let (summary,): (Vec<u8>,) = sqlx::query_as(
+        r#"
+                SELECT summary
+                FROM game_summaries
+                WHERE game_id = $1
+            "#,
+    )
+    .bind(game_id)
+    .fetch_optional(&pool)
+    .await?
+    .ok_or(ServerError::GameNotFound(game_id))?;
+
+    let summary: WorldSummary = rmp_serde::from_slice(&summary)?;
+
+    let user_ids = summary
+        .players
+        .iter()
+        .map(|player| player.user_id.0)
+        .collect::<Vec<_>>();
+    let usernames = sqlx::query_as(
+        r#"
+                SELECT user_id, username
+                FROM users
+                WHERE user_id IN (SELECT value FROM json_each($1))
+            "#,
+    )
+    .bind(serde_json::to_string(&user_ids)?)
+    .fetch_all(&pool)
+    .await?
+    .into_iter()
+    .map(|(user_id, username): (i64, String)| (UserId(user_id), username))
+    .collect::<CustomMap<UserId, String>>();
+
+    let tribe_names = summary
+        .tribes
+        .iter()
+        .map(|tribe| (tribe.tribe_id, tribe.name.clone()))
+        .collect::<CustomMap<TribeId, Option<String>>>();
+
+    let players = summary
+        .players
+        .iter()
+        .map(|player| WorldPlayer {
+            username: usernames.get(&player.user_id).cloned().unwrap_or_default(),
+            level: player.level,
+            dwarfs: player.dwarfs,
+            money: player.money,
+            tribe: player.tribe.map(|tribe_id| {
+                let custom_name = tribe_names.get(&tribe_id).cloned().flatten();
+                tribe_name(tribe_id, game_id, custom_name.as_deref())
+            }),
+            quests_won: player.quests_won,
+            winner: summary.winner == Some(player.user_id),
+            king: summary.king == Some(player.user_id),
+        })
+        .collect();
+
+    let mut tribes = summary
+        .tribes
+        .iter()
+        .map(|tribe| {
+            let (name, color) = tribe_name(tribe.tribe_id, game_id, tribe.name.as_deref());
+            WorldTribe {
+                name,
+                color,
+                members: tribe.members,
+                score: tribe.score,
+                territories: tribe
+                    .territories
+                    .iter()
+                    .map(|(territory, score)| (territory.to_string(), *score))
+                    .collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+    tribes.sort_by_key(|tribe| std::cmp::Reverse(tribe.score));
+
+    Ok(ValhallaWorldTemplate {
+        game_id,
+        // The ingame time passes faster on worlds with a higher speed.
+        days: summary.time / summary.config.speed.max(1) / ONE_DAY,
+        players,
+        tribes,
+    }
+    .into_response())
 }
@@ -1,10 +1,14 @@
 mod about;
 mod admin;
 mod auth;
+mod chat;
 mod db;
 mod error;
 mod game;
 mod index;
+mod save;
+mod snapshot;
+mod stats;
 mod store;
 mod wiki;
 
@@ -117,7 +121,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_same_site(SameSite::Lax)
         .with_expiry(Expiry::OnInactivity(time::Duration::days(30)));
 
-    let game_state = GameStore::new(pool.clone()).load_all().await?;
+    let game_store = GameStore::new(pool.clone());
+    let game_state = game_store.clone().load_all().await?;
 
     // Manage the number of hours for premium accounts.
     let pool_clone = pool.clone();
@@ -190,11 +195,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/store", get(store::get_store))
         .route("/about", get(about::get_about))
         .route("/valhalla", get(game::get_valhalla))
+        .route("/valhalla/:game_id", get(game::get_valhalla_world))
         .nest(
             "/game",
             Router::new()
                 .route("/", get(game::get_game_select))
                 .route("/:game_id/ws", get(game::ws_handler))
+                .route("/:game_id/history", get(stats::get_history))
+                .route("/:game_id/history/:user_id", get(stats::get_player_history))
+                .route(
+                    "/:game_id/tribe-chat",
+                    get(chat::get_tribe_chat).post(chat::post_tribe_message),
+                )
+                .route("/:game_id/conversations", get(chat::get_conversations))
+                .route(
+                    "/:game_id/conversations/:user_id",
+                    post(chat::post_direct_message),
+                )
                 .nest_service("/:game_id", get(game::get_game)),
         )
         .route(
@@ -237,8 +254,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/admin/create-world", post(admin::post_create_world))
         .route("/admin/update-settings", post(admin::post_update_settings))
         .route("/admin/add-premium", post(admin::post_add_premium))
+        .route("/admin/snapshots/:game_id", get(admin::get_snapshots))
+        .route("/admin/restore-snapshot", post(admin::post_restore_snapshot))
         .route("/stripe-webhooks", post(store::handle_webhook))
         .layer(Extension(game_state))
+        .layer(Extension(game_store))
         .layer(Extension(pool.clone()))
         .layer(session_layer)
         .layer(
@@ -0,0 +1,258 @@
+use crate::ServerError;
+use engine_shared::utils::custom_map::CustomSet;
+use rmpv::Value;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use shared::{AutoAction, AutoFunctions, AutoRule, Item, Log, LogMsg, Time};
+
+// Versioned saves start with this header followed by the version as big endian u32,
+// saves from before the versioned format contain only the encoded state.
+const SAVE_HEADER: &[u8] = b"DIE";
+
+pub const SAVE_VERSION: u32 = 1;
+
+// New fields with defaults don't need a migration, older saves decode into the current
+// state as long as fields are only added at the end of a struct. Everything else is
+// migrated on the schemaless value before the state is decoded: the migration at index `n`
+// upgrades a save with version `n` to `n + 1`.
+type Migration = fn(&mut Value) -> Result<(), ServerError>;
+
+const MIGRATIONS: [Migration; SAVE_VERSION as usize] = [
+    // 0 -> 1: automation rules replace the auto functions.
+    auto_rules,
+];
+
+// Structs are encoded as arrays of their fields, these are the positions the migrations
+// need.
+const STATE_PLAYERS: usize = 0;
+const STATE_TIME: usize = 6;
+const PLAYER_LOG: usize = 3;
+const PLAYER_AUTO_FUNCTIONS: usize = 6;
+
+// The auto functions as saved before version 1.
+#[derive(Serialize, Deserialize)]
+struct AutoFunctionsV0 {
+    auto_idle: bool,
+    auto_craft: CustomSet<Item>,
+    auto_store: CustomSet<Item>,
+    auto_sell: CustomSet<Item>,
+    #[serde(default = "CustomSet::new")]
+    auto_dismantle: CustomSet<Item>,
+}
+
+impl AutoFunctionsV0 {
+    // Items can no longer be sold automatically, the items that can be dismantled are
+    // dismantled instead. Returns the rules and the items that are no longer sold.
+    fn into_rules(self) -> (AutoFunctions, Vec<Item>) {
+        let mut auto_dismantle = self.auto_dismantle;
+        let mut not_sold = Vec::new();
+
+        for item in self.auto_sell.iter() {
+            let rule = AutoRule::new(AutoAction::Dismantle, *item);
+            if rule.is_valid() && !self.auto_craft.contains(item) {
+                auto_dismantle.insert(*item);
+            } else {
+                not_sold.push(*item);
+            }
+        }
+
+        let rules = auto_dismantle
+            .iter()
+            .map(|item| AutoRule::new(AutoAction::Dismantle, *item))
+            .chain(
+                self.auto_craft
+                    .iter()
+                    .map(|item| AutoRule::new(AutoAction::Craft, *item)),
+            )
+            .chain(
+                self.auto_store
+                    .iter()
+                    .map(|item| AutoRule::new(AutoAction::Store, *item)),
+            )
+            .collect();
+
+        (
+            AutoFunctions {
+                auto_idle: self.auto_idle,
+                rules,
+            },
+            not_sold,
+        )
+    }
+}
+
+fn auto_rules(state: &mut Value) -> Result<(), ServerError> {
+    let fields = fields_mut(state)?;
+    let time: Time = from_value(fields.get(STATE_TIME).ok_or(ServerError::CorruptSave)?)?;
+    let Value::Map(players) = fields
+        .get_mut(STATE_PLAYERS)
+        .ok_or(ServerError::CorruptSave)?
+    else {
+        return Err(ServerError::CorruptSave);
+    };
+
+    for (_, player) in players {
+        let fields = fields_mut(player)?;
+        if fields.len() <= PLAYER_AUTO_FUNCTIONS {
+            return Err(ServerError::CorruptSave);
+        }
+
+        let auto_functions: AutoFunctionsV0 = from_value(&fields[PLAYER_AUTO_FUNCTIONS])?;
+        let (auto_functions, not_sold) = auto_functions.into_rules();
+        fields[PLAYER_AUTO_FUNCTIONS] = to_value(&auto_functions)?;
+
+        if !not_sold.is_empty() {
+            let mut log: Log = from_value(&fields[PLAYER_LOG])?;
+            log.add(time, LogMsg::AutoSellRemoved(not_sold));
+            fields[PLAYER_LOG] = to_value(&log)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn fields_mut(value: &mut Value) -> Result<&mut Vec<Value>, ServerError> {
+    match value {
+        Value::Array(fields) => Ok(fields),
+        _ => Err(ServerError::CorruptSave),
+    }
+}
+
+fn from_value<T: DeserializeOwned>(value: &Value) -> Result<T, ServerError> {
+    let mut data = Vec::new();
+    rmpv::encode::write_value(&mut data, value).map_err(|_| ServerError::CorruptSave)?;
+    Ok(rmp_serde::from_slice(&data)?)
+}
+
+fn to_value<T: Serialize>(value: &T) -> Result<Value, ServerError> {
+    let data = rmp_serde::to_vec(value)?;
+    rmpv::decode::read_value(&mut &data[..]).map_err(|_| ServerError::CorruptSave)
+}
+
+pub fn encode(state: &shared::State) -> Result<Vec<u8>, ServerError> {
+    let mut data = SAVE_HEADER.to_vec();
+    data.extend_from_slice(&SAVE_VERSION.to_be_bytes());
+    rmp_serde::encode::write(&mut data, state)?;
+    Ok(data)
+}
+
+pub fn decode(data: &[u8]) -> Result<shared::State, ServerError> {
+    let (version, data) = if let Some(data) = data.strip_prefix(SAVE_HEADER) {
+        if data.len() < 4 {
+            return Err(ServerError::CorruptSave);
+        }
+        let (version, data) = data.split_at(4);
+        let version = u32::from_be_bytes([version[0], version[1], version[2], version[3]]);
+        (version, data)
+    } else {
+        (0, data)
+    };
+
+    if version > SAVE_VERSION {
+        return Err(ServerError::UnsupportedSaveVersion(version));
+    }
+    if version == SAVE_VERSION {
+        return Ok(rmp_serde::from_slice(data)?);
+    }
+
+    let mut state =
+        rmpv::decode::read_value(&mut &data[..]).map_err(|_| ServerError::CorruptSave)?;
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut state)?;
+    }
+
+    from_value(&state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_version(version: u32, state: &Value) -> Vec<u8> {
+        let mut data = SAVE_HEADER.to_vec();
+        data.extend_from_slice(&version.to_be_bytes());
+        rmpv::encode::write_value(&mut data, state).unwrap();
+        data
+    }
+
+    #[test]
+    fn encoded_state_decodes_again() {
+        let state = shared::State {
+            time: 1234,
+            ..Default::default()
+        };
+        let decoded = decode(&encode(&state).unwrap()).unwrap();
+        assert_eq!(decoded.time, 1234);
+    }
+
+    #[test]
+    fn saves_without_envelope_are_migrated() {
+        let state = shared::State {
+            time: 1234,
+            ..Default::default()
+        };
+        let decoded = decode(&rmp_serde::to_vec(&state).unwrap()).unwrap();
+        assert_eq!(decoded.time, 1234);
+    }
+
+    #[test]
+    fn auto_functions_are_converted_into_rules() {
+        use rand::{rngs::SmallRng, SeedableRng};
+        use shared::{Player, UserId};
+
+        let mut state = shared::State::default();
+        let player = Player::new(0, &mut SmallRng::seed_from_u64(0), &mut 0);
+        state.players.insert(UserId(0), player);
+
+        // A save from before the versioned format, with the old auto functions.
+        let mut auto_functions = AutoFunctionsV0 {
+            auto_idle: true,
+            auto_craft: CustomSet::new(),
+            auto_store: CustomSet::new(),
+            auto_sell: CustomSet::new(),
+            auto_dismantle: CustomSet::new(),
+        };
+        auto_functions.auto_craft.insert(Item::Iron);
+        auto_functions.auto_sell.insert(Item::Pickaxe);
+        auto_functions.auto_sell.insert(Item::Wood);
+        let mut value = to_value(&state).unwrap();
+        let Value::Map(players) = &mut fields_mut(&mut value).unwrap()[STATE_PLAYERS] else {
+            panic!("players are a map");
+        };
+        fields_mut(&mut players[0].1).unwrap()[PLAYER_AUTO_FUNCTIONS] =
+            to_value(&auto_functions).unwrap();
+        let mut data = Vec::new();
+        rmpv::encode::write_value(&mut data, &value).unwrap();
+
+        let decoded = decode(&data).unwrap();
+        let player = decoded.players.get(&UserId(0)).unwrap();
+        assert_eq!(
+            player.auto_functions.rules,
+            vec![
+                AutoRule::new(AutoAction::Dismantle, Item::Pickaxe),
+                AutoRule::new(AutoAction::Craft, Item::Iron),
+            ]
+        );
+        assert!(matches!(
+            player.log.msgs.back(),
+            Some((_, LogMsg::AutoSellRemoved(items))) if items == &vec![Item::Wood]
+        ));
+    }
+
+    #[test]
+    fn newer_saves_are_rejected() {
+        let state = to_value(&shared::State::default()).unwrap();
+        let data = with_version(SAVE_VERSION + 1, &state);
+        assert!(matches!(
+            decode(&data),
+            Err(ServerError::UnsupportedSaveVersion(version)) if version == SAVE_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn truncated_envelopes_are_rejected() {
+        let state = to_value(&shared::State::default()).unwrap();
+        assert!(matches!(decode(b"DIE\0"), Err(ServerError::CorruptSave)));
+        assert!(decode(&with_version(SAVE_VERSION, &state)[..20]).is_err());
+        assert!(decode(&with_version(0, &state)[..20]).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::ServerError;
+use engine_shared::GameId;
+use sqlx::SqlitePool;
+
+// Kind of snapshot, minimum age of the newest snapshot of that kind before a new one is
+// taken, and number of snapshots that are kept.
+const SNAPSHOT_KINDS: [(&str, &str, i64); 2] = [("hourly", "-1 hours", 48), ("daily", "-1 days", 14)];
+
+// The state before a restore is kept so that the restore can be undone.
+const BEFORE_RESTORE_KIND: &str = "before-restore";
+const BEFORE_RESTORE_RETENTION: i64 = 10;
+
+pub async fn take(
+    db: &SqlitePool,
+    game_id: GameId,
+    ingame_time: u64,
+    data: &[u8],
+) -> Result<(), ServerError> {
+    for (kind, interval, retention) in SNAPSHOT_KINDS {
+        let (recent,): (i64,) = sqlx::query_as(
+            r#"
+                SELECT COUNT(*)
+                FROM game_snapshots
+                WHERE game_id = $1
+                AND kind = $2
+                AND created > DATETIME('now', $3)
+            "#,
+        )
+        .bind(game_id)
+        .bind(kind)
+        .bind(interval)
+        .fetch_one(db)
+        .await?;
+
+        if recent > 0 {
+            continue;
+        }
+
+        insert(db, game_id, kind, ingame_time, data).await?;
+        prune(db, game_id, kind, retention).await?;
+
+        tracing::info!("game {} {} snapshot taken, ingame time {}", game_id, kind, ingame_time);
+    }
+
+    Ok(())
+}
+
+pub async fn take_before_restore(
+    db: &SqlitePool,
+    game_id: GameId,
+    ingame_time: u64,
+    data: &[u8],
+) -> Result<(), ServerError> {
+    insert(db, game_id, BEFORE_RESTORE_KIND, ingame_time, data).await?;
+    prune(db, game_id, BEFORE_RESTORE_KIND, BEFORE_RESTORE_RETENTION).await
+}
+
+// Deletes all but the newest snapshots of that kind.
+async fn prune(
+    db: &SqlitePool,
+    game_id: GameId,
+    kind: &str,
+    retention: i64,
+) -> Result<(), ServerError> {
+    sqlx::query(
+        r#"
+            DELETE FROM game_snapshots
+            WHERE game_id = $1
+            AND kind = $2
+            AND id NOT IN (
+                SELECT id
+                FROM game_snapshots
+                WHERE game_id = $1
+                AND kind = $2
+                ORDER BY id DESC
+                LIMIT $3
+            )
+        "#,
+    )
+    .bind(game_id)
+    .bind(kind)
+    .bind(retention)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert(
+    db: &SqlitePool,
+    game_id: GameId,
+    kind: &str,
+    ingame_time: u64,
+    data: &[u8],
+) -> Result<(), ServerError> {
+    sqlx::query(
+        r#"
+            INSERT INTO game_snapshots (game_id, kind, ingame_time, data)
+            VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(game_id)
+    .bind(kind)
+    .bind(ingame_time as i64)
+    .bind(data)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use crate::ServerError;
+use axum::{extract::Path, Extension, Json};
+use engine_shared::GameId;
+use shared::{Occupation, PlayerStats, ONE_HOUR};
+use sqlx::SqlitePool;
+use tower_sessions::Session;
+
+// Stores the stats of all players of a world, at most once per ingame hour.
+pub async fn sample(
+    db: &SqlitePool,
+    game_id: GameId,
+    state: &shared::State,
+) -> Result<(), ServerError> {
+    let (last_sample,): (Option<i64>,) = sqlx::query_as(
+        r#"
+            SELECT MAX(ingame_time)
+            FROM player_stats
+            WHERE game_id = $1
+        "#,
+    )
+    .bind(game_id)
+    .fetch_one(db)
+    .await?;
+
+    if let Some(last_sample) = last_sample {
+        if state.time < last_sample as u64 + ONE_HOUR {
+            return Ok(());
+        }
+    }
+
+    let mut transaction = db.begin().await?;
+
+    for (user_id, player) in &state.players {
+        if state.is_bot(user_id) {
+            continue;
+        }
+
+        let stats = player.stats(state.time);
+
+        sqlx::query(
+            r#"
+                INSERT INTO player_stats (game_id, user_id, ingame_time, level, dwarfs, food, money, efficiency, produced)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#,
+        )
+        .bind(game_id)
+        .bind(user_id.0)
+        .bind(stats.time as i64)
+        .bind(stats.level as i64)
+        .bind(stats.dwarfs as i64)
+        .bind(stats.food as i64)
+        .bind(stats.money as i64)
+        .bind(stats.efficiency as i64)
+        .bind(serde_json::to_string(&stats.produced)?)
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    tracing::info!("game {} stats sampled, ingame time {}", game_id, state.time);
+
+    Ok(())
+}
+
+// Samples newer than a restored snapshot would block the sampling until the world has caught
+// up again, and they describe a timeline that no longer exists.
+pub async fn discard_after(
+    db: &SqlitePool,
+    game_id: GameId,
+    ingame_time: u64,
+) -> Result<(), ServerError> {
+    sqlx::query(
+        r#"
+            DELETE FROM player_stats
+            WHERE game_id = $1
+            AND ingame_time > $2
+        "#,
+    )
+    .bind(game_id)
+    .bind(ingame_time as i64)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_history(
+    Path(game_id): Path<GameId>,
+    session: Session,
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<Json<Vec<PlayerStats>>, ServerError> {
+    let user_id = session
+        .get::<i64>(crate::USER_ID_KEY)
+        .await?
+        .ok_or(ServerError::InvalidSession)?;
+
+    history(&pool, game_id, user_id).await
+}
+
+// Players can compare themselves with the other players of the worlds they play in.
+pub async fn get_player_history(
+    Path((game_id, user_id)): Path<(GameId, i64)>,
+    session: Session,
+    Extension(pool): Extension<SqlitePool>,
+) -> Result<Json<Vec<PlayerStats>>, ServerError> {
+    let own_user_id = session
+        .get::<i64>(crate::USER_ID_KEY)
+        .await?
+        .ok_or(ServerError::InvalidSession)?;
+
+    let (samples,): (i64,) = sqlx::query_as(
+        r#"
+            SELECT COUNT(*)
+            FROM player_stats
+            WHERE game_id = $1
+            AND user_id = $2
+        "#,
+    )
+    .bind(game_id)
+    .bind(own_user_id)
+    .fetch_one(&pool)
+    .await?;
+
+    if samples == 0 {
+        return Err(ServerError::NotInGame(game_id));
+    }
+
+    history(&pool, game_id, user_id).await
+}
+
+async fn history(
+    pool: &SqlitePool,
+    game_id: GameId,
+    user_id: i64,
+) -> Result<Json<Vec<PlayerStats>>, ServerError> {
+    let rows: Vec<(i64, i64, i64, i64, i64, i64, String)> = sqlx::query_as(
+        r#"
+            SELECT ingame_time, level, dwarfs, food, money, efficiency, produced
+            FROM player_stats
+            WHERE game_id = $1
+            AND user_id = $2
+            ORDER BY ingame_time
+        "#,
+    )
+    .bind(game_id)
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    let history = rows
+        .into_iter()
+        .map(
+            |(time, level, dwarfs, food, money, efficiency, produced)| -> Result<PlayerStats, ServerError> {
+                Ok(PlayerStats {
+                    time: time as u64,
+                    level: level as u64,
+                    dwarfs: dwarfs as u64,
+                    food: food as u64,
+                    money: money as u64,
+                    efficiency: efficiency as u64,
+                    produced: serde_json::from_str::<Vec<(Occupation, u64)>>(&produced)?,
+                })
+            },
+        )
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(history))
+}
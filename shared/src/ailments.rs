@@ -0,0 +1,149 @@
+use crate::{
+    Bundle, Dwarf, DwarfId, Item, LogMsg, Occupation, Perk, Player, Time, WorldEvent, ONE_DAY,
+    ONE_MINUTE,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+// Expected number of ticks a healer needs to treat a dwarf.
+const TREATMENT_TICKS: u64 = ONE_MINUTE * 30;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, Display)]
+pub enum Ailment {
+    Injury,
+    Infection,
+}
+
+impl Ailment {
+    pub fn treatment(self) -> Item {
+        match self {
+            Ailment::Injury => Item::Bandage,
+            Ailment::Infection => Item::Medicine,
+        }
+    }
+
+    pub fn effectiveness_percent(self) -> u64 {
+        match self {
+            Ailment::Injury => 50,
+            Ailment::Infection => 70,
+        }
+    }
+
+    pub fn extra_health_cost_per_tick(self) -> u64 {
+        match self {
+            Ailment::Injury => 1,
+            Ailment::Infection => 3,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Ailment::Injury => "This dwarf is injured and works only half as effectively. A healer can treat the injury with a bandage.",
+            Ailment::Infection => "This dwarf is infected, loses health faster and can infect other dwarfs during the plague. A healer can treat the infection with medicine.",
+        }
+    }
+}
+
+impl Dwarf {
+    // Expected number of ticks until the dwarf gets injured in its current occupation.
+    fn injury_risk(&self, event: Option<&WorldEvent>) -> Option<u32> {
+        let occupation = self.actual_occupation();
+        let risk = match (occupation, event) {
+            (Occupation::Mining, Some(WorldEvent::Earthquake)) => ONE_DAY as u32 / 2,
+            (Occupation::Fighting, _) => ONE_DAY as u32,
+            (Occupation::Exploring, _) => ONE_DAY as u32 * 2,
+            _ => return None,
+        };
+        if self.has_perk(occupation, Perk::Seasoned) {
+            Some(risk * 2)
+        } else {
+            Some(risk)
+        }
+    }
+}
+
+impl Player {
+    pub fn update_ailments(&mut self, rng: &mut impl Rng, event: Option<&WorldEvent>, time: Time) {
+        let infected = self
+            .dwarfs
+            .values()
+            .filter(|dwarf| dwarf.ailment == Some(Ailment::Infection))
+            .count() as u32;
+
+        // New injuries and infections.
+        for dwarf in self.dwarfs.values_mut() {
+            if dwarf.dead() || dwarf.ailment.is_some() {
+                continue;
+            }
+
+            if let Some(risk) = dwarf.injury_risk(event) {
+                if rng.gen_ratio(1, risk) {
+                    dwarf.ailment = Some(Ailment::Injury);
+                    self.log
+                        .add(time, LogMsg::DwarfInjured(dwarf.actual_name().to_owned()));
+                    continue;
+                }
+            }
+
+            // During the plague infected dwarfs also infect the others.
+            if matches!(event, Some(WorldEvent::Plague))
+                && rng.gen_ratio(1 + infected, ONE_DAY as u32)
+            {
+                dwarf.ailment = Some(Ailment::Infection);
+                self.log
+                    .add(time, LogMsg::DwarfInfected(dwarf.actual_name().to_owned()));
+            }
+        }
+
+        // Healers treat the dwarfs with the lowest health first.
+        let healers = self
+            .dwarfs
+            .values()
+            .filter(|dwarf| {
+                !dwarf.dead()
+                    && dwarf.is_adult()
+                    && dwarf.actual_occupation() == Occupation::Healing
+                    && dwarf.gen_ratio_effectiveness(&self.dwarfs, rng, TREATMENT_TICKS)
+            })
+            .count();
+
+        for _ in 0..healers {
+            let patient = self
+                .dwarfs
+                .iter()
+                .filter(|(_, dwarf)| {
+                    !dwarf.dead()
+                        && dwarf
+                            .ailment
+                            .map(|ailment| {
+                                self.inventory.items.get(&ailment.treatment()).copied().unwrap_or(0) > 0
+                            })
+                            .unwrap_or(false)
+                })
+                .min_by_key(|(_, dwarf)| dwarf.health)
+                .map(|(dwarf_id, _)| *dwarf_id);
+
+            if let Some(patient) = patient {
+                self.treat(patient, time);
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn treat(&mut self, dwarf_id: DwarfId, time: Time) -> Option<()> {
+        let dwarf = self.dwarfs.get_mut(&dwarf_id)?;
+        let ailment = dwarf.ailment?;
+        if self
+            .inventory
+            .items
+            .remove_checked(Bundle::new().add(ailment.treatment(), 1))
+        {
+            dwarf.ailment = None;
+            self.log
+                .add(time, LogMsg::DwarfCured(dwarf.actual_name().to_owned()));
+        }
+        Some(())
+    }
+}
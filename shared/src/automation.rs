@@ -0,0 +1,219 @@
+use crate::{
+    AutoFunctions, Bundle, Craftable, Item, ItemType, Player, Time, DISMANTLING_DIVIDER, ONE_DAY,
+};
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+pub const MAX_AUTO_RULES: usize = 50;
+
+// Rules are applied repeatedly until nothing changes, e.g. to craft Nails from the Iron
+// that another rule has just crafted.
+const MAX_AUTO_PASSES: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, PartialEq, Eq, Display)]
+pub enum AutoAction {
+    Craft,
+    Dismantle,
+    Store,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct AutoRule {
+    pub action: AutoAction,
+    pub item: Item,
+    // Number of the consumed items that is kept in the inventory. These are the ingredients
+    // for crafting and the item itself otherwise.
+    pub keep: u64,
+    // The rule only applies while the food storage lasts for less than this number of days.
+    pub max_food_days: Option<u64>,
+}
+
+impl AutoRule {
+    pub fn new(action: AutoAction, item: Item) -> Self {
+        AutoRule {
+            action,
+            item,
+            keep: 0,
+            max_food_days: None,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self.action {
+            AutoAction::Craft => self.item.requires().is_some(),
+            AutoAction::Dismantle => {
+                self.item.requires().is_some()
+                    && matches!(
+                        self.item.item_type(),
+                        Some(ItemType::Tool | ItemType::Jewelry | ItemType::Clothing)
+                    )
+            }
+            AutoAction::Store => self.item.nutritional_value().is_some(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AutoOutcome {
+    pub consumed: Bundle<Item>,
+    pub produced: Bundle<Item>,
+    pub food: u64,
+}
+
+impl AutoFunctions {
+    pub fn has_rule(&self, action: AutoAction, item: Item) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.action == action && rule.item == item)
+    }
+
+    pub fn has_any_rule(&self, item: Item) -> bool {
+        self.rules.iter().any(|rule| rule.item == item)
+    }
+
+    // Removes the rule if there is one, otherwise adds it with the lowest priority. An item is
+    // never crafted and dismantled at the same time.
+    pub fn toggle_rule(&mut self, action: AutoAction, item: Item) {
+        if self.has_rule(action, item) {
+            self.rules
+                .retain(|rule| !(rule.action == action && rule.item == item));
+        } else if self.rules.len() < MAX_AUTO_RULES {
+            let rule = AutoRule::new(action, item);
+            if rule.is_valid() {
+                self.rules.retain(|rule| {
+                    !(rule.item == item
+                        && matches!(
+                            (rule.action, action),
+                            (AutoAction::Craft, AutoAction::Dismantle)
+                                | (AutoAction::Dismantle, AutoAction::Craft)
+                        ))
+                });
+                self.rules.push(rule);
+            }
+        }
+    }
+
+    pub fn update_rule(&mut self, idx: usize, rule: AutoRule) {
+        if rule.is_valid() {
+            if let Some(old_rule) = self.rules.get_mut(idx) {
+                *old_rule = rule;
+            }
+        }
+    }
+
+    pub fn move_rule_up(&mut self, idx: usize) {
+        if idx > 0 && idx < self.rules.len() {
+            self.rules.swap(idx - 1, idx);
+        }
+    }
+}
+
+impl Player {
+    pub fn run_auto_rules(&mut self, time: Time, is_premium: bool) {
+        if is_premium {
+            self.apply_auto_rules(time);
+        }
+    }
+
+    // Shows what the rules would do if the items were added to the inventory, without
+    // changing anything.
+    pub fn preview_auto_rules(&self, items: Bundle<Item>) -> Vec<AutoOutcome> {
+        let mut player = self.clone();
+        player.inventory.items.add_checked(items);
+        player.apply_auto_rules(0)
+    }
+
+    // Returns the outcome of every rule, in the order of the rules.
+    fn apply_auto_rules(&mut self, time: Time) -> Vec<AutoOutcome> {
+        let rules = self.auto_functions.rules.clone();
+        let mut outcomes = vec![AutoOutcome::default(); rules.len()];
+
+        for _ in 0..MAX_AUTO_PASSES {
+            let mut changed = false;
+            for (rule, outcome) in rules.iter().zip(outcomes.iter_mut()) {
+                changed |= self.apply_auto_rule(rule, time, outcome);
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        outcomes
+    }
+
+    fn apply_auto_rule(&mut self, rule: &AutoRule, time: Time, outcome: &mut AutoOutcome) -> bool {
+        if let Some(days) = rule.max_food_days {
+            // World events are ignored here, the rules run whenever items are added.
+            if self.time_until_starvation(None) >= days * ONE_DAY {
+                return false;
+            }
+        }
+
+        let available = |item: Item, player: &Player| {
+            player
+                .inventory
+                .items
+                .get(&item)
+                .copied()
+                .unwrap_or_default()
+                .saturating_sub(rule.keep)
+        };
+
+        match rule.action {
+            AutoAction::Craft => {
+                let Some((level, requires)) = rule.item.requires() else {
+                    return false;
+                };
+                if self.base.curr_level < level {
+                    return false;
+                }
+                let qty = requires
+                    .iter()
+                    .map(|(ingredient, n)| available(*ingredient, self) / n)
+                    .min()
+                    .unwrap_or(0);
+                if qty == 0
+                    || !self
+                        .inventory
+                        .items
+                        .remove_checked(requires.clone().mul(qty))
+                {
+                    return false;
+                }
+                let produced = Bundle::new().add(rule.item, qty);
+                self.inventory.add(produced.clone(), time);
+                outcome.consumed.add_checked(requires.mul(qty));
+                outcome.produced.add_checked(produced);
+            }
+            AutoAction::Dismantle => {
+                let Some((_, requires)) = rule.item.requires() else {
+                    return false;
+                };
+                let qty = available(rule.item, self);
+                let consumed = Bundle::new().add(rule.item, qty);
+                if qty == 0 || !self.inventory.items.remove_checked(consumed.clone()) {
+                    return false;
+                }
+                let produced = requires.mul(qty).div(DISMANTLING_DIVIDER);
+                self.inventory.add(produced.clone(), time);
+                outcome.consumed.add_checked(consumed);
+                outcome.produced.add_checked(produced);
+            }
+            AutoAction::Store => {
+                let Some(food) = rule.item.nutritional_value() else {
+                    return false;
+                };
+                let qty = available(rule.item, self);
+                let consumed = Bundle::new().add(rule.item, qty);
+                if qty == 0 || !self.inventory.items.remove_checked(consumed.clone()) {
+                    return false;
+                }
+                self.base.food += food * qty;
+                outcome.consumed.add_checked(consumed);
+                outcome.food += food * qty;
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{
+    Ailment, DwarfId, LogMsg, Occupation, Player, Territory, Time, Tribe, TribeId, UserId,
+    MAX_HEALTH, ONE_HOUR,
+};
+use engine_shared::utils::custom_map::CustomMap;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+pub type BattleId = u64;
+
+pub const BATTLE_DURATION: Time = ONE_HOUR * 6;
+pub const MAX_BATTLE_DWARFS: usize = 5;
+pub const DECLARE_ATTACK_COST: u64 = 5;
+pub const DEFENCE_BONUS_PERCENT: u64 = 25;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Hash)]
+pub struct Battle {
+    pub territory: Territory,
+    pub attacker: TribeId,
+    // The tribe that controlled the territory when the attack was declared.
+    pub defender: Option<TribeId>,
+    pub time_left: Time,
+    pub fighters: CustomMap<UserId, Vec<DwarfId>>,
+    pub attack_score: u64,
+    // Includes the defence bonus.
+    pub defence_score: u64,
+}
+
+impl Territory {
+    // The tribe with the unique highest score controls the territory.
+    pub fn controller(self, tribes: &CustomMap<TribeId, Tribe>) -> Option<TribeId> {
+        let (tribe_id, max) = tribes
+            .iter()
+            .map(|(tribe_id, tribe)| (*tribe_id, tribe.score(self)))
+            .max_by_key(|(_, score)| *score)?;
+
+        let under_control = max > 0
+            && tribes
+                .values()
+                .filter(|tribe| tribe.score(self) >= max)
+                .count()
+                == 1;
+
+        if under_control {
+            Some(tribe_id)
+        } else {
+            None
+        }
+    }
+}
+
+impl Tribe {
+    pub fn score(&self, territory: Territory) -> u64 {
+        self.territories
+            .get(&territory)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Battle {
+    pub fn new(territory: Territory, attacker: TribeId, defender: Option<TribeId>) -> Self {
+        Battle {
+            territory,
+            attacker,
+            defender,
+            time_left: BATTLE_DURATION,
+            fighters: CustomMap::new(),
+            attack_score: 0,
+            defence_score: 0,
+        }
+    }
+
+    pub fn can_join(&self, tribe: Option<TribeId>) -> bool {
+        tribe.is_some() && (tribe == Some(self.attacker) || tribe == self.defender)
+    }
+
+    pub fn commit(
+        &mut self,
+        battle_id: BattleId,
+        user_id: UserId,
+        player: &mut Player,
+        dwarf_id: DwarfId,
+        commit: bool,
+    ) -> Option<()> {
+        let can_join = self.can_join(player.tribe) && !self.done();
+        let dwarf = player.dwarfs.get_mut(&dwarf_id)?;
+        let fighters = self.fighters.entry(user_id).or_default();
+
+        if commit {
+            if can_join
+                && dwarf.is_adult()
+                && dwarf.participates_in_quest.is_none()
+                && dwarf.participates_in_battle.is_none()
+                && fighters.len() < MAX_BATTLE_DWARFS
+            {
+                dwarf.participates_in_battle = Some(battle_id);
+                fighters.push(dwarf_id);
+            }
+        } else if dwarf.participates_in_battle == Some(battle_id) {
+            dwarf.participates_in_battle = None;
+            fighters.retain(|id| *id != dwarf_id);
+        }
+
+        Some(())
+    }
+
+    // Players that have left the world don't fight anymore, the battle still goes on.
+    pub fn run(&mut self, players: &CustomMap<UserId, Player>) {
+        if self.time_left > 0 {
+            self.time_left -= 1;
+            for (user_id, fighters) in self.fighters.iter() {
+                let Some(player) = players.get(user_id) else {
+                    continue;
+                };
+
+                let score = fighters
+                    .iter()
+                    .filter_map(|dwarf_id| player.dwarfs.get(dwarf_id))
+                    .filter(|dwarf| dwarf.actual_occupation() == Occupation::Fighting)
+                    .map(|dwarf| {
+                        dwarf.numerator_effectiveness(&player.dwarfs) * dwarf.quest_score_percent()
+                            / 100
+                            / 100
+                    })
+                    .sum::<u64>();
+
+                // Players that changed the tribe since they joined don't count anymore.
+                if player.tribe == Some(self.attacker) {
+                    self.attack_score += score;
+                } else if player.tribe.is_some() && player.tribe == self.defender {
+                    self.defence_score += score * (100 + DEFENCE_BONUS_PERCENT) / 100;
+                }
+            }
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.time_left == 0
+    }
+
+    pub fn attacker_won(&self) -> bool {
+        self.attack_score > self.defence_score
+    }
+
+    // The winner takes control of the territory, the loser loses half of its score there.
+    // All fighters get hurt, the losers much more.
+    pub fn end(
+        self,
+        players: &mut CustomMap<UserId, Player>,
+        tribes: &mut CustomMap<TribeId, Tribe>,
+        rng: &mut impl Rng,
+        time: Time,
+    ) {
+        let attacker_won = self.attacker_won();
+
+        if attacker_won {
+            if let Some(defender) = self.defender.and_then(|id| tribes.get_mut(&id)) {
+                *defender.territories.entry(self.territory).or_default() /= 2;
+            }
+            let best_other = tribes
+                .iter()
+                .filter(|(tribe_id, _)| **tribe_id != self.attacker)
+                .map(|(_, tribe)| tribe.score(self.territory))
+                .max()
+                .unwrap_or_default();
+            if let Some(attacker) = tribes.get_mut(&self.attacker) {
+                let score = attacker.territories.entry(self.territory).or_default();
+                *score = (*score).max(best_other + 1);
+            }
+        } else if let Some(attacker) = tribes.get_mut(&self.attacker) {
+            *attacker.territories.entry(self.territory).or_default() /= 2;
+        }
+
+        for (user_id, player) in players.iter_mut() {
+            if !self.can_join(player.tribe) {
+                continue;
+            }
+            let won = (player.tribe == Some(self.attacker)) == attacker_won;
+
+            if let Some(fighters) = self.fighters.get(user_id) {
+                let (damage, injury_probability) = if won {
+                    (MAX_HEALTH / 10, 0.2)
+                } else {
+                    (MAX_HEALTH / 3, 0.5)
+                };
+                for dwarf_id in fighters {
+                    if let Some(dwarf) = player.dwarfs.get_mut(dwarf_id) {
+                        dwarf.participates_in_battle = None;
+                        dwarf.decr_health(damage);
+                        if !dwarf.dead()
+                            && dwarf.ailment.is_none()
+                            && rng.gen_bool(injury_probability)
+                        {
+                            dwarf.ailment = Some(Ailment::Injury);
+                        }
+                    }
+                }
+            }
+
+            player.log.add(
+                time,
+                if won {
+                    LogMsg::BattleWon(self.territory)
+                } else {
+                    LogMsg::BattleLost(self.territory)
+                },
+            );
+        }
+
+        // Fighters of players that left the tribe are released as well.
+        for (user_id, fighters) in &self.fighters {
+            if let Some(player) = players.get_mut(user_id) {
+                for dwarf_id in fighters {
+                    if let Some(dwarf) = player.dwarfs.get_mut(dwarf_id) {
+                        dwarf.participates_in_battle = None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::players;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    #[test]
+    fn battles_end_when_fighters_have_left_the_world() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut players = players(1);
+        let mut tribes = CustomMap::new();
+        tribes.insert(0, Tribe::default());
+        let player = players.get_mut(&UserId(0)).unwrap();
+        player.tribe = Some(0);
+        let dwarf_id = *player.dwarfs.keys().next().unwrap();
+        player
+            .dwarfs
+            .get_mut(&dwarf_id)
+            .unwrap()
+            .participates_in_battle = Some(0);
+
+        let mut battle = Battle::new(Territory::Forest, 0, None);
+        battle.fighters.insert(UserId(0), vec![dwarf_id]);
+        battle.fighters.insert(UserId(1), vec![dwarf_id]);
+        while !battle.done() {
+            battle.run(&players);
+        }
+        battle.end(&mut players, &mut tribes, &mut rng, 0);
+
+        let player = players.get(&UserId(0)).unwrap();
+        assert!(player
+            .dwarfs
+            .get(&dwarf_id)
+            .unwrap()
+            .participates_in_battle
+            .is_none());
+        assert_eq!(tribes.get(&0).unwrap().score(Territory::Forest), 1);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::{ClientEvent, Craftable, Item, Occupation, Player, RewardMode, State, UserId, ONE_DAY};
+use enum_iterator::Sequence;
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+pub trait BotStrategy {
+    fn events(&self, user_id: UserId, player: &Player, state: &State) -> Vec<ClientEvent>;
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, PartialEq, Eq, Sequence, Display)]
+#[strum(serialize_all = "title_case")]
+pub enum BotKind {
+    GreedyCrafter,
+    QuestHunter,
+    FoodSafeIdler,
+}
+
+impl BotStrategy for BotKind {
+    fn events(&self, user_id: UserId, player: &Player, state: &State) -> Vec<ClientEvent> {
+        match self {
+            BotKind::GreedyCrafter => GreedyCrafter.events(user_id, player, state),
+            BotKind::QuestHunter => QuestHunter.events(user_id, player, state),
+            BotKind::FoodSafeIdler => FoodSafeIdler.events(user_id, player, state),
+        }
+    }
+}
+
+// Upgrades the base whenever possible and crafts the rarest equipment it can afford
+// with half of its resources.
+pub struct GreedyCrafter;
+
+impl BotStrategy for GreedyCrafter {
+    fn events(&self, _user_id: UserId, player: &Player, _state: &State) -> Vec<ClientEvent> {
+        let mut events = manage(player, player.dwarfs.len() as u64 / 5, &[]);
+        events.push(ClientEvent::UpgradeBase);
+
+        let mut craftable = enum_iterator::all::<Item>()
+            .filter(|item| item.item_type().is_some())
+            .filter_map(|item| {
+                let (level, requires) = item.requires()?;
+                if level > player.base.curr_level {
+                    return None;
+                }
+                let qty = player.inventory.items.can_remove_x_times(&requires)? / 2;
+                if qty == 0 {
+                    return None;
+                }
+                Some((item, qty))
+            })
+            .collect::<Vec<_>>();
+        craftable.sort_by_key(|(item, _)| std::cmp::Reverse(item.item_rarity_num()));
+
+        // Only queue a new job when the last one is finished.
+        if player.crafting_queue.is_empty() {
+            if let Some((item, qty)) = craftable.first() {
+                events.push(ClientEvent::Craft(*item, *qty));
+            }
+        }
+
+        events.extend(store_food(player));
+        events.push(ClientEvent::Optimize(None));
+        events
+    }
+}
+
+// Sends its best dwarfs on every quest it is allowed to join.
+pub struct QuestHunter;
+
+impl BotStrategy for QuestHunter {
+    fn events(&self, user_id: UserId, player: &Player, state: &State) -> Vec<ClientEvent> {
+        let mut events = manage(player, player.dwarfs.len() as u64 / 5, &[]);
+        events.push(ClientEvent::UpgradeBase);
+        events.extend(store_food(player));
+
+        let max_questing = player.dwarfs.len() / 3;
+        let mut questing = player
+            .dwarfs
+            .values()
+            .filter(|dwarf| dwarf.participates_in_quest.is_some())
+            .count();
+        let mut free_dwarfs = player
+            .dwarfs
+            .iter()
+            .filter(|(_, dwarf)| dwarf.can_be_managed())
+            .map(|(dwarf_id, dwarf)| (*dwarf_id, dwarf))
+            .collect::<Vec<_>>();
+
+        for (quest_id, quest) in &state.quests {
+            if questing >= max_questing {
+                break;
+            }
+            if player.base.curr_level > quest.max_level
+                || player.base.curr_level < quest.min_level
+                || matches!(quest.quest_type.reward_mode(), RewardMode::BecomeKing)
+            {
+                continue;
+            }
+
+            let occupation = quest.quest_type.occupation();
+            free_dwarfs.sort_by_key(|(_, dwarf)| dwarf.effectiveness_not_normalized(occupation));
+
+            for dwarf_idx in 0..quest.quest_type.max_dwarfs() {
+                if questing >= max_questing {
+                    break;
+                }
+                let taken = quest
+                    .contestants
+                    .get(&user_id)
+                    .map(|contestant| contestant.dwarfs.contains_key(&dwarf_idx))
+                    .unwrap_or(false);
+                if taken {
+                    continue;
+                }
+                if let Some((dwarf_id, _)) = free_dwarfs.pop() {
+                    events.push(ClientEvent::AssignToQuest(
+                        *quest_id,
+                        dwarf_idx,
+                        Some(dwarf_id),
+                    ));
+                    questing += 1;
+                }
+            }
+        }
+
+        events.push(ClientEvent::Optimize(None));
+        events
+    }
+}
+
+// Keeps at least three days of food in storage by moving dwarfs to the food
+// occupations, otherwise it grows slowly with many idle dwarfs.
+pub struct FoodSafeIdler;
+
+impl BotStrategy for FoodSafeIdler {
+    fn events(&self, _user_id: UserId, player: &Player, state: &State) -> Vec<ClientEvent> {
+        let food_occupations = enum_iterator::all::<Occupation>()
+            .filter(|occupation| {
+                enum_iterator::all::<Item>().any(|item| {
+                    item.nutritional_value().is_some()
+                        && item.item_probability(*occupation).is_some()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut events = if player.remaining_time_until_starvation(state) < ONE_DAY * 3 {
+            manage(player, player.dwarfs.len() as u64 / 5, &food_occupations)
+        } else {
+            manage(player, player.dwarfs.len() as u64 / 3, &[])
+        };
+        events.push(ClientEvent::UpgradeBase);
+        events.extend(store_food(player));
+        events.push(ClientEvent::Optimize(None));
+        events
+    }
+}
+
+// Spreads the managed dwarfs over the unlocked occupations, the given number of dwarfs
+// stays idle. If `only` is not empty, only these occupations are used.
+fn manage(player: &Player, idle: u64, only: &[Occupation]) -> Vec<ClientEvent> {
+    let mut events = Vec::new();
+
+    let occupations = enum_iterator::all::<Occupation>()
+        .filter(|occupation| {
+            *occupation != Occupation::Idling
+                && occupation.unlocked_at_level() <= player.base.curr_level
+                && (only.is_empty() || only.contains(occupation))
+        })
+        .collect::<Vec<_>>();
+
+    if occupations.is_empty() {
+        return events;
+    }
+
+    let managed = player
+        .dwarfs
+        .values()
+        .filter(|dwarf| dwarf.can_be_managed())
+        .count() as u64;
+    let working = managed.saturating_sub(idle);
+
+    for occupation in enum_iterator::all::<Occupation>() {
+        if occupation != Occupation::Idling {
+            events.push(ClientEvent::SetManagerOccupation(occupation, 0));
+        }
+    }
+    for (idx, occupation) in occupations.iter().enumerate() {
+        let num = working / occupations.len() as u64
+            + if (idx as u64) < working % occupations.len() as u64 {
+                1
+            } else {
+                0
+            };
+        events.push(ClientEvent::SetManagerOccupation(*occupation, num));
+    }
+
+    events
+}
+
+fn store_food(player: &Player) -> Vec<ClientEvent> {
+    player
+        .inventory
+        .items
+        .iter()
+        .filter(|(item, qty)| item.nutritional_value().is_some() && **qty > 0)
+        .map(|(item, qty)| ClientEvent::AddToFoodStorage(*item, *qty))
+        .collect()
+}
@@ -0,0 +1,187 @@
+use crate::{Dwarf, DwarfId, DwarfTrait, Occupation, Player, Stats, Time, ONE_DAY};
+use rand::{seq::SliceRandom, Rng};
+
+const MUTATION_PROBABILITY: f64 = 0.2;
+
+impl Stats {
+    // Every stat is inherited from one of the parents and sometimes mutates.
+    pub fn inherit(self, other: Self, rng: &mut impl Rng) -> Self {
+        let mut gene = |a: i8, b: i8| {
+            let mut value = if rng.gen_bool(0.5) { a } else { b };
+            if rng.gen_bool(MUTATION_PROBABILITY) {
+                value += *[-2, -1, 1, 2].choose(rng).unwrap();
+            }
+            value.clamp(1, 10)
+        };
+
+        Stats {
+            strength: gene(self.strength, other.strength),
+            endurance: gene(self.endurance, other.endurance),
+            agility: gene(self.agility, other.agility),
+            intelligence: gene(self.intelligence, other.intelligence),
+            perception: gene(self.perception, other.perception),
+        }
+    }
+}
+
+impl Dwarf {
+    pub fn can_have_children(&self) -> bool {
+        self.occupation == Occupation::Idling && self.is_adult() && !self.dead()
+    }
+
+    pub fn can_be_partner_of(&self, other: &Dwarf) -> bool {
+        self.is_adult() && other.is_adult() && self.is_female != other.is_female
+    }
+
+    // Parents, children and (half) siblings are related.
+    fn is_related_to(&self, self_id: DwarfId, other_id: DwarfId, other: &Dwarf) -> bool {
+        let is_parent = |dwarf: &Dwarf, parent_id: DwarfId| {
+            dwarf
+                .parents
+                .map(|(mother_id, father_id)| mother_id == parent_id || father_id == parent_id)
+                .unwrap_or(false)
+        };
+        let siblings = match (self.parents, other.parents) {
+            (Some((mother_a, father_a)), Some((mother_b, father_b))) => {
+                mother_a == mother_b || father_a == father_b
+            }
+            _ => false,
+        };
+
+        is_parent(self, other_id) || is_parent(other, self_id) || siblings
+    }
+}
+
+impl Player {
+    pub fn can_be_partners(&self, dwarf_id: DwarfId, partner_id: DwarfId) -> bool {
+        let (Some(dwarf), Some(partner)) =
+            (self.dwarfs.get(&dwarf_id), self.dwarfs.get(&partner_id))
+        else {
+            return false;
+        };
+
+        dwarf.can_be_partner_of(partner) && !dwarf.is_related_to(dwarf_id, partner_id, partner)
+    }
+
+    pub fn set_partner(&mut self, dwarf_id: DwarfId, partner_id: Option<DwarfId>) -> Option<()> {
+        let old_partner = self.dwarfs.get_mut(&dwarf_id)?.partner.take();
+        if let Some(old_partner) = old_partner.and_then(|id| self.dwarfs.get_mut(&id)) {
+            old_partner.partner = None;
+        }
+
+        if let Some(partner_id) = partner_id {
+            let old_partner = self.dwarfs.get_mut(&partner_id)?.partner.replace(dwarf_id);
+            if let Some(old_partner) = old_partner.and_then(|id| self.dwarfs.get_mut(&id)) {
+                old_partner.partner = None;
+            }
+            self.dwarfs.get_mut(&dwarf_id)?.partner = Some(partner_id);
+        }
+
+        Some(())
+    }
+
+    // Idle couples can get a baby, idle dwarfs without a partner are paired randomly.
+    pub fn breed(
+        &mut self,
+        rng: &mut impl Rng,
+        next_dwarf_id: &mut DwarfId,
+        time: Time,
+        multiplier: u32,
+    ) {
+        let mut parents = Vec::new();
+        let mut single_females = Vec::new();
+        let mut single_males = Vec::new();
+
+        for (dwarf_id, dwarf) in &self.dwarfs {
+            if !dwarf.can_have_children() {
+                continue;
+            }
+            match dwarf.partner {
+                Some(partner_id) => {
+                    if dwarf.is_female
+                        && self
+                            .dwarfs
+                            .get(&partner_id)
+                            .map(|partner| partner.can_have_children())
+                            .unwrap_or(false)
+                        && self.can_be_partners(*dwarf_id, partner_id)
+                    {
+                        parents.push((*dwarf_id, partner_id));
+                    }
+                }
+                None if dwarf.is_female => single_females.push(*dwarf_id),
+                None => single_males.push(*dwarf_id),
+            }
+        }
+
+        single_females.shuffle(rng);
+        single_males.shuffle(rng);
+        for female_id in single_females {
+            if let Some(idx) = single_males
+                .iter()
+                .position(|male_id| self.can_be_partners(female_id, *male_id))
+            {
+                parents.push((female_id, single_males.swap_remove(idx)));
+            }
+        }
+
+        for (mother_id, father_id) in parents {
+            if rng.gen_ratio(multiplier, ONE_DAY as u32 / 4) {
+                self.new_baby(rng, next_dwarf_id, time, mother_id, father_id);
+            }
+        }
+    }
+
+    fn new_baby(
+        &mut self,
+        rng: &mut impl Rng,
+        next_dwarf_id: &mut DwarfId,
+        time: Time,
+        mother_id: DwarfId,
+        father_id: DwarfId,
+    ) -> Option<()> {
+        let mother = self.dwarfs.get(&mother_id)?;
+        let father = self.dwarfs.get(&father_id)?;
+
+        let mut baby = Dwarf::new_baby(rng);
+        baby.stats = mother.stats.inherit(father.stats, rng);
+        baby.traits = DwarfTrait::inherit(&mother.traits, &father.traits, rng);
+        baby.parents = Some((mother_id, father_id));
+
+        let baby_id = self.add_dwarf(baby, next_dwarf_id, time)?;
+        self.dwarfs.get_mut(&mother_id)?.children.push(baby_id);
+        self.dwarfs.get_mut(&father_id)?.children.push(baby_id);
+
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{tests::crafter, Bundle, ADULT_AGE};
+
+    #[test]
+    fn relatives_cannot_be_partners() {
+        let mut player = crafter(Bundle::new());
+        let ids = player.dwarfs.keys().copied().take(4).collect::<Vec<_>>();
+        let (mother, father, daughter, son) = (ids[0], ids[1], ids[2], ids[3]);
+        for (id, is_female) in [
+            (mother, true),
+            (father, false),
+            (daughter, true),
+            (son, false),
+        ] {
+            let dwarf = player.dwarfs.get_mut(&id).unwrap();
+            dwarf.is_female = is_female;
+            dwarf.age_seconds = ADULT_AGE * 365 * 24 * 60 * 60;
+        }
+        for child in [daughter, son] {
+            player.dwarfs.get_mut(&child).unwrap().parents = Some((mother, father));
+        }
+
+        assert!(player.can_be_partners(mother, father));
+        assert!(!player.can_be_partners(mother, son));
+        assert!(!player.can_be_partners(daughter, father));
+        assert!(!player.can_be_partners(daughter, son));
+    }
+}
@@ -0,0 +1,401 @@
+use crate::{Bundle, Craftable, Inventory, Item, LogMsg, Player, Time, ONE_MINUTE};
+use engine_shared::utils::custom_map::CustomMap;
+use serde::{Deserialize, Serialize};
+
+// Time it takes to craft one item per level of its crafting depth.
+pub const CRAFTING_TIME_PER_DEPTH: Time = ONE_MINUTE;
+pub const MAX_CRAFTING_QUEUE: usize = 10;
+pub const MAX_CRAFTING_QTY: u64 = 10000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Hash)]
+pub struct CraftingJob {
+    pub item: Item,
+    pub qty: u64,
+    pub time_left: Time,
+    // Ingredients that were in the inventory are reserved when the job is queued. The rest
+    // is crafted by the jobs before it and taken from the inventory when the job starts.
+    pub reserved: Bundle<Item>,
+    pub started: bool,
+}
+
+impl CraftingJob {
+    pub fn duration(item: Item, qty: u64) -> Time {
+        item.crafting_depth()
+            .max(1)
+            .saturating_mul(qty)
+            .saturating_mul(CRAFTING_TIME_PER_DEPTH)
+    }
+
+    fn ingredients(&self) -> Bundle<Item> {
+        self.item
+            .requires()
+            .map(|(_, requires)| requires.mul(self.qty))
+            .unwrap_or_default()
+    }
+
+    // Ingredients that the jobs before this one have to craft.
+    fn unreserved(&self) -> Bundle<Item> {
+        self.ingredients()
+            .iter()
+            .map(|(item, n)| {
+                let reserved = self.reserved.get(item).copied().unwrap_or(0);
+                (*item, n.saturating_sub(reserved))
+            })
+            .collect()
+    }
+}
+
+// Items that are available for new jobs, the stock without what the queued jobs still need
+// and the items that the queued jobs will craft but no queued job uses.
+struct CraftingBudget {
+    stock: Bundle<Item>,
+    crafted: CustomMap<Item, u64>,
+}
+
+impl CraftingBudget {
+    fn new(player: &Player) -> Self {
+        let mut budget = CraftingBudget {
+            stock: player.inventory.items.clone(),
+            crafted: CustomMap::new(),
+        };
+        for job in &player.crafting_queue {
+            if !job.started {
+                for (item, n) in job.unreserved().iter() {
+                    let from_queue = budget.take_crafted(*item, *n);
+                    let from_stock = budget
+                        .stock
+                        .get(item)
+                        .copied()
+                        .unwrap_or(0)
+                        .min(n - from_queue);
+                    budget
+                        .stock
+                        .remove_checked(Bundle::new().add(*item, from_stock));
+                }
+            }
+            *budget.crafted.entry(job.item).or_default() += job.qty;
+        }
+        budget
+    }
+
+    fn take_crafted(&mut self, item: Item, n: u64) -> u64 {
+        let crafted = self.crafted.entry(item).or_default();
+        let taken = (*crafted).min(n);
+        *crafted -= taken;
+        taken
+    }
+
+    // Takes the ingredients from the stock first and from the queued jobs otherwise. Returns
+    // the job with the ingredients it has to reserve in the inventory.
+    fn plan_job(&mut self, item: Item, qty: u64) -> Option<CraftingJob> {
+        let (_, requires) = item.requires()?;
+        let mut reserved = Bundle::new();
+        for (ingredient, n) in requires.mul(qty).iter() {
+            let from_stock = self.stock.get(ingredient).copied().unwrap_or(0).min(*n);
+            if self.crafted.get(ingredient).copied().unwrap_or(0) < n - from_stock {
+                return None;
+            }
+            self.take_crafted(*ingredient, n - from_stock);
+            self.stock
+                .remove_checked(Bundle::new().add(*ingredient, from_stock));
+            reserved = reserved.add(*ingredient, from_stock);
+        }
+        *self.crafted.entry(item).or_default() += qty;
+
+        Some(CraftingJob {
+            item,
+            qty,
+            time_left: CraftingJob::duration(item, qty),
+            reserved,
+            started: false,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CraftingPlan {
+    // Items that have to be crafted, ingredients always come before the items that need them.
+    pub steps: Vec<(Item, u64)>,
+    // Items that can't be crafted and are not in the inventory.
+    pub missing: Bundle<Item>,
+}
+
+impl Inventory {
+    // Expands the full recipe tree of the target items. The target items are always crafted,
+    // their ingredients are taken from the inventory where possible.
+    pub fn plan_crafting(&self, target: &Bundle<Item>) -> CraftingPlan {
+        let mut available = self.items.clone();
+        let mut crafts = CustomMap::<Item, u64>::new();
+        let mut missing = CustomMap::<Item, u64>::new();
+
+        for (item, qty) in target.iter() {
+            if let Some((_, requires)) = item.requires() {
+                *crafts.entry(*item).or_default() += *qty;
+                for (ingredient, n) in requires.iter() {
+                    plan_item(
+                        *ingredient,
+                        n.saturating_mul(*qty),
+                        &mut available,
+                        &mut crafts,
+                        &mut missing,
+                    );
+                }
+            } else {
+                *missing.entry(*item).or_default() += *qty;
+            }
+        }
+
+        let mut steps = crafts.into_iter().collect::<Vec<_>>();
+        // An item is always deeper in the recipe tree than its ingredients.
+        steps.sort_by_key(|(item, _)| item.crafting_depth());
+
+        CraftingPlan {
+            steps,
+            missing: missing.into_iter().collect(),
+        }
+    }
+}
+
+fn plan_item(
+    item: Item,
+    qty: u64,
+    available: &mut Bundle<Item>,
+    crafts: &mut CustomMap<Item, u64>,
+    missing: &mut CustomMap<Item, u64>,
+) {
+    let in_stock = available.get(&item).copied().unwrap_or(0).min(qty);
+    if in_stock > 0 {
+        available.remove_checked(Bundle::new().add(item, in_stock));
+    }
+
+    let to_craft = qty - in_stock;
+    if to_craft == 0 {
+        return;
+    }
+
+    if let Some((_, requires)) = item.requires() {
+        let entry = crafts.entry(item).or_default();
+        *entry = entry.saturating_add(to_craft);
+        for (ingredient, n) in requires.iter() {
+            plan_item(
+                *ingredient,
+                n.saturating_mul(to_craft),
+                available,
+                crafts,
+                missing,
+            );
+        }
+    } else {
+        let entry = missing.entry(item).or_default();
+        *entry = entry.saturating_add(to_craft);
+    }
+}
+
+impl Player {
+    // Queues all steps of the crafting plan or none of them, if anything is missing, not yet
+    // unlocked or the queue is too short. The ingredients of the whole plan are reserved at once.
+    pub fn queue_crafting_plan(&mut self, target: &Bundle<Item>) -> bool {
+        if target.values().any(|qty| *qty > MAX_CRAFTING_QTY) {
+            return false;
+        }
+        let plan = self.inventory.plan_crafting(target);
+
+        if plan.steps.is_empty()
+            || !plan.missing.is_empty()
+            || self.crafting_queue.len() + plan.steps.len() > MAX_CRAFTING_QUEUE
+            || !plan
+                .steps
+                .iter()
+                .all(|(item, qty)| self.can_craft(*item, *qty))
+        {
+            return false;
+        }
+
+        let mut budget = CraftingBudget::new(self);
+        let Some(jobs) = plan
+            .steps
+            .into_iter()
+            .map(|(item, qty)| budget.plan_job(item, qty))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return false;
+        };
+
+        for job in jobs {
+            self.reserve_job(job);
+        }
+        true
+    }
+
+    // Queues the job if its ingredients are in the inventory or crafted by the queued jobs,
+    // without using anything that the queued jobs need.
+    pub fn queue_craft(&mut self, item: Item, qty: u64) -> bool {
+        if self.crafting_queue.len() >= MAX_CRAFTING_QUEUE || !self.can_craft(item, qty) {
+            return false;
+        }
+        let Some(job) = CraftingBudget::new(self).plan_job(item, qty) else {
+            return false;
+        };
+
+        self.reserve_job(job);
+        true
+    }
+
+    fn can_craft(&self, item: Item, qty: u64) -> bool {
+        (1..=MAX_CRAFTING_QTY).contains(&qty)
+            && item
+                .requires()
+                .map(|(level, _)| self.base.curr_level >= level)
+                .unwrap_or(false)
+    }
+
+    fn reserve_job(&mut self, job: CraftingJob) {
+        let reserved = self.inventory.items.remove_checked(job.reserved.clone());
+        debug_assert!(reserved);
+        self.crafting_queue.push_back(job);
+    }
+
+    // Removes the job from the queue and gives back the reserved ingredients.
+    pub fn cancel_craft(&mut self, idx: usize, time: Time) -> bool {
+        let Some(job) = self.crafting_queue.remove(idx) else {
+            return false;
+        };
+        if job.started {
+            self.inventory.add(job.ingredients(), time);
+        } else {
+            self.inventory.add(job.reserved, time);
+        }
+        true
+    }
+
+    // Called every tick. Only the first job in the queue makes progress. When it starts, it
+    // takes the ingredients crafted by the jobs before it, if they are gone, the job fails.
+    pub fn progress_crafting(&mut self, time: Time, is_premium: bool) {
+        let Some(job) = self.crafting_queue.front_mut() else {
+            return;
+        };
+
+        if !job.started {
+            if !self.inventory.items.remove_checked(job.unreserved()) {
+                let job = self.crafting_queue.pop_front().unwrap();
+                self.log.add(
+                    time,
+                    LogMsg::CraftingFailed(Bundle::new().add(job.item, job.qty)),
+                );
+                self.inventory.add(job.reserved, time);
+                return;
+            }
+            job.started = true;
+        }
+
+        job.time_left = job.time_left.saturating_sub(1);
+
+        if job.time_left == 0 {
+            let job = self.crafting_queue.pop_front().unwrap();
+            let crafted = Bundle::new().add(job.item, job.qty);
+            self.log.add(time, LogMsg::Crafted(crafted.clone()));
+            self.add_items(crafted, time, is_premium);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::crafter;
+
+    fn count(player: &Player, item: Item) -> u64 {
+        player.inventory.items.get(&item).copied().unwrap_or(0)
+    }
+
+    fn finish_crafting(player: &mut Player) {
+        while !player.crafting_queue.is_empty() {
+            player.progress_crafting(0, false);
+        }
+    }
+
+    #[test]
+    fn queued_jobs_reserve_their_ingredients() {
+        let mut player = crafter(Bundle::new().add(Item::Wood, 4));
+        assert!(player.queue_craft(Item::Coal, 1));
+        assert_eq!(count(&player, Item::Wood), 1);
+        assert!(!player.queue_craft(Item::Coal, 1));
+
+        assert!(player.cancel_craft(0, 0));
+        assert_eq!(count(&player, Item::Wood), 4);
+        assert!(player.crafting_queue.is_empty());
+    }
+
+    #[test]
+    fn queued_jobs_use_what_the_jobs_before_them_craft() {
+        let mut player = crafter(Bundle::new().add(Item::Wood, 6).add(Item::IronOre, 2));
+        assert!(!player.queue_craft(Item::Iron, 2));
+        assert!(player.queue_craft(Item::Coal, 2));
+        assert!(player.queue_craft(Item::Iron, 2));
+        assert!(!player.queue_craft(Item::Iron, 1));
+
+        finish_crafting(&mut player);
+        assert_eq!(count(&player, Item::Iron), 2);
+        assert_eq!(count(&player, Item::Coal), 0);
+        assert_eq!(count(&player, Item::IronOre), 0);
+    }
+
+    #[test]
+    fn jobs_fail_and_refund_when_their_ingredients_are_gone() {
+        let mut player = crafter(Bundle::new().add(Item::Wood, 3).add(Item::IronOre, 1));
+        assert!(player.queue_craft(Item::Coal, 1));
+        assert!(player.queue_craft(Item::Iron, 1));
+
+        while player.crafting_queue.len() > 1 {
+            player.progress_crafting(0, false);
+        }
+        player.inventory.items = Bundle::new();
+        player.progress_crafting(0, false);
+
+        assert!(player.crafting_queue.is_empty());
+        assert_eq!(count(&player, Item::IronOre), 1);
+        assert_eq!(count(&player, Item::Iron), 0);
+        assert!(matches!(
+            player.log.msgs.back(),
+            Some((_, LogMsg::CraftingFailed(_)))
+        ));
+    }
+
+    #[test]
+    fn huge_crafting_jobs_are_rejected() {
+        let mut player = crafter(Bundle::new().add(Item::Wood, 3));
+        assert_eq!(CraftingJob::duration(Item::Coal, u64::MAX), u64::MAX);
+        assert!(!player.queue_craft(Item::Coal, u64::MAX));
+        assert!(!player.queue_crafting_plan(&Bundle::new().add(Item::Coal, u64::MAX)));
+        assert_eq!(count(&player, Item::Wood), 3);
+    }
+
+    #[test]
+    fn crafting_plans_are_reserved_at_once_or_rejected() {
+        let target = Bundle::new().add(Item::Iron, 2);
+        let mut player = crafter(Bundle::new().add(Item::Wood, 6).add(Item::IronOre, 2));
+        assert!(player.queue_crafting_plan(&target));
+        assert_eq!(player.crafting_queue.len(), 2);
+        assert!(player.inventory.items.values().all(|n| *n == 0));
+
+        finish_crafting(&mut player);
+        assert_eq!(count(&player, Item::Iron), 2);
+
+        let mut player = crafter(Bundle::new().add(Item::Wood, 6).add(Item::IronOre, 2));
+        assert!(player.queue_craft(Item::Coal, 1));
+        assert!(!player.queue_crafting_plan(&target));
+        assert_eq!(player.crafting_queue.len(), 1);
+        assert_eq!(count(&player, Item::Wood), 3);
+
+        // The coal in the inventory is needed by the queued iron job.
+        let mut player = crafter(Bundle::new().add(Item::Wood, 3).add(Item::IronOre, 2));
+        assert!(player.queue_craft(Item::Coal, 1));
+        assert!(player.queue_craft(Item::Iron, 1));
+        while player.crafting_queue.len() > 1 {
+            player.progress_crafting(0, false);
+        }
+        assert_eq!(count(&player, Item::Coal), 1);
+        assert!(!player.queue_crafting_plan(&Bundle::new().add(Item::Iron, 1)));
+        assert_eq!(count(&player, Item::IronOre), 1);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{Dwarf, Occupation, Player, Time, APPRENTICE_EFFECTIVENESS_DIVIDER, ONE_DAY};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+// Experience in ticks worked that is needed to reach the levels 1 to 5.
+pub const EXPERIENCE_LEVELS: [Time; 5] = [ONE_DAY, ONE_DAY * 3, ONE_DAY * 7, ONE_DAY * 14, ONE_DAY * 30];
+pub const EXPERIENCE_BONUS_PERCENT: u64 = 5;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, Display)]
+pub enum Perk {
+    Seasoned,
+    Efficient,
+    Master,
+}
+
+impl Perk {
+    pub fn level(self) -> u64 {
+        match self {
+            Perk::Seasoned => 2,
+            Perk::Efficient => 4,
+            Perk::Master => 5,
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Perk::Seasoned => "Gets injured only half as often.",
+            Perk::Efficient => "Loses less health while working.",
+            Perk::Master => "Passes on experience to the apprentice twice as fast.",
+        }
+    }
+
+    pub fn all() -> [Perk; 3] {
+        [Perk::Seasoned, Perk::Efficient, Perk::Master]
+    }
+}
+
+impl Dwarf {
+    pub fn experience(&self, occupation: Occupation) -> Time {
+        self.experience.get(&occupation).copied().unwrap_or(0)
+    }
+
+    pub fn experience_level(&self, occupation: Occupation) -> u64 {
+        let experience = self.experience(occupation);
+        EXPERIENCE_LEVELS
+            .iter()
+            .take_while(|threshold| experience >= **threshold)
+            .count() as u64
+    }
+
+    // Experience needed for the next level, `None` at the maximum level.
+    pub fn next_experience_level(&self, occupation: Occupation) -> Option<Time> {
+        EXPERIENCE_LEVELS
+            .get(self.experience_level(occupation) as usize)
+            .copied()
+    }
+
+    pub fn experience_percent(&self, occupation: Occupation) -> u64 {
+        100 + self.experience_level(occupation) * EXPERIENCE_BONUS_PERCENT
+    }
+
+    pub fn has_perk(&self, occupation: Occupation, perk: Perk) -> bool {
+        occupation != Occupation::Idling && self.experience_level(occupation) >= perk.level()
+    }
+}
+
+impl Player {
+    // Working dwarfs gain experience in their occupation, apprentices learn from the
+    // experience of their mentor.
+    pub fn gain_experience(&mut self, rng: &mut impl Rng) {
+        let mut apprentices = Vec::new();
+
+        for dwarf in self.dwarfs.values_mut() {
+            let occupation = dwarf.actual_occupation();
+            if dwarf.dead() || !dwarf.is_adult() || occupation == Occupation::Idling {
+                continue;
+            }
+            *dwarf.experience.entry(occupation).or_default() += 1;
+
+            if let Some(apprentice_id) = dwarf.apprentice {
+                let chance = if dwarf.has_perk(occupation, Perk::Master) {
+                    2
+                } else {
+                    1
+                };
+                if dwarf.experience(occupation) > 0
+                    && rng.gen_ratio(chance, APPRENTICE_EFFECTIVENESS_DIVIDER as u32)
+                {
+                    apprentices.push((apprentice_id, occupation, dwarf.experience(occupation)));
+                }
+            }
+        }
+
+        for (apprentice_id, occupation, mentor_experience) in apprentices {
+            if let Some(apprentice) = self.dwarfs.get_mut(&apprentice_id) {
+                // Apprentices can't get more experienced than their mentor.
+                let experience = apprentice.experience.entry(occupation).or_default();
+                if *experience < mentor_experience {
+                    *experience += 1;
+                }
+            }
+        }
+    }
+}
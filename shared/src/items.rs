@@ -144,6 +144,9 @@ pub enum Item {
     Fairy,
     Dog,
     Wildcat,
+    Herbs,
+    Bandage,
+    Medicine,
 }
 
 impl Craftable for Item {
@@ -181,6 +184,14 @@ impl Craftable for Item {
             )),
             Item::Bow => Some((7, Bundle::new().add(Item::Wood, 3).add(Item::String, 1))),
             Item::Fabric => Some((8, Bundle::new().add(Item::String, 3))),
+            Item::Bandage => Some((5, Bundle::new().add(Item::Hemp, 3).add(Item::Herbs, 2))),
+            Item::Medicine => Some((
+                10,
+                Bundle::new()
+                    .add(Item::Herbs, 5)
+                    .add(Item::Sulfur, 1)
+                    .add(Item::Milk, 1),
+            )),
             Item::Backpack => Some((9, Bundle::new().add(Item::String, 2).add(Item::Leather, 5))),
             Item::Bag => Some((10, Bundle::new().add(Item::String, 1).add(Item::Fabric, 2))),
             Item::LeatherArmor => {
@@ -775,6 +786,10 @@ impl Item {
             (Item::MiningGear, Occupation::Mining) => 10,
             (Item::LoggingGear, Occupation::Logging) => 10,
             (Item::Fairy, Occupation::Exploring) => 10,
+            (Item::Fairy, Occupation::Healing) => 8,
+            (Item::Bag, Occupation::Healing) => 5,
+            (Item::Gloves, Occupation::Healing) => 4,
+            (Item::Cat, Occupation::Healing) => 3,
             _ => 0,
         }
     }
@@ -857,6 +872,9 @@ impl Item {
                 Item::Parrot => Some(ItemProbability {
                     expected_ticks_per_drop: ONE_DAY * 7,
                 }),
+                Item::Herbs => Some(ItemProbability {
+                    expected_ticks_per_drop: ONE_MINUTE * 10,
+                }),
                 _ => None,
             },
             Occupation::Fishing => match self {
@@ -961,6 +979,12 @@ impl Item {
                 }),
                 _ => None,
             },
+            Occupation::Healing => match self {
+                Item::Herbs => Some(ItemProbability {
+                    expected_ticks_per_drop: ONE_MINUTE * 5,
+                }),
+                _ => None,
+            },
             Occupation::Idling => None,
         }
     }
@@ -1,10 +1,34 @@
+mod ailments;
+mod automation;
+mod battles;
+mod bots;
+mod breeding;
+mod crafting;
+mod experience;
 mod items;
-
+mod optimize;
+mod production;
+mod traits;
+mod treasury;
+mod tribes;
+mod world;
+
+pub use ailments::*;
+pub use automation::*;
+pub use battles::*;
+pub use bots::*;
+pub use crafting::*;
+pub use experience::*;
 pub use items::*;
+pub use production::*;
+pub use traits::*;
+pub use treasury::*;
+pub use tribes::*;
+pub use world::*;
 
 use engine_shared::{
     utils::custom_map::{CustomMap, CustomSet},
-    Event,
+    Event, GameId,
 };
 use enum_iterator::Sequence;
 use rand::{
@@ -15,31 +39,25 @@ use serde::{Deserialize, Serialize};
 use std::{collections::VecDeque, hash::Hash, ops::Deref};
 use strum::Display;
 
-#[cfg(not(debug_assertions))]
-pub const SPEED: u64 = 1;
-#[cfg(debug_assertions)]
-pub const SPEED: u64 = 20;
 pub const ONE_MINUTE: u64 = 60;
 pub const ONE_HOUR: u64 = ONE_MINUTE * 60;
 pub const ONE_DAY: u64 = ONE_HOUR * 24;
 pub const MAX_HEALTH: Health = ONE_DAY * 3;
 pub const LOOT_CRATE_COST: Money = 1000;
 pub const FREE_LOOT_CRATE: u64 = ONE_DAY;
-pub const WINNER_NUM_PREMIUM_DAYS: i64 = 30;
-pub const WINNER_TRIBE_NUM_PREMIUM_DAYS: i64 = 7;
 pub const FEMALE_PROBABILITY: f64 = 1.0 / 3.0;
-pub const MAX_LEVEL: u64 = 100;
 pub const AGE_SECONDS_PER_TICK: u64 = 365 * 24;
 pub const ADULT_AGE: u64 = 20;
 pub const DEATH_AGE: u64 = 200;
-pub const IMPROVEMENT_DURATION: u32 = ONE_DAY as u32 * 5;
 pub const APPRENTICE_EFFECTIVENESS_DIVIDER: u64 = 10;
 pub const MAX_EFFECTIVENESS: u64 = 6000;
 pub const MIN_MAX_DWARF_DIFFERENCE: u64 = 3;
 pub const TRADE_MONEY_MULTIPLIER: u64 = 10;
+pub const AUCTION_LISTING_FEE_DIVIDER: u64 = 20;
+pub const AUCTION_MIN_DURATION: Time = ONE_HOUR;
+pub const AUCTION_MAX_DURATION: Time = ONE_DAY;
 pub const DISMANTLING_DIVIDER: u64 = 2;
 pub const NEW_PLAYER_DIVIDER: u64 = 8;
-pub const JOIN_TRIBE_LEVEL: u64 = 16;
 
 pub type Money = u64;
 pub type Food = u64;
@@ -53,7 +71,7 @@ pub type TradeId = u64;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub enum Popup {
-    NewDwarf(Dwarf),
+    NewDwarf(Box<Dwarf>),
     NewItems(Bundle<Item>),
 }
 
@@ -290,15 +308,31 @@ pub struct State {
     pub event: Option<WorldEvent>,
     pub trade_deals: CustomMap<TradeId, TradeDeal>,
     pub tribes: CustomMap<TribeId, Tribe>,
+    #[serde(default)]
+    pub bots: CustomMap<UserId, BotKind>,
+    #[serde(default)]
+    pub battles: CustomMap<BattleId, Battle>,
+    #[serde(default)]
+    pub next_battle_id: BattleId,
+    #[serde(default)]
+    pub config: WorldConfig,
+    // Set by the server whenever the world is loaded.
+    #[serde(default)]
+    pub game_id: GameId,
 }
 
 impl Default for State {
     fn default() -> Self {
-        let mut tribes = CustomMap::default();
-        tribes.insert(0, Tribe::default());
-        tribes.insert(1, Tribe::default());
-        tribes.insert(2, Tribe::default());
+        State::new(WorldConfig::default())
+    }
+}
 
+impl State {
+    pub fn new(config: WorldConfig) -> Self {
+        let mut tribes = CustomMap::default();
+        for tribe_id in 0..config.num_tribes.max(1) {
+            tribes.insert(tribe_id, Tribe::default());
+        }
 
         Self {
             players: CustomMap::default(),
@@ -312,11 +346,14 @@ impl Default for State {
             event: None,
             trade_deals: CustomMap::default(),
             tribes,
+            bots: CustomMap::default(),
+            battles: CustomMap::default(),
+            next_battle_id: 0,
+            config,
+            game_id: 0,
         }
     }
-}
 
-impl State {
     fn add_to_food_storage(player: &mut Player, item: Item, qty: u64) {
         if let Some(food) = item.nutritional_value() {
             if player
@@ -343,17 +380,6 @@ impl State {
     }
     */
 
-    fn craft(player: &mut Player, item: Item, qty: u64) {
-        if let Some((level, requires)) = item.requires() {
-            if player.base.curr_level >= level && player.inventory.items.remove_checked(requires.mul(qty)) {
-                player
-                    .inventory
-                    .items
-                    .add_checked(Bundle::new().add(item, qty));
-            }
-        }
-    }
-
     fn dismantle(player: &mut Player, item: Item, qty: u64) {
         if let Some((_level, requires)) = item.requires() {
             if matches!(
@@ -371,28 +397,169 @@ impl State {
         }
     }
 
+    pub fn add_bot(&mut self, bot_kind: BotKind) -> UserId {
+        // Bots use negative ids so they never collide with registered users.
+        let lowest_id = self
+            .players
+            .keys()
+            .chain(self.bots.keys())
+            .map(|user_id| user_id.0)
+            .min()
+            .unwrap_or(0);
+        let user_id = UserId(lowest_id.min(0) - 1);
+        self.bots.insert(user_id, bot_kind);
+        user_id
+    }
+
+    // Removes the bot from the world, the quests, battles and trades continue without it.
+    fn retire_bot(&mut self, user_id: UserId) {
+        self.bots.swap_remove(&user_id);
+        if self.king == Some(user_id) {
+            self.king = None;
+        }
+        if let Some(player) = self.players.swap_remove(&user_id) {
+            if let Some(tribe) = player.tribe.and_then(|tribe_id| self.tribes.get_mut(&tribe_id)) {
+                tribe.remove_member(user_id);
+            }
+        }
+        for quest in self.quests.values_mut() {
+            quest.contestants.swap_remove(&user_id);
+        }
+        for battle in self.battles.values_mut() {
+            battle.fighters.swap_remove(&user_id);
+        }
+        for trade_deal in self.trade_deals.values_mut() {
+            trade_deal.withdraw(user_id, &mut self.players, self.time);
+        }
+    }
+
+    pub fn is_bot(&self, user_id: &UserId) -> bool {
+        self.bots.contains_key(user_id)
+    }
+
+    fn run_bots(&mut self, rng: &mut impl Rng, user_data: &CustomMap<UserId, UserData>) {
+        let active_players = self
+            .players
+            .iter()
+            .filter(|(user_id, player)| {
+                !self.is_bot(user_id) && player.is_active(self.time, self.config.speed)
+            })
+            .count();
+        let bots_needed = self.config.bot_fill_players.saturating_sub(active_players);
+
+        if self.bots.len() < bots_needed {
+            let bot_kind = enum_iterator::all::<BotKind>()
+                .cycle()
+                .nth(self.bots.len())
+                .unwrap();
+            self.add_bot(bot_kind);
+        } else if self.bots.len() > bots_needed {
+            // Bots leave one by one as real players arrive.
+            if let Some(user_id) = self.bots.keys().last().copied() {
+                self.retire_bot(user_id);
+            }
+        }
+
+        let bots = self
+            .bots
+            .iter()
+            .map(|(user_id, bot_kind)| (*user_id, *bot_kind))
+            .collect::<Vec<_>>();
+
+        for (user_id, bot_kind) in bots {
+            if !self.players.contains_key(&user_id) {
+                engine_shared::State::update(
+                    self,
+                    rng,
+                    Event::ClientEvent(ClientEvent::Init, user_id),
+                    user_data,
+                );
+            }
+
+            let events = self
+                .players
+                .get(&user_id)
+                .map(|player| bot_kind.events(user_id, player, self))
+                .unwrap_or_default();
+
+            for event in events {
+                engine_shared::State::update(
+                    self,
+                    rng,
+                    Event::ClientEvent(event, user_id),
+                    user_data,
+                );
+            }
+        }
+    }
+
     pub fn winner(&self) -> Option<UserId> {
         for (user_id, player) in &self.players {
-            if player.base.curr_level == 100 && self.king == Some(*user_id) {
+            if player.base.curr_level == self.config.max_level
+                && self.king == Some(*user_id)
+                && !self.is_bot(user_id)
+            {
                 return Some(*user_id);
             }
         }
         None
     }
 
+    pub fn summary(&self) -> WorldSummary {
+        let mut players = self
+            .players
+            .iter()
+            .filter(|(user_id, _)| !self.is_bot(user_id))
+            .map(|(user_id, player)| PlayerSummary {
+                user_id: *user_id,
+                level: player.base.curr_level,
+                dwarfs: player.dwarfs.len(),
+                money: player.money,
+                tribe: player.tribe,
+                quests_won: player.quests_won,
+            })
+            .collect::<Vec<_>>();
+        players.sort_by_key(|player| std::cmp::Reverse((player.level, player.quests_won, player.money)));
+
+        let tribes = self
+            .tribes
+            .iter()
+            .map(|(tribe_id, tribe)| TribeSummary {
+                tribe_id: *tribe_id,
+                members: players
+                    .iter()
+                    .filter(|player| player.tribe == Some(*tribe_id))
+                    .count(),
+                score: tribe.territories.values().sum(),
+                territories: tribe.territories.clone(),
+                name: tribe.name.clone(),
+            })
+            .collect();
+
+        WorldSummary {
+            time: self.time,
+            winner: self.winner(),
+            king: self.king,
+            players,
+            tribes,
+            config: self.config.clone(),
+        }
+    }
+
     pub fn rewarded_premium_days(&self) -> Vec<(UserId, i64)> {
         let winner_id = self.winner().unwrap();
         let winner_tribe = self.players.get(&winner_id).and_then(|p|p.tribe);
 
         self.players
             .iter()
+            .filter(|(user_id, _)| !self.is_bot(user_id))
             .flat_map(|(&user_id, player)| {
                 if user_id == winner_id {
-                    return Some((user_id, WINNER_NUM_PREMIUM_DAYS));
+                    Some((user_id, self.config.winner_num_premium_days))
                 } else if player.tribe == winner_tribe {
-                    return Some((user_id, WINNER_TRIBE_NUM_PREMIUM_DAYS));
+                    Some((user_id, self.config.winner_tribe_num_premium_days))
                 } else {
-                    return None;
+                    None
                 }
             })
             .collect()
@@ -405,9 +572,8 @@ impl engine_shared::State for State {
     type UserId = UserId;
     type UserData = UserData;
 
-    const DURATION_PER_TICK: std::time::Duration = std::time::Duration::from_millis(1000 / SPEED);
-
-   
+    // Faster worlds run several ticks per second, see `WorldConfig::speed`.
+    const DURATION_PER_TICK: std::time::Duration = std::time::Duration::from_millis(1000);
 
     fn closed(&self) -> bool {
         self.winner().is_some()
@@ -419,7 +585,27 @@ impl engine_shared::State for State {
         event: Event<Self>,
         user_data: &CustomMap<UserId, UserData>,
     ) {
-        let update_result = move || -> Option<()> {
+        if matches!(event, Event::ServerEvent(ServerEvent::Tick)) {
+            for _ in 0..self.config.speed.max(1) {
+                self.update_once(rng, Event::ServerEvent(ServerEvent::Tick), user_data);
+            }
+        } else {
+            self.update_once(rng, event, user_data);
+        }
+    }
+}
+
+impl State {
+    // A single tick or client event.
+    fn update_once(
+        &mut self,
+        rng: &mut impl Rng,
+        event: Event<Self>,
+        user_data: &CustomMap<UserId, UserData>,
+    ) {
+        let is_tick = matches!(event, Event::ServerEvent(ServerEvent::Tick));
+
+        let update_result = (|| -> Option<()> {
             match event {
                 Event::ClientEvent(event, user_id) => {
                     if !self.players.contains_key(&user_id) {
@@ -448,9 +634,74 @@ impl engine_shared::State for State {
                             }
 
                         }
+                        ClientEvent::DeclareAttack(territory) => {
+                            let tribe_id = player.tribe?;
+                            let defender = territory.controller(&self.tribes);
+
+                            if defender != Some(tribe_id)
+                                && player.tribe_points >= DECLARE_ATTACK_COST
+                                && !self
+                                    .battles
+                                    .values()
+                                    .any(|battle| battle.territory == territory)
+                            {
+                                player.tribe_points -= DECLARE_ATTACK_COST;
+                                self.battles.insert(
+                                    self.next_battle_id,
+                                    Battle::new(territory, tribe_id, defender),
+                                );
+                                self.next_battle_id += 1;
+                            }
+                        }
+                        ClientEvent::CommitToBattle(battle_id, dwarf_id, commit) => {
+                            let battle = self.battles.get_mut(&battle_id)?;
+                            battle.commit(battle_id, user_id, player, dwarf_id, commit)?;
+                        }
+                        ClientEvent::SetTribeName(name) => {
+                            self.set_tribe_name(user_id, name)?;
+                        }
+                        ClientEvent::SetTribeMotd(motd) => {
+                            self.set_tribe_motd(user_id, motd)?;
+                        }
+                        ClientEvent::SetTribeRole(member_id, role) => {
+                            self.set_tribe_role(user_id, member_id, role)?;
+                        }
+                        ClientEvent::KickTribeMember(member_id) => {
+                            self.kick_tribe_member(user_id, member_id)?;
+                        }
+                        ClientEvent::VoteChief(candidate) => {
+                            self.vote_chief(user_id, candidate)?;
+                        }
+                        ClientEvent::VoteTerritory(territory) => {
+                            self.vote_territory(user_id, territory)?;
+                        }
+                        ClientEvent::DonateTribePoints(points) => {
+                            self.donate_tribe_points(user_id, points)?;
+                        }
+                        ClientEvent::SpendSharedTribePoints(territory, points) => {
+                            self.spend_shared_tribe_points(user_id, territory, points)?;
+                        }
+                        ClientEvent::DepositTribeMoney(money) => {
+                            self.deposit_tribe_money(user_id, money)?;
+                        }
+                        ClientEvent::DepositTribeItems(item, qty) => {
+                            self.deposit_tribe_items(user_id, item, qty)?;
+                        }
+                        ClientEvent::WithdrawTribeMoney(recipient_id, money) => {
+                            self.withdraw_tribe_money(user_id, recipient_id, money)?;
+                        }
+                        ClientEvent::WithdrawTribeItems(recipient_id, item, qty) => {
+                            self.withdraw_tribe_items(user_id, recipient_id, item, qty)?;
+                        }
+                        ClientEvent::SetTribeTax(percent) => {
+                            self.set_tribe_tax(user_id, percent)?;
+                        }
+                        ClientEvent::SwitchTribe(tribe_id) => {
+                            self.switch_tribe(user_id, tribe_id)?;
+                        }
                         ClientEvent::Bid(trade_id) => {
                             if let Some(trade) = self.trade_deals.get_mut(&trade_id) {
-                                trade.bid(&mut self.players, user_id, self.time)?;
+                                trade.bid(&mut self.players, user_id, self.time, self.config.speed)?;
                             }
                         }
                         ClientEvent::SetMentor(apprentice_id, mentor_id) => {
@@ -466,6 +717,14 @@ impl engine_shared::State for State {
                             }
                             player.set_mentor(apprentice_id, mentor_id)?;
                         }
+                        ClientEvent::SetPartner(dwarf_id, partner_id) => {
+                            if let Some(partner_id) = partner_id {
+                                if !player.can_be_partners(dwarf_id, partner_id) {
+                                    return None;
+                                }
+                            }
+                            player.set_partner(dwarf_id, partner_id)?;
+                        }
                         ClientEvent::ReleaseDwarf(dwarf_id) => {
                             let dwarf = player.dwarfs.get_mut(&dwarf_id)?;
                             dwarf.released = true;
@@ -484,165 +743,10 @@ impl engine_shared::State for State {
                             }
                         }
                         ClientEvent::Optimize(to_optimize_dwarf_id) => {
-                            // Reassign occupations.
-
-                            if to_optimize_dwarf_id.is_none() {
-                                player.set_manager();
-
-                                let mut occupations_to_fill = player.manager.clone();
-                                occupations_to_fill.swap_remove(&Occupation::Idling);
-
-                                for dwarf in player.dwarfs.values_mut() {
-                                    if dwarf.can_be_managed() {
-                                        dwarf.occupation = Occupation::Idling;
-                                    }
-                                }
-
-                                loop {
-                                    occupations_to_fill.retain(|_, num| *num > 0);
-
-                                    if occupations_to_fill.is_empty() {
-                                        break;
-                                    }
-
-                                    let mut best_dwarf_effectiveness = 0;
-                                    let mut best_dwarf_occupation = None;
-                                    let mut best_dwarf_id = None;
-                                    for (dwarf_id, dwarf) in &player.dwarfs {
-                                        if dwarf.occupation == Occupation::Idling
-                                            && dwarf.can_be_managed()
-                                        {
-                                            for (occupation, _num) in &occupations_to_fill {
-                                                let effectiveness =
-                                                    dwarf.stats.cross(occupation.requires_stats());
-                                                if effectiveness >= best_dwarf_effectiveness {
-                                                    best_dwarf_effectiveness = effectiveness;
-                                                    best_dwarf_id = Some(*dwarf_id);
-                                                    best_dwarf_occupation = Some(*occupation);
-                                                }
-                                            }
-                                        }
-                                    }
-
-                                    if let Some(best_dwarf_id) = best_dwarf_id {
-                                        let best_dwarf = player.dwarfs.get_mut(&best_dwarf_id)?;
-                                        let best_dwarf_occupation = best_dwarf_occupation
-                                            .expect("occupation known if id is known");
-                                        best_dwarf.change_occupation(best_dwarf_occupation);
-                                        *occupations_to_fill
-                                            .get_mut(&best_dwarf_occupation)
-                                            .expect("occupation is always one that is to fill") -=
-                                            1;
-                                    } else {
-                                        break;
-                                    }
-                                }
-
-                                debug_assert!(occupations_to_fill.is_empty());
-                            }
-
-                            // Reassign equipment.
-
-                            let dwarf_ids = if let Some(dwarf_id) = to_optimize_dwarf_id {
-                                vec![dwarf_id]
-                            } else {
-                                player.dwarfs.keys().cloned().collect()
-                            };
-
-                            for dwarf_id in &dwarf_ids {
-                                let dwarf = player.dwarfs.get_mut(dwarf_id)?;
-                                if dwarf.can_be_managed() || to_optimize_dwarf_id.is_some() {
-                                    for (_, item) in dwarf.equipment.drain(..) {
-                                        player
-                                            .inventory
-                                            .items
-                                            .add_checked(Bundle::new().add(item, 1));
-                                    }
-                                }
-                            }
-
-                            loop {
-                                let mut best_dwarf_effectiveness = 0;
-                                let mut best_dwarf_id = None;
-                                let mut best_dwarf_item = None;
-                                for dwarf_id in &dwarf_ids {
-                                    let dwarf = player.dwarfs.get(dwarf_id)?;
-                                    if dwarf.can_be_managed() || to_optimize_dwarf_id.is_some() {
-                                        let occupation_to_optimize =
-                                            if to_optimize_dwarf_id.is_some() {
-                                                dwarf.actual_occupation()
-                                            } else {
-                                                dwarf.occupation
-                                            };
-
-                                        for (item, _) in
-                                            player.inventory.items.iter().filter(|(item, num)| {
-                                                **num > 0
-                                                    && item
-                                                        .item_type()
-                                                        .map(|item_type| item_type.equippable())
-                                                        .unwrap_or(false)
-                                                    && dwarf
-                                                        .equipment
-                                                        .get(&item.item_type().expect(
-                                                            "equippables always have item types",
-                                                        ))
-                                                        .is_none()
-                                            })
-                                        {
-                                            let mut dwarf_clone = dwarf.clone();
-
-                                            let effectiveness_before = dwarf_clone
-                                                .effectiveness_not_normalized(
-                                                    occupation_to_optimize,
-                                                );
-
-                                            dwarf_clone.equipment.insert(
-                                                item.item_type()
-                                                    .expect("equippables always have item types"),
-                                                *item,
-                                            );
-
-                                            let effectiveness_after = dwarf_clone
-                                                .effectiveness_not_normalized(
-                                                    occupation_to_optimize,
-                                                );
-
-                                            let effectiveness_diff = effectiveness_after as i64
-                                                - effectiveness_before as i64;
-
-                                            if effectiveness_diff > best_dwarf_effectiveness {
-                                                best_dwarf_effectiveness = effectiveness_diff;
-                                                best_dwarf_item = Some(*item);
-                                                best_dwarf_id = Some(*dwarf_id);
-                                            }
-                                        }
-                                    }
-                                }
-
-                                if let Some(best_dwarf_id) = best_dwarf_id {
-                                    let best_dwarf = player.dwarfs.get_mut(&best_dwarf_id)?;
-                                    let best_dwarf_item =
-                                        best_dwarf_item.expect("item known if id is known");
-
-                                    if player
-                                        .inventory
-                                        .items
-                                        .remove_checked(Bundle::new().add(best_dwarf_item, 1))
-                                    {
-                                        best_dwarf.equipment.insert(
-                                            best_dwarf_item
-                                                .item_type()
-                                                .expect("equippables always have item types"),
-                                            best_dwarf_item,
-                                        );
-                                    } else if cfg!(debug_assertions) {
-                                        panic!("something went wrong!");
-                                    }
-                                } else {
-                                    break;
-                                }
+                            if let Some(dwarf_id) = to_optimize_dwarf_id {
+                                player.dwarfs.get(&dwarf_id)?;
                             }
+                            player.optimize(to_optimize_dwarf_id);
                         }
                         ClientEvent::SetManagerOccupation(occupation, num) => {
                             player.set_manager();
@@ -666,6 +770,31 @@ impl engine_shared::State for State {
                                 *player.manager.entry(Occupation::Idling).or_default() += diff;
                             }
                         }
+                        ClientEvent::ToggleProductionGoals => {
+                            player.production_goals.enabled = !player.production_goals.enabled;
+                            if player.production_goals.enabled {
+                                player.plan_production(self.event.as_ref());
+                            }
+                        }
+                        ClientEvent::SetProductionGoal(item, rate) => {
+                            if rate == 0 {
+                                player.production_goals.items.swap_remove(&item);
+                            } else {
+                                player
+                                    .production_goals
+                                    .items
+                                    .insert(item, rate.min(MAX_PRODUCTION_GOAL));
+                            }
+                            if player.production_goals.enabled {
+                                player.plan_production(self.event.as_ref());
+                            }
+                        }
+                        ClientEvent::SetFoodGoal(days) => {
+                            player.production_goals.food_days = days.min(MAX_FOOD_GOAL_DAYS);
+                            if player.production_goals.enabled {
+                                player.plan_production(self.event.as_ref());
+                            }
+                        }
                         ClientEvent::ConfirmPopup => {
                             player.popups.pop_front();
                         }
@@ -704,44 +833,37 @@ impl engine_shared::State for State {
                         }
                         ClientEvent::ToggleAutoCraft(item) => {
                             if is_premium {
-                                if player.auto_functions.auto_craft.contains(&item) {
-                                    player.auto_functions.auto_craft.swap_remove(&item);
-                                } else {
-                                    player.auto_functions.auto_craft.insert(item);
-                                    player.auto_functions.auto_dismantle.swap_remove(&item);
-                                    player.auto_craft(self.time, is_premium);
-                                }
+                                player.auto_functions.toggle_rule(AutoAction::Craft, item);
+                                player.run_auto_rules(self.time, is_premium);
                             }
                         }
                         ClientEvent::ToggleAutoDismantle(item) => {
                             if is_premium {
-                                if player.auto_functions.auto_dismantle.contains(&item) {
-                                    player.auto_functions.auto_dismantle.swap_remove(&item);
-                                } else {
-                                    player.auto_functions.auto_dismantle.insert(item);
-                                    player.auto_functions.auto_craft.swap_remove(&item);
-                                    player.auto_dismantle(self.time, is_premium);
-                                }
+                                player.auto_functions.toggle_rule(AutoAction::Dismantle, item);
+                                player.run_auto_rules(self.time, is_premium);
                             }
                         }
                         ClientEvent::ToggleAutoStore(item) => {
                             if is_premium {
-                                if player.auto_functions.auto_store.contains(&item) {
-                                    player.auto_functions.auto_store.swap_remove(&item);
-                                } else {
-                                    player.auto_functions.auto_store.insert(item);
-                                    player.auto_store(is_premium);
-                                }
+                                player.auto_functions.toggle_rule(AutoAction::Store, item);
+                                player.run_auto_rules(self.time, is_premium);
                             }
                         }
-                        ClientEvent::ToggleAutoSell(_item) => {
-                            /*if is_premium {
-                                if player.auto_functions.auto_sell.contains(&item) {
-                                    player.auto_functions.auto_sell.swap_remove(&item);
-                                } else {
-                                    player.auto_functions.auto_sell.insert(item);
-                                }
-                            }*/
+                        ClientEvent::UpdateAutoRule(idx, rule) => {
+                            if is_premium {
+                                player.auto_functions.update_rule(idx, rule);
+                                player.run_auto_rules(self.time, is_premium);
+                            }
+                        }
+                        ClientEvent::MoveAutoRuleUp(idx) => {
+                            if is_premium {
+                                player.auto_functions.move_rule_up(idx);
+                            }
+                        }
+                        ClientEvent::RemoveAutoRule(idx) => {
+                            if is_premium && idx < player.auto_functions.rules.len() {
+                                player.auto_functions.rules.remove(idx);
+                            }
                         }
                         ClientEvent::ToggleAutoIdle => {
                             //if is_premium {
@@ -758,10 +880,28 @@ impl engine_shared::State for State {
                             self.chat
                                 .add_message(&mut self.players, user_id, message, self.time);
                         }
+                        ClientEvent::TribeMessage => {
+                            // The message itself is only sent to the members by the server.
+                            let tribe_id = player.tribe?;
+                            for (other_id, other) in self.players.iter_mut() {
+                                if *other_id != user_id && other.tribe == Some(tribe_id) {
+                                    other.tribe_chat_unread = true;
+                                }
+                            }
+                        }
+                        ClientEvent::DirectMessage(other_id) => {
+                            // The message itself is only sent to the recipient by the server.
+                            if other_id != user_id && !self.is_bot(&other_id) {
+                                if let Some(other) = self.players.get_mut(&other_id) {
+                                    other.unread_conversations.insert(user_id);
+                                }
+                            }
+                        }
                         ClientEvent::ChangeOccupation(dwarf_id, occupation) => {
                             let dwarf = player.dwarfs.get_mut(&dwarf_id)?;
 
                             if dwarf.participates_in_quest.is_none()
+                                && dwarf.participates_in_battle.is_none()
                                 && player.base.curr_level >= occupation.unlocked_at_level()
                                 && dwarf.is_adult()
                             {
@@ -769,15 +909,21 @@ impl engine_shared::State for State {
                             }
                         }
                         ClientEvent::Craft(item, qty) => {
-                            Self::craft(player, item, qty);
+                            player.queue_craft(item, qty);
+                        }
+                        ClientEvent::CraftPlan(target) => {
+                            player.queue_crafting_plan(&target);
+                        }
+                        ClientEvent::CancelCraft(idx) => {
+                            player.cancel_craft(idx, self.time);
                         }
                         ClientEvent::Dismantle(item, qty) => {
                             Self::dismantle(player, item, qty);
                         }
                         ClientEvent::UpgradeBase => {
-                            if let Some(requires) = player.base.upgrade_cost() {
+                            if let Some(requires) = player.base.upgrade_cost(self.config.max_level) {
                                 if player.inventory.items.remove_checked(requires) {
-                                    player.base.upgrade();
+                                    player.base.upgrade(self.config.max_level);
                                 }
                             }
                         }
@@ -831,10 +977,19 @@ impl engine_shared::State for State {
                                 {
                                     return None;
                                 }
+                                // Bots fill the world but must not rule it.
+                                if self.bots.contains_key(&user_id)
+                                    && matches!(
+                                        quest.quest_type.reward_mode(),
+                                        RewardMode::BecomeKing
+                                    )
+                                {
+                                    return None;
+                                }
 
                                 let dwarf = player.dwarfs.get_mut(&dwarf_id)?;
 
-                                if dwarf.is_adult() {
+                                if dwarf.is_adult() && dwarf.participates_in_battle.is_none() {
                                     if let Some((_, old_quest_id, old_dwarf_idx)) =
                                         dwarf.participates_in_quest
                                     {
@@ -874,33 +1029,43 @@ impl engine_shared::State for State {
                         ClientEvent::AddToFoodStorage(item, qty) => {
                             Self::add_to_food_storage(player, item, qty);
                         }
+                        ClientEvent::Auction(item, qty, reserve_price, duration) => {
+                            let trade_deal = TradeDeal::from_player(
+                                user_id,
+                                player,
+                                item,
+                                qty,
+                                reserve_price,
+                                duration,
+                            )?;
+                            self.trade_deals.insert(self.next_trade_id, trade_deal);
+                            self.next_trade_id += 1;
+                        }
+                        ClientEvent::BuyOrder(item, qty, max_price, duration) => {
+                            let trade_deal = TradeDeal::buy_order_from_player(
+                                user_id,
+                                player,
+                                item,
+                                qty,
+                                max_price,
+                                duration,
+                            )?;
+                            self.trade_deals.insert(self.next_trade_id, trade_deal);
+                            self.next_trade_id += 1;
+                        }
                         ClientEvent::Sell(item, qty) => {
-                            if qty > 0 {
-                                /*
-                                if let Some(trade_deal) =
-                                    TradeDeal::from_player(user_id, player, item, qty)
-                                {
-                                    self.trade_deals.push(trade_deal);
-                                }
-                                */
-                                let qty = qty.min(player.inventory.items.get(&item).copied().unwrap_or(0));
-                                let items = Bundle::new().add(item, qty);
-                                let next_bid = item.money_value(qty) * TRADE_MONEY_MULTIPLIER;
-
-                                if qty == 0 {
-                                    return None;
-                                }
-
-                                if next_bid == 0 {
-                                    return None;
-                                }
-
-                                if !player.inventory.items.remove_checked(items.clone()) {
-                                    return None;
-                                }
-
-                                player.money += next_bid;
-                            }
+                            // A quick auction at the market value, there is no instant sale.
+                            let qty = qty.min(player.inventory.items.get(&item).copied().unwrap_or(0));
+                            let trade_deal = TradeDeal::from_player(
+                                user_id,
+                                player,
+                                item,
+                                qty,
+                                TradeDeal::market_price(item, qty),
+                                AUCTION_MIN_DURATION,
+                            )?;
+                            self.trade_deals.insert(self.next_trade_id, trade_deal);
+                            self.next_trade_id += 1;
                         }
                         ClientEvent::ReadLog => {
                             player.log.unread = false;
@@ -908,6 +1073,12 @@ impl engine_shared::State for State {
                         ClientEvent::ReadChat => {
                             player.chat_unread = false;
                         }
+                        ClientEvent::ReadTribeChat => {
+                            player.tribe_chat_unread = false;
+                        }
+                        ClientEvent::ReadDirectMessages(other_id) => {
+                            player.unread_conversations.swap_remove(&other_id);
+                        }
                     }
                 }
                 Event::ServerEvent(event) => {
@@ -927,59 +1098,52 @@ impl engine_shared::State for State {
                                 self.event = Some(enum_iterator::all().choose(rng).unwrap());
                             }
 
-                            let fewest_members_tribe_id = *self
-                                        .tribes
-                                        .keys()
-                                        .map(|tribe_id| (tribe_id, self.players.values().filter(|player| player.is_active(self.time) && player.tribe == Some(*tribe_id)).count()))
-                                        .min_by_key(|(_, count)| *count)
-                                        .unwrap().0;
+                            if self.time.is_multiple_of(ONE_DAY) {
+                                self.spend_voted_tribe_points();
+                            }
 
                             let tribes_map = self.players.iter()
                                 .filter_map(|(user_id, player)| player.tribe.map(|tribe_id| (*user_id, tribe_id)))
                                 .collect::<CustomMap<_, _>>();
 
+                            // Build the bases, bots stay out of the tribes.
+                            let mut joined = Vec::new();
                             for (user_id, player) in self.players.iter_mut() {
-                                let is_premium = user_data
-                                    .get(user_id)
-                                    .map(|user_data| user_data.premium > 0)
-                                    .unwrap_or(false);
+                                if player.base.build() == Some(self.config.join_tribe_level)
+                                    && !self.bots.contains_key(user_id)
+                                {
+                                    joined.push(*user_id);
+                                }
+                            }
+
+                            if !joined.is_empty() {
+                                let weakest_tribe_id = self.weakest_tribe(None);
 
-                                // Build the base.
-                                if let Some(JOIN_TRIBE_LEVEL) = player.base.build() {
-                                    let referrer_tribe = user_data.get(user_id).and_then(|user_data| {
+                                for user_id in joined {
+                                    let referrer_tribe = user_data.get(&user_id).and_then(|user_data| {
                                         user_data.referrer
                                     }).and_then(|referrer_id| {
                                         tribes_map.get(&referrer_id)
                                     });
 
-                                    if let Some(referrer_tribe) = referrer_tribe {
-                                        player.tribe = Some(*referrer_tribe);
-                                    } else {
-                                        player.tribe = Some(fewest_members_tribe_id);
+                                    if let Some(player) = self.players.get_mut(&user_id) {
+                                        player.tribe = referrer_tribe.copied().or(weakest_tribe_id);
+                                        player.tribe_joined = self.time;
                                     }
                                 }
+                            }
+
+                            for (user_id, player) in self.players.iter_mut() {
+                                let is_premium = user_data
+                                    .get(user_id)
+                                    .map(|user_data| user_data.premium > 0)
+                                    .unwrap_or(false);
 
                                 // Chance for a new dwarf!
                                 let controlled_territories = enum_iterator::all::<Territory>()
                                     .filter(|territory| {
-                                        if let Some(tribe_id) = player.tribe {
-                                            let score = self.tribes.get(&tribe_id).unwrap().territories.get(territory).copied().unwrap_or(0);
-                                            let scores = self.tribes
-                                                .values()
-                                                .map(|tribe| {
-                                                    tribe.territories.get(territory).copied().unwrap_or_default()
-                                                })
-                                                .collect::<Vec<_>>();
-
-                                            let under_control = scores
-                                                .iter()
-                                                .filter(|s| **s >= score)
-                                                .count() == 1;
-                                            
-                                            under_control
-                                        } else {
-                                            false
-                                        }
+                                        player.tribe.is_some()
+                                            && territory.controller(&self.tribes) == player.tribe
                                     })
                                     .map(|territory| territory.provides_stats())
                                     .collect::<Vec<_>>();
@@ -1000,26 +1164,6 @@ impl engine_shared::State for State {
                                     );
                                 }
 
-                                let male_idle_dwarfs = player
-                                    .dwarfs
-                                    .values()
-                                    .filter(|dwarf| {
-                                        dwarf.occupation == Occupation::Idling
-                                            && dwarf.is_adult()
-                                            && !dwarf.is_female
-                                    })
-                                    .count();
-
-                                let female_idle_dwarfs = player
-                                    .dwarfs
-                                    .values()
-                                    .filter(|dwarf| {
-                                        dwarf.occupation == Occupation::Idling
-                                            && dwarf.is_adult()
-                                            && dwarf.is_female
-                                    })
-                                    .count();
-
                                 // Chance for a new baby dwarf!
                                 let baby_dwarf_multiplier =
                                     if matches!(self.event, Some(WorldEvent::FullMoon)) {
@@ -1027,13 +1171,12 @@ impl engine_shared::State for State {
                                     } else {
                                         1
                                     };
-                                if rng.gen_ratio(
-                                    male_idle_dwarfs.min(female_idle_dwarfs) as u32
-                                        * baby_dwarf_multiplier,
-                                    ONE_DAY as u32 / 4,
-                                ) {
-                                    player.new_dwarf(rng, &mut self.next_dwarf_id, self.time, None);
-                                }
+                                player.breed(
+                                    rng,
+                                    &mut self.next_dwarf_id,
+                                    self.time,
+                                    baby_dwarf_multiplier,
+                                );
 
                                 let mut became_adult = CustomSet::new();
 
@@ -1049,8 +1192,7 @@ impl engine_shared::State for State {
                                 sorted_by_health.sort_by_key(|(_, dwarf)| dwarf.health);
                                 for (dwarf_id, dwarf) in sorted_by_health {
                                     dwarf.decr_health(
-                                        dwarf.actual_occupation().health_cost_per_tick()
-                                            * health_cost_multiplier,
+                                        dwarf.health_cost_per_tick() * health_cost_multiplier,
                                     );
                                     if dwarf.actual_occupation() == Occupation::Idling {
                                         if player.base.food > 0 {
@@ -1116,12 +1258,14 @@ impl engine_shared::State for State {
                                     };
 
                                     let dwarf = player.dwarfs.get_mut(&dwarf_id)?;
+                                    let improvement_multiplier =
+                                        improvement_multiplier * dwarf.improvement_multiplier();
 
                                     if !dwarf.dead() {
                                         if rng.gen_ratio(
                                             improvement_occupation.requires_stats().agility as u32
                                                 * improvement_multiplier as u32,
-                                            IMPROVEMENT_DURATION,
+                                            self.config.improvement_duration,
                                         ) && dwarf.stats.agility < 10
                                         {
                                             dwarf.stats.agility += 1;
@@ -1137,7 +1281,7 @@ impl engine_shared::State for State {
                                             improvement_occupation.requires_stats().endurance
                                                 as u32
                                                 * improvement_multiplier as u32,
-                                            IMPROVEMENT_DURATION,
+                                            self.config.improvement_duration,
                                         ) && dwarf.stats.endurance < 10
                                         {
                                             dwarf.stats.endurance += 1;
@@ -1152,7 +1296,7 @@ impl engine_shared::State for State {
                                         if rng.gen_ratio(
                                             improvement_occupation.requires_stats().strength as u32
                                                 * improvement_multiplier as u32,
-                                            IMPROVEMENT_DURATION,
+                                            self.config.improvement_duration,
                                         ) && dwarf.stats.strength < 10
                                         {
                                             dwarf.stats.strength += 1;
@@ -1168,7 +1312,7 @@ impl engine_shared::State for State {
                                             improvement_occupation.requires_stats().intelligence
                                                 as u32
                                                 * improvement_multiplier as u32,
-                                            IMPROVEMENT_DURATION,
+                                            self.config.improvement_duration,
                                         ) && dwarf.stats.intelligence < 10
                                         {
                                             dwarf.stats.intelligence += 1;
@@ -1184,7 +1328,7 @@ impl engine_shared::State for State {
                                             improvement_occupation.requires_stats().perception
                                                 as u32
                                                 * improvement_multiplier as u32,
-                                            IMPROVEMENT_DURATION,
+                                            self.config.improvement_duration,
                                         ) && dwarf.stats.perception < 10
                                         {
                                             dwarf.stats.perception += 1;
@@ -1221,9 +1365,15 @@ impl engine_shared::State for State {
                                                                     dwarf.actual_occupation(),
                                                                 )
                                                             })
-                                                            .unwrap_or(1),
+                                                            .unwrap_or(1)
+                                                        * 100
+                                                        / dwarf.drop_rate_percent(self.time),
                                                 ) {
                                                     added_items = added_items.add(item, 1);
+                                                    *player
+                                                        .produced
+                                                        .entry(dwarf.actual_occupation())
+                                                        .or_default() += 1;
                                                 }
                                             }
                                         }
@@ -1231,6 +1381,15 @@ impl engine_shared::State for State {
                                 }
                                 player.add_items(added_items, self.time, is_premium);
 
+                                // Continue the crafting queue.
+                                player.progress_crafting(self.time, is_premium);
+
+                                // Injure, infect and treat the dwarfs.
+                                player.update_ailments(rng, self.event.as_ref(), self.time);
+
+                                // Let the working dwarfs gain experience.
+                                player.gain_experience(rng);
+
                                 // Handle dwarfs that became adult
                                 for dwarf_id in became_adult {
                                     player.set_mentor(dwarf_id, None);
@@ -1258,6 +1417,7 @@ impl engine_shared::State for State {
                                         player.set_mentor(apprentice_id, None);
                                     }
                                     player.set_mentor(dwarf_id, None);
+                                    player.set_partner(dwarf_id, None);
 
                                     let dwarf: &Dwarf = player.dwarfs.get(&dwarf_id)?;
                                     // Send log message that dwarf died.
@@ -1285,6 +1445,18 @@ impl engine_shared::State for State {
                                         });
                                     }
                                 }
+                                // Remove dead dwarfs from battles.
+                                for battle in self.battles.values_mut() {
+                                    if let Some(fighters) = battle.fighters.get_mut(user_id) {
+                                        fighters.retain(|dwarf_id| {
+                                            !player
+                                                .dwarfs
+                                                .get(dwarf_id)
+                                                .map(|d| d.dead() || d.released)
+                                                .unwrap_or(true)
+                                        });
+                                    }
+                                }
 
                                 // Remove dead dwarfs from the base.
                                 player
@@ -1303,12 +1475,18 @@ impl engine_shared::State for State {
                                                 if let Some(player) = self.players.get_mut(&user_id)
                                                 {
                                                     player.tribe_points += 1;
-
-                                                    if self.king.is_some() {
-                                                        player.money += money * 9 / 10;
-                                                    } else {
-                                                        player.money += money;
-                                                    }
+                                                    player.quests_won += 1;
+
+                                                    player.add_quest_money(
+                                                        user_id,
+                                                        if self.king.is_some() {
+                                                            money * 9 / 10
+                                                        } else {
+                                                            money
+                                                        },
+                                                        &mut self.tribes,
+                                                        self.time,
+                                                    );
                                                     player.log.add(
                                                         self.time,
                                                         LogMsg::QuestCompletedMoney(
@@ -1348,6 +1526,7 @@ impl engine_shared::State for State {
                                                 if let Some(player) = self.players.get_mut(&user_id)
                                                 {
                                                     player.tribe_points += 1;
+                                                    player.quests_won += 1;
 
                                                     if !matches!(
                                                         self.event,
@@ -1388,7 +1567,12 @@ impl engine_shared::State for State {
                                             {
                                                 if let Some(player) = self.players.get_mut(&user_id)
                                                 {
-                                                    player.money += money;
+                                                    player.add_quest_money(
+                                                        user_id,
+                                                        money,
+                                                        &mut self.tribes,
+                                                        self.time,
+                                                    );
                                                     player.log.add(
                                                         self.time,
                                                         LogMsg::QuestCompletedMoney(
@@ -1413,6 +1597,7 @@ impl engine_shared::State for State {
                                                 if let Some(player) = self.players.get_mut(&user_id)
                                                 {
                                                     player.tribe_points += 1;
+                                                    player.quests_won += 1;
 
                                                     let is_premium = user_data
                                                         .get(&user_id)
@@ -1526,6 +1711,7 @@ impl engine_shared::State for State {
                                                 if let Some(player) = self.players.get_mut(&user_id)
                                                 {
                                                     player.tribe_points += 1;
+                                                    player.quests_won += 1;
                                                     player.log.add(
                                                         self.time,
                                                         LogMsg::QuestCompletedDwarfs(
@@ -1571,18 +1757,36 @@ impl engine_shared::State for State {
 
                             self.quests.retain(|_, quest| !quest.done());
 
+                            // Continue the active battles.
+                            for battle in self.battles.values_mut() {
+                                battle.run(&self.players);
+                            }
+
+                            let ended_battles = self
+                                .battles
+                                .iter()
+                                .filter(|(_, battle)| battle.done())
+                                .map(|(battle_id, _)| *battle_id)
+                                .collect::<Vec<_>>();
+                            for battle_id in ended_battles {
+                                if let Some(battle) = self.battles.swap_remove(&battle_id) {
+                                    battle.end(&mut self.players, &mut self.tribes, rng, self.time);
+                                }
+                            }
+
                             // Add quests.
                             let active_players = self
                                 .players
                                 .iter()
-                                .filter(|(_, player)| player.is_active(self.time))
+                                .filter(|(_, player)| player.is_active(self.time, self.config.speed))
                                 .count();
 
                             let active_not_new_players = self
                                 .players
                                 .iter()
                                 .filter(|(_, player)| {
-                                    player.is_active(self.time) && !player.is_new(self.time)
+                                    player.is_active(self.time, self.config.speed)
+                                        && !player.is_new(self.time, self.config.speed)
                                 })
                                 .count();
 
@@ -1636,15 +1840,15 @@ impl engine_shared::State for State {
                                     if let Some(level) = selected_quest.max_level() {
                                         (1, level)
                                     } else if selected_quest.one_at_a_time() {
-                                        (1, 100)
+                                        (1, self.config.max_level)
                                     } else {
                                         let selected_level = self
                                             .players
                                             .iter()
                                             .collect::<Vec<_>>()
                                             .choose_weighted(rng, |(_, player)| {
-                                                if player.is_active(self.time) {
-                                                    if player.is_new(self.time) {
+                                                if player.is_active(self.time, self.config.speed) {
+                                                    if player.is_new(self.time, self.config.speed) {
                                                         1
                                                     } else {
                                                         NEW_PLAYER_DIVIDER
@@ -1657,7 +1861,7 @@ impl engine_shared::State for State {
                                             .unwrap_or(1);
 
                                         let min_level = (selected_level.saturating_sub(rng.gen_range(5..=15))).max(1);
-                                        let max_level = (selected_level + rng.gen_range(5..=15)).min(100);
+                                        let max_level = (selected_level + rng.gen_range(5..=15)).min(self.config.max_level);
                                         (min_level, max_level)
                                     };
 
@@ -1699,11 +1903,21 @@ impl engine_shared::State for State {
             }
 
             Some(())
-        }();
+        })();
 
         if update_result.is_none() {
             println!("state update failed");
         }
+
+        if is_tick && self.time.is_multiple_of(ONE_HOUR) {
+            self.run_bots(rng, user_data);
+
+            for player in self.players.values_mut() {
+                if player.production_goals.enabled {
+                    player.plan_production(self.event.as_ref());
+                }
+            }
+        }
     }
 }
 
@@ -1902,6 +2116,16 @@ pub enum LogMsg {
     BidWon(Bundle<Item>, Money, TradeType),
     ItemSold(Bundle<Item>, Money),
     ItemNotSold(Bundle<Item>, Money),
+    ItemNotBought(Bundle<Item>, Money),
+    Crafted(Bundle<Item>),
+    DwarfInjured(String),
+    DwarfInfected(String),
+    DwarfCured(String),
+    BattleWon(Territory),
+    BattleLost(Territory),
+    TribeTax(Money),
+    CraftingFailed(Bundle<Item>),
+    AutoSellRemoved(Vec<Item>),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Hash)]
@@ -1924,26 +2148,34 @@ pub struct Player {
     pub chat_unread: bool,
     pub tribe: Option<TribeId>,
     pub tribe_points: u64,
+    #[serde(default)]
+    pub quests_won: u64,
+    #[serde(default)]
+    pub produced: CustomMap<Occupation, u64>,
+    #[serde(default)]
+    pub tribe_chat_unread: bool,
+    #[serde(default)]
+    pub unread_conversations: CustomSet<UserId>,
+    #[serde(default)]
+    pub production_goals: ProductionGoals,
+    #[serde(default)]
+    pub crafting_queue: VecDeque<CraftingJob>,
+    #[serde(default)]
+    pub tribe_joined: Time,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Hash)]
 pub struct AutoFunctions {
     pub auto_idle: bool,
-    pub auto_craft: CustomSet<Item>,
-    pub auto_store: CustomSet<Item>,
-    pub auto_sell: CustomSet<Item>,
-    #[serde(default = "CustomSet::new")]
-    pub auto_dismantle: CustomSet<Item>,
+    // Applied in this order whenever items are added to the inventory.
+    pub rules: Vec<AutoRule>,
 }
 
 impl Default for AutoFunctions {
     fn default() -> Self {
         Self {
             auto_idle: true,
-            auto_craft: CustomSet::new(),
-            auto_store: CustomSet::new(),
-            auto_sell: CustomSet::new(),
-            auto_dismantle: CustomSet::new(),
+            rules: Vec::new(),
         }
     }
 }
@@ -1966,6 +2198,13 @@ impl Player {
             chat_unread: false,
             tribe: None,
             tribe_points: 0,
+            quests_won: 0,
+            produced: CustomMap::new(),
+            tribe_chat_unread: false,
+            unread_conversations: CustomSet::new(),
+            production_goals: ProductionGoals::default(),
+            crafting_queue: VecDeque::new(),
+            tribe_joined: 0,
         };
 
         player.new_dwarf(rng, next_dwarf_id, time, Some(Stats::default()));
@@ -1990,24 +2229,7 @@ impl Player {
     }
 
     pub fn remaining_time_until_starvation(&self, state: &State) -> Time {
-        let mut health_available = self.base.food * (MAX_HEALTH / 1000);
-        let mut health_cost_per_tick = 0;
-
-        let health_cost_multiplier = match state.event {
-            Some(WorldEvent::Plague) => (1 + self.dwarfs.len() as u64 / 20).min(5),
-            _ => 1,
-        };
-
-        for dwarf in self.dwarfs.values() {
-            health_available += dwarf.health;
-            health_cost_per_tick +=
-                dwarf.actual_occupation().health_cost_per_tick() * health_cost_multiplier;
-        }
-
-        if health_cost_per_tick == 0 {
-            return u64::MAX;
-        }
-        health_available / health_cost_per_tick
+        self.time_until_starvation(state.event.as_ref())
     }
 
     pub fn average_efficiency(&self) -> Option<u64> {
@@ -2018,6 +2240,22 @@ impl Player {
             .checked_div(self.dwarfs.len() as u64)
     }
 
+    pub fn stats(&self, time: Time) -> PlayerStats {
+        PlayerStats {
+            time,
+            level: self.base.curr_level,
+            dwarfs: self.dwarfs.len() as u64,
+            food: self.base.food,
+            money: self.money,
+            efficiency: self.average_efficiency().unwrap_or(0),
+            produced: self
+                .produced
+                .iter()
+                .map(|(occupation, qty)| (*occupation, *qty))
+                .collect(),
+        }
+    }
+
     pub fn set_manager(&mut self) {
         let manager_num = self.manager.values().copied().sum::<u64>();
         let dwarfs_num = self
@@ -2077,16 +2315,16 @@ impl Player {
         self.popups.push_back(popup);
     }
 
-    pub fn is_online(&self, time: Time) -> bool {
-        (time - self.last_online) / SPEED < ONE_MINUTE * 5
+    pub fn is_online(&self, time: Time, speed: u64) -> bool {
+        (time - self.last_online) / speed < ONE_MINUTE * 5
     }
 
-    pub fn is_active(&self, time: Time) -> bool {
-        (time - self.last_online) / SPEED < ONE_DAY && !self.dwarfs.is_empty()
+    pub fn is_active(&self, time: Time, speed: u64) -> bool {
+        (time - self.last_online) / speed < ONE_DAY && !self.dwarfs.is_empty()
     }
 
-    pub fn is_new(&self, time: Time) -> bool {
-        (time - self.start_time) / SPEED < ONE_DAY || self.base.curr_level == 1
+    pub fn is_new(&self, time: Time, speed: u64) -> bool {
+        (time - self.start_time) / speed < ONE_DAY || self.base.curr_level == 1
     }
 
     pub fn new_dwarf(
@@ -2096,19 +2334,26 @@ impl Player {
         time: Time,
         adult_with_added_stats: Option<Stats>,
     ) {
+        let dwarf = if let Some(stats) = adult_with_added_stats {
+            Dwarf::new_with_added_stats(rng, stats)
+        } else {
+            Dwarf::new_baby(rng)
+        };
+        self.add_dwarf(dwarf, next_dwarf_id, time);
+    }
+
+    fn add_dwarf(&mut self, dwarf: Dwarf, next_dwarf_id: &mut DwarfId, time: Time) -> Option<DwarfId> {
         if self.dwarfs.len() < self.base.max_dwarfs() {
-            let dwarf = if let Some(stats) = adult_with_added_stats {
-                Dwarf::new_with_added_stats(rng, stats)
-            } else {
-                Dwarf::new_baby(rng)
-            };
+            let dwarf_id = *next_dwarf_id;
             self.log
                 .add(time, LogMsg::NewDwarf(dwarf.actual_name().to_owned()));
-            self.add_popup(Popup::NewDwarf(dwarf.clone()));
-            self.dwarfs.insert(*next_dwarf_id, dwarf);
+            self.add_popup(Popup::NewDwarf(Box::new(dwarf.clone())));
+            self.dwarfs.insert(dwarf_id, dwarf);
             *next_dwarf_id += 1;
+            Some(dwarf_id)
         } else {
             self.log.add(time, LogMsg::NotEnoughSpaceForDwarf);
+            None
         }
     }
 
@@ -2127,94 +2372,7 @@ impl Player {
 
     pub fn add_items(&mut self, bundle: Bundle<Item>, time: Time, is_premium: bool) {
         self.inventory.add(bundle, time);
-        self.auto_dismantle(time, is_premium);
-        self.auto_craft(time, is_premium);
-        self.auto_store(is_premium);
-        self.auto_sell(is_premium);
-    }
-
-    pub fn auto_craft(&mut self, time: Time, is_premium: bool) {
-        if is_premium {
-            let mut items_added = false;
-            // Auto-craft!
-            for &item in &self.auto_functions.auto_craft {
-                if let Some((level, requires)) = item.requires() {
-                    if self.base.curr_level >= level {
-                        if let Some(qty) = self.inventory.items.can_remove_x_times(&requires) {
-                            if qty > 0 && self.inventory.items.remove_checked(requires.mul(qty)) {
-                                self.inventory.add(Bundle::new().add(item, qty), time);
-
-                                items_added = true;
-                            }
-                        }
-                    }
-                }
-            }
-
-            if items_added {
-                self.auto_craft(time, is_premium);
-            }
-        }
-    }
-
-    pub fn auto_dismantle(&mut self, time: Time, is_premium: bool) {
-        if is_premium {
-            let mut items_added = false;
-            // Auto-craft!
-            for &item in &self.auto_functions.auto_dismantle {
-                if let Some((_level, requires)) = item.requires() {
-                    let qty = self.inventory.items.get(&item).copied().unwrap_or_default();
-                    if qty > 0 && self
-                            .inventory
-                            .items
-                            .remove_checked(Bundle::new().add(item, qty)) {
-                        self.inventory
-                            .add(requires.mul(qty).div(DISMANTLING_DIVIDER), time);
-
-                        items_added = true;
-                    }
-                }
-            }
-
-            if items_added {
-                self.auto_dismantle(time, is_premium);
-            }
-        }
-    }
-
-    pub fn auto_store(&mut self, is_premium: bool) {
-        if is_premium {
-            // Auto-store!
-            for &item in &self.auto_functions.auto_store {
-                if let Some(&qty) = self.inventory.items.get(&item) {
-                    if let Some(food) = item.nutritional_value() {
-                        if self
-                            .inventory
-                            .items
-                            .remove_checked(Bundle::new().add(item, qty))
-                        {
-                            self.base.food += food * qty;
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    pub fn auto_sell(&mut self, is_premium: bool) {
-        if is_premium {
-            // Auto-sell!
-            for &item in &self.auto_functions.auto_sell {
-                if let Some(&qty) = self.inventory.items.get(&item) {
-                    if item.money_value(1) > 0 && self
-                            .inventory
-                            .items
-                            .remove_checked(Bundle::new().add(item, qty)) {
-                        self.money += item.money_value(1) * qty;
-                    }
-                }
-            }
-        }
+        self.run_auto_rules(time, is_premium);
     }
 }
 
@@ -2325,6 +2483,22 @@ pub struct Dwarf {
     pub apprentice: Option<DwarfId>,
     #[serde(default)]
     pub released: bool,
+    #[serde(default)]
+    pub partner: Option<DwarfId>,
+    // Mother and father.
+    #[serde(default)]
+    pub parents: Option<(DwarfId, DwarfId)>,
+    #[serde(default)]
+    pub children: Vec<DwarfId>,
+    #[serde(default)]
+    pub traits: Vec<DwarfTrait>,
+    #[serde(default)]
+    pub ailment: Option<Ailment>,
+    // Ticks worked in each occupation.
+    #[serde(default)]
+    pub experience: CustomMap<Occupation, Time>,
+    #[serde(default)]
+    pub participates_in_battle: Option<BattleId>,
 }
 
 impl Dwarf {
@@ -2341,7 +2515,10 @@ impl Dwarf {
     }
 
     pub fn can_be_managed(&self) -> bool {
-        self.is_adult() && self.participates_in_quest.is_none() && !self.manual_management
+        self.is_adult()
+            && self.participates_in_quest.is_none()
+            && self.participates_in_battle.is_none()
+            && !self.manual_management
     }
 
     pub fn name(rng: &mut impl Rng) -> String {
@@ -2407,6 +2584,13 @@ impl Dwarf {
             mentor: None,
             apprentice: None,
             released: false,
+            partner: None,
+            parents: None,
+            children: Vec::new(),
+            traits: DwarfTrait::random(rng),
+            ailment: None,
+            experience: CustomMap::new(),
+            participates_in_battle: None,
         }
     }*/
 
@@ -2428,10 +2612,16 @@ impl Dwarf {
             mentor: None,
             apprentice: None,
             released: false,
+            partner: None,
+            parents: None,
+            children: Vec::new(),
+            traits: DwarfTrait::random(rng),
+            ailment: None,
+            experience: CustomMap::new(),
+            participates_in_battle: None,
         }
     }
 
-
     fn new_baby(rng: &mut impl Rng) -> Self {
         let name = Dwarf::name(rng);
 
@@ -2450,6 +2640,13 @@ impl Dwarf {
             mentor: None,
             apprentice: None,
             released: false,
+            partner: None,
+            parents: None,
+            children: Vec::new(),
+            traits: DwarfTrait::random(rng),
+            ailment: None,
+            experience: CustomMap::new(),
+            participates_in_battle: None,
         }
     }
 
@@ -2462,6 +2659,10 @@ impl Dwarf {
             return Occupation::Idling;
         }
 
+        if self.participates_in_battle.is_some() {
+            return Occupation::Fighting;
+        }
+
         self.participates_in_quest
             .map(|(quest_type, _, _)| quest_type.occupation())
             .unwrap_or(self.occupation)
@@ -2546,7 +2747,14 @@ impl Dwarf {
 
         usefulness = usefulness.min(30);
 
-        let effectiveness = usefulness * self.effective_stats().cross(occupation.requires_stats());
+        let effectiveness = usefulness
+            * self.effective_stats().cross(occupation.requires_stats())
+            * self.trait_effectiveness_percent(occupation)
+            / 100
+            * self.ailment.map(|ailment| ailment.effectiveness_percent()).unwrap_or(100)
+            / 100
+            * self.experience_percent(occupation)
+            / 100;
 
         effectiveness.min(MAX_EFFECTIVENESS)
     }
@@ -2565,6 +2773,7 @@ pub enum Occupation {
     Exploring,
     Farming,
     Rockhounding,
+    Healing,
 }
 
 impl Occupation {
@@ -2580,6 +2789,7 @@ impl Occupation {
             Occupation::Exploring => 5,
             Occupation::Farming => 3,
             Occupation::Rockhounding => 5,
+            Occupation::Healing => 2,
         }
     }
 
@@ -2595,6 +2805,7 @@ impl Occupation {
             Occupation::Fighting => 30,
             Occupation::Farming => 40,
             Occupation::Rockhounding => 50,
+            Occupation::Healing => 5,
         }
     }
 
@@ -2648,6 +2859,11 @@ impl Occupation {
                 strength: 10,
                 ..Default::default()
             },
+            Occupation::Healing => Stats {
+                intelligence: 10,
+                agility: 10,
+                ..Default::default()
+            },
         }
     }
 }
@@ -2682,8 +2898,8 @@ impl Base {
         (level as usize + 1) / 2
     }
 
-    pub fn upgrade_cost(&self) -> Option<Bundle<Item>> {
-        if self.curr_level < MAX_LEVEL {
+    pub fn upgrade_cost(&self, max_level: u64) -> Option<Bundle<Item>> {
+        if self.curr_level < max_level {
             let multiplier = |unlocked_after_level: u64| {
                 self.curr_level.saturating_sub(unlocked_after_level)
                     * (self.curr_level.saturating_sub(unlocked_after_level) / 10 + 1)
@@ -2717,8 +2933,8 @@ impl Base {
         None
     }
 
-    pub fn upgrade(&mut self) {
-        if self.curr_level < MAX_LEVEL && self.build_time == 0 {
+    pub fn upgrade(&mut self, max_level: u64) {
+        if self.curr_level < max_level && self.build_time == 0 {
             self.build_time = self.build_time_ticks();
         }
     }
@@ -2760,8 +2976,12 @@ pub type DwarfId = u64;
 pub enum ClientEvent {
     Init,
     Message(String),
+    TribeMessage,
+    DirectMessage(UserId),
     ChangeOccupation(DwarfId, Occupation),
     Craft(Item, u64),
+    CraftPlan(Bundle<Item>),
+    CancelCraft(usize),
     Dismantle(Item, u64),
     UpgradeBase,
     ChangeEquipment(DwarfId, ItemType, Option<Item>),
@@ -2770,25 +2990,51 @@ pub enum ClientEvent {
     AssignToQuest(QuestId, usize, Option<DwarfId>),
     AddToFoodStorage(Item, u64),
     Sell(Item, u64),
+    Auction(Item, u64, Money, Time),
+    BuyOrder(Item, u64, Money, Time),
     Restart,
     ToggleAutoCraft(Item),
     ToggleAutoStore(Item),
-    ToggleAutoSell(Item),
     ToggleAutoDismantle(Item),
+    UpdateAutoRule(usize, AutoRule),
+    MoveAutoRuleUp(usize),
+    RemoveAutoRule(usize),
     ToggleAutoIdle,
     HireDwarf(HireDwarfType),
     NextTutorialStep,
     ConfirmPopup,
     SetManagerOccupation(Occupation, u64),
+    ToggleProductionGoals,
+    SetProductionGoal(Item, u64),
+    SetFoodGoal(u64),
     Optimize(Option<DwarfId>),
     SetDwarfName(DwarfId, String),
     ToggleManualManagement(DwarfId),
     SetMentor(DwarfId, Option<DwarfId>),
+    SetPartner(DwarfId, Option<DwarfId>),
     Bid(TradeId),
     ReleaseDwarf(DwarfId),
     ReadLog,
     ReadChat,
+    ReadTribeChat,
+    ReadDirectMessages(UserId),
     SpendTribePoint(Territory),
+    DeclareAttack(Territory),
+    CommitToBattle(BattleId, DwarfId, bool),
+    SetTribeName(String),
+    SetTribeMotd(String),
+    SetTribeRole(UserId, TribeRole),
+    KickTribeMember(UserId),
+    VoteChief(Option<UserId>),
+    VoteTerritory(Option<Territory>),
+    DonateTribePoints(u64),
+    SpendSharedTribePoints(Territory, u64),
+    DepositTribeMoney(Money),
+    DepositTribeItems(Item, u64),
+    WithdrawTribeMoney(UserId, Money),
+    WithdrawTribeItems(UserId, Item, u64),
+    SetTribeTax(u64),
+    SwitchTribe(TribeId),
 }
 
 impl engine_shared::ClientEvent for ClientEvent {
@@ -2912,8 +3158,10 @@ impl Quest {
 
                     if dwarf.actual_occupation() == self.quest_type.occupation() {
                         for _ in 0..10 {}
-                        contestant.achieved_score +=
-                            dwarf.numerator_effectiveness(&player.dwarfs) / 100;
+                        contestant.achieved_score += dwarf.numerator_effectiveness(&player.dwarfs)
+                            * dwarf.quest_score_percent()
+                            / 100
+                            / 100;
                     }
                 }
             }
@@ -3239,6 +3487,8 @@ pub struct TradeDeal {
     pub user_trade_type: TradeType,
     #[serde(default)]
     pub creator: Option<UserId>,
+    #[serde(default)]
+    pub escrow: Money,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, PartialEq, Eq)]
@@ -3262,51 +3512,120 @@ impl TradeDeal {
         let time_left = rng.gen_range(ONE_MINUTE * 20..ONE_HOUR * 2);
         let qty = ((time_left * 10) / item.item_rarity_num()).max(1);
 
-        /*
         let user_trade_type = if rng.gen_bool(0.5) {
             TradeType::Buy
         } else {
             TradeType::Sell
         };
-        */
+
+        // The market pays more than selling directly, sellers compete the price down.
+        let next_bid = match user_trade_type {
+            TradeType::Buy => item.money_value(qty) * TRADE_MONEY_MULTIPLIER,
+            TradeType::Sell => item.money_value(qty) * TRADE_MONEY_MULTIPLIER * 2,
+        };
 
         TradeDeal {
             items: Bundle::new().add(item, qty),
-            next_bid: item.money_value(qty) as u64 * TRADE_MONEY_MULTIPLIER,
+            next_bid,
             time_left,
             highest_bidder: None,
             creator: None,
-            user_trade_type: TradeType::Buy,
+            user_trade_type,
+            escrow: 0,
         }
     }
 
-    pub fn from_player(user_id: UserId, player: &mut Player, item: Item, qty: u64) -> Option<Self> {
+    pub fn market_price(item: Item, qty: u64) -> Money {
+        (item.money_value(qty) * TRADE_MONEY_MULTIPLIER).max(2)
+    }
+
+    pub fn listing_fee(reserve_price: Money) -> Money {
+        (reserve_price / AUCTION_LISTING_FEE_DIVIDER).max(1)
+    }
+
+    pub fn from_player(
+        user_id: UserId,
+        player: &mut Player,
+        item: Item,
+        qty: u64,
+        reserve_price: Money,
+        duration: Time,
+    ) -> Option<Self> {
         let qty = qty.min(player.inventory.items.get(&item).copied().unwrap_or(0));
-        let time_left = ((qty * item.item_rarity_num()) / 10)
-            .max(ONE_MINUTE * 20)
-            .min(ONE_HOUR * 4);
+        let time_left = duration.clamp(AUCTION_MIN_DURATION, AUCTION_MAX_DURATION);
         let items = Bundle::new().add(item, qty);
-        let next_bid = item.money_value(qty) * TRADE_MONEY_MULTIPLIER;
+        let listing_fee = Self::listing_fee(reserve_price);
 
         if qty == 0 {
             return None;
         }
 
-        if next_bid == 0 {
+        if reserve_price <= 1 {
+            return None;
+        }
+
+        if player.money < listing_fee {
             return None;
         }
 
+        // The items are kept in the deal until it is settled.
         if !player.inventory.items.remove_checked(items.clone()) {
             return None;
         }
 
+        player.money -= listing_fee;
+
         Some(TradeDeal {
             items,
-            next_bid,
+            next_bid: reserve_price,
             time_left,
             highest_bidder: None,
             creator: Some(user_id),
             user_trade_type: TradeType::Buy,
+            escrow: 0,
+        })
+    }
+
+    pub fn buy_order_from_player(
+        user_id: UserId,
+        player: &mut Player,
+        item: Item,
+        qty: u64,
+        max_price: Money,
+        duration: Time,
+    ) -> Option<Self> {
+        let time_left = duration.clamp(AUCTION_MIN_DURATION, AUCTION_MAX_DURATION);
+        let listing_fee = Self::listing_fee(max_price);
+
+        if qty == 0 {
+            return None;
+        }
+
+        if max_price <= 1 {
+            return None;
+        }
+
+        if let Some((level, _)) = item.requires() {
+            if level > player.base.curr_level {
+                return None;
+            }
+        }
+
+        // The coins are kept in the deal until it is settled.
+        if player.money < max_price + listing_fee {
+            return None;
+        }
+
+        player.money -= max_price + listing_fee;
+
+        Some(TradeDeal {
+            items: Bundle::new().add(item, qty),
+            next_bid: max_price,
+            time_left,
+            highest_bidder: None,
+            creator: Some(user_id),
+            user_trade_type: TradeType::Sell,
+            escrow: max_price,
         })
     }
 
@@ -3314,61 +3633,156 @@ impl TradeDeal {
         if self.time_left > 0 {
             self.time_left -= 1;
             if self.time_left == 0 || self.next_bid <= 1 {
-                if let Some((best_bidder_user_id, best_bidder_money)) = self.highest_bidder {
-                    let p = players.get_mut(&best_bidder_user_id)?;
-                    p.inventory.add(self.items.clone(), time);
-                    p.log.add(
-                        time,
-                        LogMsg::BidWon(self.items.clone(), best_bidder_money, self.user_trade_type),
-                    );
-
-                    if let Some(creator) = self.creator {
-                        let c = players.get_mut(&creator)?;
-                        c.money += best_bidder_money;
-                        c.log.add(
-                            time,
-                            LogMsg::ItemSold(self.items.clone(), best_bidder_money),
-                        );
-                    }
-                } else if let Some(creator) = self.creator {
-                    let c = players.get_mut(&creator)?;
-                    c.inventory.add(self.items.clone(), time);
-                    c.log
-                        .add(time, LogMsg::ItemNotSold(self.items.clone(), self.next_bid));
+                self.time_left = 0;
+                match self.user_trade_type {
+                    TradeType::Buy => self.settle_auction(players, time)?,
+                    TradeType::Sell => self.settle_buy_order(players, time)?,
                 }
             }
         }
         Some(())
     }
 
+    fn settle_auction(&mut self, players: &mut CustomMap<UserId, Player>, time: Time) -> Option<()> {
+        if let Some((best_bidder_user_id, best_bidder_money)) = self.highest_bidder {
+            let p = players.get_mut(&best_bidder_user_id)?;
+            p.inventory.add(self.items.clone(), time);
+            p.log.add(
+                time,
+                LogMsg::BidWon(self.items.clone(), best_bidder_money, self.user_trade_type),
+            );
+
+            if let Some(creator) = self.creator {
+                let c = players.get_mut(&creator)?;
+                c.money += best_bidder_money;
+                c.log.add(
+                    time,
+                    LogMsg::ItemSold(self.items.clone(), best_bidder_money),
+                );
+            }
+        } else if let Some(creator) = self.creator {
+            let c = players.get_mut(&creator)?;
+            c.inventory.add(self.items.clone(), time);
+            c.log
+                .add(time, LogMsg::ItemNotSold(self.items.clone(), self.next_bid));
+        }
+        Some(())
+    }
+
+    fn settle_buy_order(&mut self, players: &mut CustomMap<UserId, Player>, time: Time) -> Option<()> {
+        if let Some((best_seller_user_id, best_seller_money)) = self.highest_bidder {
+            let p = players.get_mut(&best_seller_user_id)?;
+            p.money += best_seller_money;
+            p.log.add(
+                time,
+                LogMsg::ItemSold(self.items.clone(), best_seller_money),
+            );
+
+            if let Some(creator) = self.creator {
+                let c = players.get_mut(&creator)?;
+                c.inventory.add(self.items.clone(), time);
+                c.money += self.escrow.saturating_sub(best_seller_money);
+                c.log.add(
+                    time,
+                    LogMsg::BidWon(self.items.clone(), best_seller_money, self.user_trade_type),
+                );
+            }
+        } else if let Some(creator) = self.creator {
+            let c = players.get_mut(&creator)?;
+            c.money += self.escrow;
+            c.log
+                .add(time, LogMsg::ItemNotBought(self.items.clone(), self.escrow));
+        }
+        Some(())
+    }
+
     pub fn done(&self) -> bool {
         self.time_left == 0
     }
 
+    // Takes a player out of the deal. Deals created by the player are cancelled and the
+    // best offer is returned.
+    pub fn withdraw(
+        &mut self,
+        user_id: UserId,
+        players: &mut CustomMap<UserId, Player>,
+        time: Time,
+    ) {
+        if self.creator == Some(user_id) {
+            if let Some((best_user_id, best_money)) = self.highest_bidder.take() {
+                if let Some(p) = players.get_mut(&best_user_id) {
+                    match self.user_trade_type {
+                        TradeType::Buy => p.money += best_money,
+                        TradeType::Sell => p.inventory.add(self.items.clone(), time),
+                    }
+                    p.log.add(
+                        time,
+                        LogMsg::Overbid(self.items.clone(), best_money, self.user_trade_type),
+                    );
+                }
+            }
+            self.creator = None;
+            self.time_left = 0;
+        } else if matches!(self.highest_bidder, Some((best_user_id, _)) if best_user_id == user_id)
+        {
+            self.highest_bidder = None;
+        }
+    }
+
     pub fn bid(
         &mut self,
         players: &mut CustomMap<UserId, Player>,
         user_id: UserId,
         time: Time,
+        speed: u64,
     ) -> Option<()> {
-        if self.user_trade_type == TradeType::Buy {
-            if self.creator == Some(user_id) {
-                return None;
+        if self.creator == Some(user_id) {
+            return None;
+        }
+
+        match self.user_trade_type {
+            TradeType::Buy => {
+                if players.get_mut(&user_id)?.money >= self.next_bid {
+                    if let Some((best_bidder_user_id, best_bidder_money)) = self.highest_bidder {
+                        let p = players.get_mut(&best_bidder_user_id)?;
+                        p.money += best_bidder_money;
+                        p.log.add(
+                            time,
+                            LogMsg::Overbid(self.items.clone(), self.next_bid, self.user_trade_type),
+                        );
+                    }
+                    players.get_mut(&user_id)?.money -= self.next_bid;
+                    self.highest_bidder = Some((user_id, self.next_bid));
+                    self.next_bid += (self.next_bid / 10).max(1);
+                    if self.time_left < ONE_MINUTE * speed {
+                        self.time_left += ONE_MINUTE * speed;
+                    }
+                }
             }
-            if players.get_mut(&user_id)?.money >= self.next_bid {
-                if let Some((best_bidder_user_id, best_bidder_money)) = self.highest_bidder {
-                    let p = players.get_mut(&best_bidder_user_id)?;
-                    p.money += best_bidder_money;
-                    p.log.add(
-                        time,
-                        LogMsg::Overbid(self.items.clone(), self.next_bid, self.user_trade_type),
-                    );
+            TradeType::Sell => {
+                if self.next_bid == 0 {
+                    return None;
                 }
-                players.get_mut(&user_id)?.money -= self.next_bid;
-                self.highest_bidder = Some((user_id, self.next_bid));
-                self.next_bid += (self.next_bid / 10).max(1);
-                if self.time_left < ONE_MINUTE * SPEED {
-                    self.time_left += ONE_MINUTE * SPEED;
+                // The items of the seller are kept in the deal until it is settled.
+                if players
+                    .get_mut(&user_id)?
+                    .inventory
+                    .items
+                    .remove_checked(self.items.clone())
+                {
+                    if let Some((best_seller_user_id, _)) = self.highest_bidder {
+                        let p = players.get_mut(&best_seller_user_id)?;
+                        p.inventory.add(self.items.clone(), time);
+                        p.log.add(
+                            time,
+                            LogMsg::Overbid(self.items.clone(), self.next_bid, self.user_trade_type),
+                        );
+                    }
+                    self.highest_bidder = Some((user_id, self.next_bid));
+                    self.next_bid -= (self.next_bid / 10).max(1);
+                    if self.time_left < ONE_MINUTE * speed {
+                        self.time_left += ONE_MINUTE * speed;
+                    }
                 }
             }
         }
@@ -3377,9 +3791,78 @@ impl TradeDeal {
     }
 }
 
+// A sample of the progress of a player, `produced` counts the items produced per
+// occupation since the player joined.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PlayerStats {
+    pub time: Time,
+    pub level: u64,
+    pub dwarfs: u64,
+    pub food: Food,
+    pub money: Money,
+    pub efficiency: u64,
+    pub produced: Vec<(Occupation, u64)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WorldSummary {
+    pub time: Time,
+    pub winner: Option<UserId>,
+    pub king: Option<UserId>,
+    pub players: Vec<PlayerSummary>,
+    pub tribes: Vec<TribeSummary>,
+    #[serde(default)]
+    pub config: WorldConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlayerSummary {
+    pub user_id: UserId,
+    pub level: u64,
+    pub dwarfs: usize,
+    pub money: Money,
+    pub tribe: Option<TribeId>,
+    pub quests_won: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TribeSummary {
+    pub tribe_id: TribeId,
+    pub members: usize,
+    pub score: u64,
+    pub territories: CustomMap<Territory, u64>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Hash, Default)]
 pub struct Tribe {
     pub territories: CustomMap<Territory, u64>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub chief: Option<UserId>,
+    #[serde(default)]
+    pub officers: CustomSet<UserId>,
+    // Message of the day.
+    #[serde(default)]
+    pub motd: String,
+    // Fame points donated by the members.
+    #[serde(default)]
+    pub points: u64,
+    #[serde(default)]
+    pub chief_votes: CustomMap<UserId, UserId>,
+    #[serde(default)]
+    pub territory_votes: CustomMap<UserId, Territory>,
+    #[serde(default)]
+    pub treasury: Money,
+    #[serde(default)]
+    pub storehouse: Bundle<Item>,
+    // Taken from the money quest rewards of the members.
+    #[serde(default)]
+    pub tax_percent: u64,
+    #[serde(default)]
+    pub ledger: VecDeque<(Time, UserId, TribeTransaction)>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, PartialEq, Eq, Sequence, Display)]
@@ -3421,4 +3904,206 @@ impl Territory {
             },
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    pub(crate) fn players(num: i64) -> CustomMap<UserId, Player> {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut next_dwarf_id = 0;
+        (0..num)
+            .map(|id| {
+                let mut player = Player::new(0, &mut rng, &mut next_dwarf_id);
+                player.money = 1000;
+                player.inventory.items = Bundle::new().add(Item::Iron, 100);
+                (UserId(id), player)
+            })
+            .collect()
+    }
+
+    pub(crate) fn crafter(items: Bundle<Item>) -> Player {
+        let mut player = players(1).swap_remove(&UserId(0)).unwrap();
+        player.inventory.items = items;
+        player
+    }
+
+    fn run(trade_deal: &mut TradeDeal, players: &mut CustomMap<UserId, Player>) {
+        while !trade_deal.done() {
+            trade_deal.update(players, 0);
+        }
+    }
+
+    fn iron(player: &Player) -> u64 {
+        player.inventory.items.get(&Item::Iron).copied().unwrap_or(0)
+    }
+
+    #[test]
+    fn auction_pays_the_seller_and_refunds_outbid_players() {
+        let mut players = players(3);
+        let (seller, first, second) = (UserId(0), UserId(1), UserId(2));
+        let mut trade_deal = TradeDeal::from_player(
+            seller,
+            players.get_mut(&seller).unwrap(),
+            Item::Iron,
+            10,
+            100,
+            AUCTION_MIN_DURATION,
+        )
+        .unwrap();
+        let fee = TradeDeal::listing_fee(100);
+        assert_eq!(players.get(&seller).unwrap().money, 1000 - fee);
+        assert_eq!(iron(players.get(&seller).unwrap()), 90);
+
+        trade_deal.bid(&mut players, first, 0, 1).unwrap();
+        assert_eq!(players.get(&first).unwrap().money, 900);
+        trade_deal.bid(&mut players, second, 0, 1).unwrap();
+        assert_eq!(players.get(&first).unwrap().money, 1000);
+        assert_eq!(players.get(&second).unwrap().money, 890);
+
+        run(&mut trade_deal, &mut players);
+        assert_eq!(players.get(&seller).unwrap().money, 1000 - fee + 110);
+        assert_eq!(iron(players.get(&second).unwrap()), 110);
+        assert_eq!(iron(players.get(&first).unwrap()), 100);
+    }
+
+    #[test]
+    fn auction_without_bids_returns_the_items() {
+        let mut players = players(1);
+        let seller = UserId(0);
+        let mut trade_deal = TradeDeal::from_player(
+            seller,
+            players.get_mut(&seller).unwrap(),
+            Item::Iron,
+            10,
+            100,
+            AUCTION_MIN_DURATION,
+        )
+        .unwrap();
+
+        run(&mut trade_deal, &mut players);
+        assert_eq!(iron(players.get(&seller).unwrap()), 100);
+        assert_eq!(players.get(&seller).unwrap().money, 1000 - TradeDeal::listing_fee(100));
+    }
+
+    #[test]
+    fn sellers_cannot_bid_on_their_own_auction() {
+        let mut players = players(1);
+        let seller = UserId(0);
+        let mut trade_deal = TradeDeal::from_player(
+            seller,
+            players.get_mut(&seller).unwrap(),
+            Item::Iron,
+            10,
+            100,
+            AUCTION_MIN_DURATION,
+        )
+        .unwrap();
+
+        assert!(trade_deal.bid(&mut players, seller, 0, 1).is_none());
+        assert!(trade_deal.highest_bidder.is_none());
+    }
+
+    #[test]
+    fn buy_order_pays_the_seller_from_the_escrow() {
+        let mut players = players(2);
+        let (buyer, seller) = (UserId(0), UserId(1));
+        let mut trade_deal = TradeDeal::buy_order_from_player(
+            buyer,
+            players.get_mut(&buyer).unwrap(),
+            Item::Iron,
+            10,
+            100,
+            AUCTION_MIN_DURATION,
+        )
+        .unwrap();
+        let fee = TradeDeal::listing_fee(100);
+        assert_eq!(players.get(&buyer).unwrap().money, 1000 - 100 - fee);
+
+        trade_deal.bid(&mut players, seller, 0, 1).unwrap();
+        assert_eq!(iron(players.get(&seller).unwrap()), 90);
+
+        run(&mut trade_deal, &mut players);
+        assert_eq!(players.get(&seller).unwrap().money, 1100);
+        assert_eq!(iron(players.get(&buyer).unwrap()), 110);
+        assert_eq!(players.get(&buyer).unwrap().money, 1000 - 100 - fee);
+    }
+
+    #[test]
+    fn bots_leave_as_players_arrive_and_refund_their_auctions() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut state = State::new(WorldConfig {
+            bot_fill_players: 2,
+            ..Default::default()
+        });
+        let user_data = CustomMap::new();
+        state.run_bots(&mut rng, &user_data);
+        state.run_bots(&mut rng, &user_data);
+        assert_eq!(state.bots.len(), 2);
+        let bot_id = *state.bots.keys().last().unwrap();
+
+        let user_id = UserId(1);
+        engine_shared::State::update(
+            &mut state,
+            &mut rng,
+            Event::ClientEvent(ClientEvent::Init, user_id),
+            &user_data,
+        );
+        let player = state.players.get_mut(&user_id).unwrap();
+        player.money = 1000;
+        let mut trade_deal = TradeDeal::from_player(
+            bot_id,
+            state.players.get_mut(&bot_id).unwrap(),
+            Item::Iron,
+            10,
+            100,
+            AUCTION_MIN_DURATION,
+        )
+        .unwrap();
+        trade_deal.bid(&mut state.players, user_id, 0, 1).unwrap();
+        state.trade_deals.insert(0, trade_deal);
+        state.king = Some(bot_id);
+
+        state.run_bots(&mut rng, &user_data);
+        assert_eq!(state.bots.len(), 1);
+        assert!(!state.players.contains_key(&bot_id));
+        assert_eq!(state.king, None);
+        assert_eq!(state.players.get(&user_id).unwrap().money, 1000);
+        assert!(state.trade_deals.get(&0).unwrap().done());
+    }
+
+    #[test]
+    fn selling_lists_the_items_instead_of_paying_instantly() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut state = State::default();
+        let user_id = UserId(0);
+        let user_data = CustomMap::new();
+        engine_shared::State::update(
+            &mut state,
+            &mut rng,
+            Event::ClientEvent(ClientEvent::Init, user_id),
+            &user_data,
+        );
+        let player = state.players.get_mut(&user_id).unwrap();
+        player.money = 1000;
+        player.inventory.items = Bundle::new().add(Item::Iron, 100);
+
+        engine_shared::State::update(
+            &mut state,
+            &mut rng,
+            Event::ClientEvent(ClientEvent::Sell(Item::Iron, 10), user_id),
+            &user_data,
+        );
+
+        let player = state.players.get(&user_id).unwrap();
+        let price = TradeDeal::market_price(Item::Iron, 10);
+        assert_eq!(iron(player), 90);
+        assert_eq!(player.money, 1000 - TradeDeal::listing_fee(price));
+        assert_eq!(state.trade_deals.len(), 1);
+        let trade_deal = state.trade_deals.values().next().unwrap();
+        assert_eq!(trade_deal.creator, Some(user_id));
+        assert_eq!(trade_deal.next_bid, price);
+    }
+}
@@ -0,0 +1,480 @@
+use crate::{Bundle, Dwarf, DwarfId, Item, ItemType, Occupation, Player};
+use engine_shared::utils::custom_map::CustomMap;
+
+// Upper bound on the alternating rounds of occupation and equipment improvements.
+const MAX_OPTIMIZE_ROUNDS: usize = 10;
+
+impl Player {
+    // Reassigns the occupations of the managed dwarfs according to the manager and
+    // redistributes the equipment. If a dwarf is given, only its equipment is optimized.
+    //
+    // The greedy heuristic provides the starting point. Afterwards the occupations are
+    // assigned optimally for the current equipment and the equipment is improved for the
+    // new occupations in turns. Neither step lowers the total effectiveness, so the result
+    // is never worse than greedy.
+    pub fn optimize(&mut self, to_optimize_dwarf_id: Option<DwarfId>) {
+        let Some(dwarf_id) = to_optimize_dwarf_id else {
+            self.optimize_greedy();
+
+            let dwarf_ids = self.dwarfs_to_optimize(None);
+            self.improve_equipment(&dwarf_ids, false);
+            for _ in 0..MAX_OPTIMIZE_ROUNDS {
+                let before = self.total_effectiveness(None);
+                self.assign_optimal(&dwarf_ids);
+                self.improve_equipment(&dwarf_ids, false);
+                if self.total_effectiveness(None) <= before {
+                    break;
+                }
+            }
+            return;
+        };
+
+        self.unequip(&[dwarf_id]);
+        self.equip_greedy(&[dwarf_id], true);
+        self.improve_equipment(&[dwarf_id], true);
+    }
+
+    pub(crate) fn optimize_greedy(&mut self) {
+        self.set_manager();
+        self.assign_greedy();
+
+        let dwarf_ids = self.dwarfs_to_optimize(None);
+        self.unequip(&dwarf_ids);
+        self.equip_greedy(&dwarf_ids, false);
+    }
+
+    fn assign_greedy(&mut self) {
+        let mut occupations_to_fill = self.unassign_managed();
+
+        loop {
+            occupations_to_fill.retain(|_, num| *num > 0);
+
+            if occupations_to_fill.is_empty() {
+                break;
+            }
+
+            let mut best_dwarf_effectiveness = 0;
+            let mut best_dwarf_occupation = None;
+            let mut best_dwarf_id = None;
+            for (dwarf_id, dwarf) in &self.dwarfs {
+                if dwarf.occupation == Occupation::Idling && dwarf.can_be_managed() {
+                    for (occupation, _num) in &occupations_to_fill {
+                        let effectiveness = dwarf.stats.cross(occupation.requires_stats());
+                        if effectiveness >= best_dwarf_effectiveness {
+                            best_dwarf_effectiveness = effectiveness;
+                            best_dwarf_id = Some(*dwarf_id);
+                            best_dwarf_occupation = Some(*occupation);
+                        }
+                    }
+                }
+            }
+
+            if let Some(best_dwarf_id) = best_dwarf_id {
+                let best_dwarf_occupation =
+                    best_dwarf_occupation.expect("occupation known if id is known");
+                if let Some(best_dwarf) = self.dwarfs.get_mut(&best_dwarf_id) {
+                    best_dwarf.change_occupation(best_dwarf_occupation);
+                }
+                *occupations_to_fill
+                    .get_mut(&best_dwarf_occupation)
+                    .expect("occupation is always one that is to fill") -= 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    // Assigns the occupations such that the total effectiveness with the current equipment
+    // is maximal. The current assignment is one of the candidates, so this never lowers it.
+    fn assign_optimal(&mut self, dwarf_ids: &[DwarfId]) {
+        let occupations_to_fill = self.unassign_managed();
+
+        let mut slots = occupations_to_fill
+            .iter()
+            .flat_map(|(occupation, num)| std::iter::repeat_n(*occupation, *num as usize))
+            .collect::<Vec<_>>();
+        slots.truncate(dwarf_ids.len());
+
+        // Every slot of an occupation is a row, every dwarf a column. Maximizing the
+        // total effectiveness is minimizing the negated effectiveness.
+        let cost = slots
+            .iter()
+            .map(|occupation| {
+                dwarf_ids
+                    .iter()
+                    .map(|dwarf_id| {
+                        self.dwarfs
+                            .get(dwarf_id)
+                            .map(|dwarf| -(dwarf.effectiveness_not_normalized(*occupation) as i64))
+                            .unwrap_or(0)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        for (slot, dwarf_idx) in hungarian(&cost).into_iter().enumerate() {
+            if let Some(dwarf) = self.dwarfs.get_mut(&dwarf_ids[dwarf_idx]) {
+                dwarf.change_occupation(slots[slot]);
+            }
+        }
+    }
+
+    // Sets all managed dwarfs to idling and returns how many dwarfs each occupation needs.
+    fn unassign_managed(&mut self) -> CustomMap<Occupation, u64> {
+        let mut occupations_to_fill = self.manager.clone();
+        occupations_to_fill.swap_remove(&Occupation::Idling);
+
+        for dwarf in self.dwarfs.values_mut() {
+            if dwarf.can_be_managed() {
+                dwarf.occupation = Occupation::Idling;
+            }
+        }
+
+        occupations_to_fill
+    }
+
+    fn dwarfs_to_optimize(&self, to_optimize_dwarf_id: Option<DwarfId>) -> Vec<DwarfId> {
+        if let Some(dwarf_id) = to_optimize_dwarf_id {
+            vec![dwarf_id]
+        } else {
+            self.dwarfs
+                .iter()
+                .filter(|(_, dwarf)| dwarf.can_be_managed())
+                .map(|(dwarf_id, _)| *dwarf_id)
+                .collect()
+        }
+    }
+
+    pub(crate) fn total_effectiveness(&self, to_optimize_dwarf_id: Option<DwarfId>) -> u64 {
+        self.dwarfs_to_optimize(to_optimize_dwarf_id)
+            .iter()
+            .filter_map(|dwarf_id| self.dwarfs.get(dwarf_id))
+            .map(|dwarf| {
+                dwarf.effectiveness_not_normalized(occupation_to_optimize(
+                    dwarf,
+                    to_optimize_dwarf_id.is_some(),
+                ))
+            })
+            .sum()
+    }
+
+    fn unequip(&mut self, dwarf_ids: &[DwarfId]) {
+        for dwarf_id in dwarf_ids {
+            if let Some(dwarf) = self.dwarfs.get_mut(dwarf_id) {
+                for (_, item) in dwarf.equipment.drain(..) {
+                    self.inventory.items.add_checked(Bundle::new().add(item, 1));
+                }
+            }
+        }
+    }
+
+    // Repeatedly hands out the item that increases the effectiveness of a dwarf the most.
+    fn equip_greedy(&mut self, dwarf_ids: &[DwarfId], single: bool) {
+        loop {
+            let mut best_dwarf_effectiveness = 0;
+            let mut best_dwarf_id = None;
+            let mut best_dwarf_item = None;
+            for dwarf_id in dwarf_ids {
+                let Some(dwarf) = self.dwarfs.get(dwarf_id) else {
+                    continue;
+                };
+                let occupation = occupation_to_optimize(dwarf, single);
+                let effectiveness_before = dwarf.effectiveness_not_normalized(occupation);
+
+                for (item, _) in self.inventory.items.iter().filter(|(item, num)| {
+                    **num > 0
+                        && item
+                            .item_type()
+                            .map(|item_type| {
+                                item_type.equippable() && dwarf.equipment.get(&item_type).is_none()
+                            })
+                            .unwrap_or(false)
+                }) {
+                    let effectiveness_after = effectiveness_with(dwarf, occupation, Some(*item));
+                    let effectiveness_diff =
+                        effectiveness_after as i64 - effectiveness_before as i64;
+
+                    if effectiveness_diff > best_dwarf_effectiveness {
+                        best_dwarf_effectiveness = effectiveness_diff;
+                        best_dwarf_item = Some(*item);
+                        best_dwarf_id = Some(*dwarf_id);
+                    }
+                }
+            }
+
+            if let Some(best_dwarf_id) = best_dwarf_id {
+                let best_dwarf_item = best_dwarf_item.expect("item known if id is known");
+                if !self.equip(best_dwarf_id, best_dwarf_item) {
+                    break;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    // Local search on top of the greedy equipment: an equipped item is replaced by one from
+    // the inventory or swapped with the item of another dwarf whenever this increases the
+    // total effectiveness. Every step strictly improves the total, so this terminates.
+    fn improve_equipment(&mut self, dwarf_ids: &[DwarfId], single: bool) {
+        let item_types = enum_iterator::all::<ItemType>()
+            .filter(|item_type| item_type.equippable())
+            .collect::<Vec<_>>();
+
+        loop {
+            let mut improved = false;
+
+            for dwarf_id in dwarf_ids {
+                for item_type in &item_types {
+                    let candidates = self
+                        .inventory
+                        .items
+                        .iter()
+                        .filter(|(item, num)| **num > 0 && item.item_type() == Some(*item_type))
+                        .map(|(item, _)| *item)
+                        .collect::<Vec<_>>();
+
+                    for item in candidates {
+                        let Some(dwarf) = self.dwarfs.get(dwarf_id) else {
+                            continue;
+                        };
+                        let occupation = occupation_to_optimize(dwarf, single);
+                        if effectiveness_with(dwarf, occupation, Some(item))
+                            > dwarf.effectiveness_not_normalized(occupation)
+                            && self.equip(*dwarf_id, item)
+                        {
+                            improved = true;
+                        }
+                    }
+
+                    for other_id in dwarf_ids {
+                        if other_id == dwarf_id {
+                            continue;
+                        }
+                        let (Some(dwarf), Some(other)) =
+                            (self.dwarfs.get(dwarf_id), self.dwarfs.get(other_id))
+                        else {
+                            continue;
+                        };
+                        let item = dwarf.equipment.get(item_type).copied();
+                        let other_item = other.equipment.get(item_type).copied();
+                        if item == other_item {
+                            continue;
+                        }
+
+                        let occupation = occupation_to_optimize(dwarf, single);
+                        let other_occupation = occupation_to_optimize(other, single);
+                        let before = dwarf.effectiveness_not_normalized(occupation)
+                            + other.effectiveness_not_normalized(other_occupation);
+                        let after =
+                            effectiveness_with_slot(dwarf, occupation, *item_type, other_item)
+                                + effectiveness_with_slot(
+                                    other,
+                                    other_occupation,
+                                    *item_type,
+                                    item,
+                                );
+
+                        if after > before {
+                            set_slot(self.dwarfs.get_mut(dwarf_id), *item_type, other_item);
+                            set_slot(self.dwarfs.get_mut(other_id), *item_type, item);
+                            improved = true;
+                        }
+                    }
+                }
+            }
+
+            if !improved {
+                break;
+            }
+        }
+    }
+
+    // Moves the item from the inventory to the dwarf, a previously equipped item of the
+    // same type goes back to the inventory.
+    fn equip(&mut self, dwarf_id: DwarfId, item: Item) -> bool {
+        let Some(item_type) = item.item_type() else {
+            return false;
+        };
+        let Some(dwarf) = self.dwarfs.get_mut(&dwarf_id) else {
+            return false;
+        };
+        if !self
+            .inventory
+            .items
+            .remove_checked(Bundle::new().add(item, 1))
+        {
+            return false;
+        }
+        if let Some(previous) = dwarf.equipment.insert(item_type, item) {
+            self.inventory
+                .items
+                .add_checked(Bundle::new().add(previous, 1));
+        }
+        true
+    }
+}
+
+fn occupation_to_optimize(dwarf: &Dwarf, single: bool) -> Occupation {
+    if single {
+        dwarf.actual_occupation()
+    } else {
+        dwarf.occupation
+    }
+}
+
+fn effectiveness_with(dwarf: &Dwarf, occupation: Occupation, item: Option<Item>) -> u64 {
+    match item.and_then(|item| item.item_type()) {
+        Some(item_type) => effectiveness_with_slot(dwarf, occupation, item_type, item),
+        None => dwarf.effectiveness_not_normalized(occupation),
+    }
+}
+
+fn effectiveness_with_slot(
+    dwarf: &Dwarf,
+    occupation: Occupation,
+    item_type: ItemType,
+    item: Option<Item>,
+) -> u64 {
+    let mut dwarf_clone = dwarf.clone();
+    set_slot(Some(&mut dwarf_clone), item_type, item);
+    dwarf_clone.effectiveness_not_normalized(occupation)
+}
+
+fn set_slot(dwarf: Option<&mut Dwarf>, item_type: ItemType, item: Option<Item>) {
+    if let Some(dwarf) = dwarf {
+        match item {
+            Some(item) => {
+                dwarf.equipment.insert(item_type, item);
+            }
+            None => {
+                dwarf.equipment.swap_remove(&item_type);
+            }
+        }
+    }
+}
+
+// Solves the assignment problem with the Hungarian method for a cost matrix with at most as
+// many rows as columns. Returns the column assigned to every row such that the total cost
+// is minimal.
+fn hungarian(cost: &[Vec<i64>]) -> Vec<usize> {
+    let n = cost.len();
+    let m = cost.first().map(|row| row.len()).unwrap_or(0);
+    debug_assert!(n <= m);
+
+    // Potentials and matching are 1-indexed, column 0 is a virtual column.
+    let mut u = vec![0i64; n + 1];
+    let mut v = vec![0i64; m + 1];
+    let mut p = vec![0usize; m + 1];
+    let mut way = vec![0usize; m + 1];
+
+    for i in 1..=n {
+        p[0] = i;
+        let mut j0 = 0;
+        let mut minv = vec![i64::MAX; m + 1];
+        let mut used = vec![false; m + 1];
+
+        loop {
+            used[j0] = true;
+            let i0 = p[j0];
+            let mut delta = i64::MAX;
+            let mut j1 = 0;
+            for j in 1..=m {
+                if !used[j] {
+                    let cur = cost[i0 - 1][j - 1] - u[i0] - v[j];
+                    if cur < minv[j] {
+                        minv[j] = cur;
+                        way[j] = j0;
+                    }
+                    if minv[j] < delta {
+                        delta = minv[j];
+                        j1 = j;
+                    }
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    u[p[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    minv[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if p[j0] == 0 {
+                break;
+            }
+        }
+
+        loop {
+            let j1 = way[j0];
+            p[j0] = p[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut assignment = vec![0; n];
+    for j in 1..=m {
+        if p[j] != 0 {
+            assignment[p[j] - 1] = j - 1;
+        }
+    }
+    assignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Stats;
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    #[test]
+    fn optimize_is_never_worse_than_greedy_and_keeps_the_manager_quotas() {
+        let occupations = enum_iterator::all::<Occupation>().collect::<Vec<_>>();
+        let equipment = enum_iterator::all::<Item>()
+            .filter(|item| {
+                item.item_type()
+                    .is_some_and(|item_type| item_type.equippable())
+            })
+            .collect::<Vec<_>>();
+
+        for seed in 0..20 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let mut next_dwarf_id = 0;
+            let mut player = Player::new(0, &mut rng, &mut next_dwarf_id);
+            player.base.curr_level = 20;
+            while player.dwarfs.len() < player.base.max_dwarfs() {
+                player.new_dwarf(&mut rng, &mut next_dwarf_id, 0, Some(Stats::default()));
+            }
+            for _ in 0..rng.gen_range(0..30) {
+                let item = equipment[rng.gen_range(0..equipment.len())];
+                player
+                    .inventory
+                    .items
+                    .add_checked(Bundle::new().add(item, 1));
+            }
+            for _ in 0..rng.gen_range(0..player.dwarfs.len()) {
+                let occupation = occupations[rng.gen_range(0..occupations.len())];
+                *player.manager.entry(occupation).or_default() += 1;
+            }
+
+            let mut greedy = player.clone();
+            greedy.optimize_greedy();
+            player.optimize(None);
+
+            assert!(player.total_effectiveness(None) >= greedy.total_effectiveness(None));
+            assert_eq!(player.manager, greedy.manager);
+            for (occupation, num) in &player.manager {
+                let assigned = player
+                    .dwarfs
+                    .values()
+                    .filter(|dwarf| dwarf.can_be_managed() && dwarf.occupation == *occupation)
+                    .count() as u64;
+                assert_eq!(assigned, *num);
+            }
+        }
+    }
+}
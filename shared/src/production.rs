@@ -0,0 +1,325 @@
+use crate::{
+    Craftable, Item, ItemProbability, Occupation, Player, Time, WorldEvent, MAX_EFFECTIVENESS,
+    MAX_HEALTH, MIN_MAX_DWARF_DIFFERENCE, ONE_DAY, ONE_HOUR,
+};
+use engine_shared::utils::custom_map::CustomMap;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_FOOD_GOAL_DAYS: u64 = 30;
+// Maximum number of items per hour that can be set as a goal.
+pub const MAX_PRODUCTION_GOAL: u64 = 10000;
+
+// Crafting chains are expanded at most this deep when the goals are planned.
+const MAX_CRAFTING_DEPTH: u32 = 8;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Hash, Default)]
+pub struct ProductionGoals {
+    pub enabled: bool,
+    // Target number of items per hour.
+    pub items: CustomMap<Item, u64>,
+    // Minimum number of days the food storage should last.
+    pub food_days: u64,
+}
+
+// Expected number of items a dwarf with the given effectiveness produces per hour.
+pub fn expected_items_per_hour(
+    item: Item,
+    occupation: Occupation,
+    effectiveness: u64,
+    event: Option<&WorldEvent>,
+) -> f64 {
+    let numerator = effectiveness + MAX_EFFECTIVENESS / (MIN_MAX_DWARF_DIFFERENCE - 1);
+    expected_items_per_hour_with_numerator(item, occupation, numerator, event)
+}
+
+// This matches `Dwarf::gen_ratio_effectiveness` as it is used when the dwarfs work in
+// `State::update`.
+fn expected_items_per_hour_with_numerator(
+    item: Item,
+    occupation: Occupation,
+    numerator: u64,
+    event: Option<&WorldEvent>,
+) -> f64 {
+    let Some(ItemProbability {
+        expected_ticks_per_drop,
+    }) = item.item_probability(occupation)
+    else {
+        return 0.0;
+    };
+    let divider = event
+        .map(|event| event.occupation_divider(occupation))
+        .unwrap_or(1);
+    let denominator = MAX_EFFECTIVENESS / (MIN_MAX_DWARF_DIFFERENCE - 1);
+
+    ONE_HOUR as f64 * numerator as f64 / (denominator * expected_ticks_per_drop * divider) as f64
+}
+
+impl Player {
+    // Sets the manager such that the production goals are met as good as possible with the
+    // available dwarfs and reassigns the occupations accordingly.
+    pub fn plan_production(&mut self, event: Option<&WorldEvent>) {
+        let managed = self
+            .dwarfs
+            .values()
+            .filter(|dwarf| dwarf.can_be_managed())
+            .count() as u64;
+        let mut needed = CustomMap::<Occupation, u64>::new();
+
+        // No occupation can use more than the managed dwarfs, this also keeps the sums below
+        // from overflowing for huge goals.
+        for (item, rate) in self.raw_production_goals() {
+            if let Some((occupation, per_dwarf)) = self.best_occupation_for(item, event) {
+                let dwarfs = ((rate / per_dwarf).ceil() as u64).min(managed);
+                let entry = needed.entry(occupation).or_default();
+                *entry = (*entry).max(dwarfs);
+            }
+        }
+
+        let food_goal = self.production_goals.food_days * ONE_DAY;
+        if food_goal > 0 && self.time_until_starvation(event) < food_goal {
+            if let Some((occupation, food_per_dwarf)) = self.best_food_occupation(event) {
+                let dwarfs = ((self.food_consumption_per_hour(event) / food_per_dwarf).ceil()
+                    as u64)
+                    .min(managed);
+                let entry = needed.entry(occupation).or_default();
+                *entry = (*entry).max(dwarfs);
+            }
+        }
+
+        // If there are not enough dwarfs, all occupations are scaled down evenly.
+        let total = needed.values().sum::<u64>();
+        if total > managed {
+            for num in needed.values_mut() {
+                *num = *num * managed / total;
+            }
+        }
+
+        let working = needed.values().sum::<u64>();
+        self.manager = needed;
+        self.manager
+            .insert(Occupation::Idling, managed.saturating_sub(working));
+        self.optimize(None);
+    }
+
+    // Expected number of items per hour that the dwarfs produce with their current
+    // occupations, equipment and apprentices.
+    pub fn production_forecast(&self, event: Option<&WorldEvent>) -> CustomMap<Item, f64> {
+        let mut forecast = CustomMap::new();
+        for dwarf in self.dwarfs.values() {
+            if dwarf.dead() || !dwarf.is_adult() {
+                continue;
+            }
+            let occupation = dwarf.actual_occupation();
+            let numerator = dwarf.numerator_effectiveness(&self.dwarfs);
+            let drop_rate = dwarf.average_drop_rate_percent() as f64 / 100.0;
+            for item in enum_iterator::all::<Item>() {
+                let rate =
+                    expected_items_per_hour_with_numerator(item, occupation, numerator, event)
+                        * drop_rate;
+                if rate > 0.0 {
+                    *forecast.entry(item).or_default() += rate;
+                }
+            }
+        }
+        forecast
+    }
+
+    // Expected time until the items for the next base upgrade are in the inventory, crafted
+    // items are counted by their ingredients. Returns `None` if the upgrade is not possible
+    // with the current production.
+    pub fn time_until_upgrade_affordable(
+        &self,
+        event: Option<&WorldEvent>,
+        max_level: u64,
+    ) -> Option<Time> {
+        let requires = self.base.upgrade_cost(max_level)?;
+        let forecast = self.production_forecast(event);
+
+        let mut missing = CustomMap::new();
+        for (item, qty) in requires.iter() {
+            let owned = self.inventory.items.get(item).copied().unwrap_or(0);
+            if *qty > owned && !self.add_raw_goal(*item, (*qty - owned) as f64, &mut missing, 0) {
+                return None;
+            }
+        }
+
+        let mut time: Time = 0;
+        for (item, qty) in missing {
+            let rate = forecast.get(&item).copied().unwrap_or(0.0);
+            if rate <= 0.0 {
+                return None;
+            }
+            time = time.max((qty / rate * ONE_HOUR as f64).ceil() as Time);
+        }
+        Some(time)
+    }
+
+    // Expands the production goals into the items that the dwarfs have to produce per hour.
+    // Items that can't be produced by any occupation are replaced by their ingredients.
+    pub fn raw_production_goals(&self) -> CustomMap<Item, f64> {
+        let mut goals = CustomMap::new();
+        for (item, rate) in &self.production_goals.items {
+            self.add_raw_goal(*item, *rate as f64, &mut goals, 0);
+        }
+        goals
+    }
+
+    // Returns false if the item can neither be produced nor crafted from producible items.
+    fn add_raw_goal(
+        &self,
+        item: Item,
+        rate: f64,
+        goals: &mut CustomMap<Item, f64>,
+        depth: u32,
+    ) -> bool {
+        if self.can_produce(item) {
+            *goals.entry(item).or_default() += rate;
+            true
+        } else if let Some((_, requires)) = item.requires() {
+            depth < MAX_CRAFTING_DEPTH
+                && requires.iter().all(|(ingredient, qty)| {
+                    self.add_raw_goal(*ingredient, rate * *qty as f64, goals, depth + 1)
+                })
+        } else {
+            false
+        }
+    }
+
+    fn can_produce(&self, item: Item) -> bool {
+        self.unlocked_occupations()
+            .any(|occupation| item.item_probability(occupation).is_some())
+    }
+
+    fn unlocked_occupations(&self) -> impl Iterator<Item = Occupation> + '_ {
+        enum_iterator::all::<Occupation>().filter(|occupation| {
+            *occupation != Occupation::Idling
+                && occupation.unlocked_at_level() <= self.base.curr_level
+        })
+    }
+
+    // Average effectiveness of the managed dwarfs in an occupation with their current equipment.
+    fn average_effectiveness(&self, occupation: Occupation) -> u64 {
+        let managed = self
+            .dwarfs
+            .values()
+            .filter(|dwarf| dwarf.can_be_managed())
+            .collect::<Vec<_>>();
+        managed
+            .iter()
+            .map(|dwarf| dwarf.effectiveness_not_normalized(occupation))
+            .sum::<u64>()
+            .checked_div(managed.len() as u64)
+            .unwrap_or(0)
+    }
+
+    // The unlocked occupation that produces the item fastest and its rate per dwarf.
+    fn best_occupation_for(
+        &self,
+        item: Item,
+        event: Option<&WorldEvent>,
+    ) -> Option<(Occupation, f64)> {
+        self.unlocked_occupations()
+            .map(|occupation| {
+                (
+                    occupation,
+                    expected_items_per_hour(
+                        item,
+                        occupation,
+                        self.average_effectiveness(occupation),
+                        event,
+                    ),
+                )
+            })
+            .filter(|(_, rate)| *rate > 0.0)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    // The unlocked occupation that produces the most food and its food per dwarf and hour.
+    fn best_food_occupation(&self, event: Option<&WorldEvent>) -> Option<(Occupation, f64)> {
+        self.unlocked_occupations()
+            .map(|occupation| {
+                let effectiveness = self.average_effectiveness(occupation);
+                let food = enum_iterator::all::<Item>()
+                    .filter_map(|item| {
+                        let nutrition = item.nutritional_value()?;
+                        Some(
+                            expected_items_per_hour(item, occupation, effectiveness, event)
+                                * nutrition as f64,
+                        )
+                    })
+                    .sum::<f64>();
+                (occupation, food)
+            })
+            .filter(|(_, food)| *food > 0.0)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    fn health_cost_per_tick(&self, event: Option<&WorldEvent>) -> u64 {
+        let health_cost_multiplier = match event {
+            Some(WorldEvent::Plague) => (1 + self.dwarfs.len() as u64 / 20).min(5),
+            _ => 1,
+        };
+
+        self.dwarfs
+            .values()
+            .map(|dwarf| dwarf.health_cost_per_tick() * health_cost_multiplier)
+            .sum()
+    }
+
+    // Food needed per hour to make up for the health the dwarfs lose.
+    fn food_consumption_per_hour(&self, event: Option<&WorldEvent>) -> f64 {
+        (self.health_cost_per_tick(event) * ONE_HOUR) as f64 / (MAX_HEALTH / 1000) as f64
+    }
+
+    pub fn time_until_starvation(&self, event: Option<&WorldEvent>) -> Time {
+        let health_cost_per_tick = self.health_cost_per_tick(event);
+        if health_cost_per_tick == 0 {
+            return u64::MAX;
+        }
+
+        let health_available = self.base.food * (MAX_HEALTH / 1000)
+            + self.dwarfs.values().map(|dwarf| dwarf.health).sum::<u64>();
+        health_available / health_cost_per_tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientEvent, Event, State, UserId};
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    #[test]
+    fn huge_production_goals_are_clamped_and_planned() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut state = State::default();
+        let user_id = UserId(0);
+        let user_data = CustomMap::new();
+        for event in [
+            ClientEvent::Init,
+            ClientEvent::ToggleProductionGoals,
+            ClientEvent::SetProductionGoal(Item::Iron, u64::MAX),
+            ClientEvent::SetProductionGoal(Item::Coal, u64::MAX / 2),
+            ClientEvent::SetFoodGoal(u64::MAX),
+        ] {
+            engine_shared::State::update(
+                &mut state,
+                &mut rng,
+                Event::ClientEvent(event, user_id),
+                &user_data,
+            );
+        }
+
+        let player = state.players.get(&user_id).unwrap();
+        assert_eq!(
+            player.production_goals.items.get(&Item::Iron).copied(),
+            Some(MAX_PRODUCTION_GOAL)
+        );
+        let managed = player
+            .dwarfs
+            .values()
+            .filter(|dwarf| dwarf.can_be_managed())
+            .count() as u64;
+        assert_eq!(player.manager.values().sum::<u64>(), managed);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::{Dwarf, Occupation, Perk, Time, ONE_DAY, ONE_HOUR};
+use enum_iterator::Sequence;
+use rand::{seq::IteratorRandom, Rng};
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
+pub const MAX_TRAITS: usize = 2;
+const TRAIT_PROBABILITY: f64 = 0.4;
+const TRAIT_INHERITANCE_PROBABILITY: f64 = 0.5;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Sequence, PartialEq, Eq, Display, Hash)]
+#[strum(serialize_all = "title_case")]
+pub enum DwarfTrait {
+    NightOwl,
+    Glutton,
+    Frugal,
+    Claustrophobic,
+    Lucky,
+    Brave,
+    QuickLearner,
+}
+
+impl DwarfTrait {
+    pub fn description(self) -> &'static str {
+        match self {
+            DwarfTrait::NightOwl => "Finds more items at night, but less during the day.",
+            DwarfTrait::Glutton => "Loses health faster and needs more food.",
+            DwarfTrait::Frugal => "Loses health slower and needs less food.",
+            DwarfTrait::Claustrophobic => "Is less effective in the mines and when rockhounding.",
+            DwarfTrait::Lucky => "Finds more items in every occupation.",
+            DwarfTrait::Brave => "Achieves a higher score in quests.",
+            DwarfTrait::QuickLearner => "Improves the stats twice as fast.",
+        }
+    }
+
+    fn conflicts_with(self, other: Self) -> bool {
+        self == other
+            || matches!(
+                (self, other),
+                (DwarfTrait::Glutton, DwarfTrait::Frugal)
+                    | (DwarfTrait::Frugal, DwarfTrait::Glutton)
+            )
+    }
+
+    pub fn random(rng: &mut impl Rng) -> Vec<DwarfTrait> {
+        let mut traits = Vec::new();
+        for _ in 0..MAX_TRAITS {
+            if rng.gen_bool(TRAIT_PROBABILITY) {
+                add_trait(
+                    &mut traits,
+                    enum_iterator::all::<DwarfTrait>().choose(rng).unwrap(),
+                );
+            }
+        }
+        traits
+    }
+
+    // Each trait of the parents is passed on with some probability, the rest is random.
+    pub fn inherit(
+        mother: &[DwarfTrait],
+        father: &[DwarfTrait],
+        rng: &mut impl Rng,
+    ) -> Vec<DwarfTrait> {
+        let mut traits = Vec::new();
+        for dwarf_trait in mother.iter().chain(father) {
+            if rng.gen_bool(TRAIT_INHERITANCE_PROBABILITY) {
+                add_trait(&mut traits, *dwarf_trait);
+            }
+        }
+        for dwarf_trait in DwarfTrait::random(rng) {
+            add_trait(&mut traits, dwarf_trait);
+        }
+        traits
+    }
+}
+
+fn add_trait(traits: &mut Vec<DwarfTrait>, new_trait: DwarfTrait) {
+    if traits.len() < MAX_TRAITS && !traits.iter().any(|t| t.conflicts_with(new_trait)) {
+        traits.push(new_trait);
+    }
+}
+
+fn is_night(time: Time) -> bool {
+    let hour = time % ONE_DAY / ONE_HOUR;
+    !(6..22).contains(&hour)
+}
+
+impl Dwarf {
+    pub fn has_trait(&self, dwarf_trait: DwarfTrait) -> bool {
+        self.traits.contains(&dwarf_trait)
+    }
+
+    // Applied to the effectiveness, so the optimizer takes it into account.
+    pub fn trait_effectiveness_percent(&self, occupation: Occupation) -> u64 {
+        if self.has_trait(DwarfTrait::Claustrophobic)
+            && matches!(occupation, Occupation::Mining | Occupation::Rockhounding)
+        {
+            60
+        } else {
+            100
+        }
+    }
+
+    pub fn drop_rate_percent(&self, time: Time) -> u64 {
+        let mut percent = 100;
+        if self.has_trait(DwarfTrait::Lucky) {
+            percent = percent * 120 / 100;
+        }
+        if self.has_trait(DwarfTrait::NightOwl) {
+            percent = if is_night(time) {
+                percent * 150 / 100
+            } else {
+                percent * 75 / 100
+            };
+        }
+        percent
+    }
+
+    // The drop rate averaged over a whole day, used for the forecasts.
+    pub fn average_drop_rate_percent(&self) -> u64 {
+        (0..24)
+            .map(|hour| self.drop_rate_percent(hour * ONE_HOUR))
+            .sum::<u64>()
+            / 24
+    }
+
+    pub fn health_cost_per_tick(&self) -> u64 {
+        let occupation = self.actual_occupation();
+        let mut cost = occupation.health_cost_per_tick();
+        if self.has_perk(occupation, Perk::Efficient) {
+            cost = cost.saturating_sub(1).max(1);
+        }
+        let cost = cost
+            + self
+                .ailment
+                .map(|ailment| ailment.extra_health_cost_per_tick())
+                .unwrap_or(0);
+        if self.has_trait(DwarfTrait::Glutton) {
+            cost * 3 / 2
+        } else if self.has_trait(DwarfTrait::Frugal) {
+            (cost * 3 / 4).max(1)
+        } else {
+            cost
+        }
+    }
+
+    pub fn quest_score_percent(&self) -> u64 {
+        if self.has_trait(DwarfTrait::Brave) {
+            130
+        } else {
+            100
+        }
+    }
+
+    pub fn improvement_multiplier(&self) -> u64 {
+        if self.has_trait(DwarfTrait::QuickLearner) {
+            2
+        } else {
+            1
+        }
+    }
+}
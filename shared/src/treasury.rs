@@ -0,0 +1,171 @@
+use crate::{Bundle, Item, LogMsg, Money, Player, State, Time, Tribe, TribeId, TribeRole, UserId};
+use engine_shared::utils::custom_map::CustomMap;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_TRIBE_TAX_PERCENT: u64 = 25;
+const MAX_LEDGER_ENTRIES: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Hash)]
+pub enum TribeTransaction {
+    DepositMoney(Money),
+    // Withdrawn by the chief for the given member.
+    WithdrawMoney(UserId, Money),
+    DepositItems(Bundle<Item>),
+    WithdrawItems(UserId, Bundle<Item>),
+    Tax(Money),
+    TaxChanged(u64),
+}
+
+impl Tribe {
+    fn record(&mut self, time: Time, user_id: UserId, transaction: TribeTransaction) {
+        self.ledger.push_back((time, user_id, transaction));
+        if self.ledger.len() > MAX_LEDGER_ENTRIES {
+            self.ledger.pop_front();
+        }
+    }
+
+    // Takes the tax from a quest reward and returns it.
+    fn collect_tax(&mut self, user_id: UserId, money: Money, time: Time) -> Money {
+        let tax = money * self.tax_percent / 100;
+        if tax > 0 {
+            self.treasury += tax;
+            self.record(time, user_id, TribeTransaction::Tax(tax));
+        }
+        tax
+    }
+}
+
+impl Player {
+    // Money from quest rewards, minus the tax of the tribe.
+    pub(crate) fn add_quest_money(
+        &mut self,
+        user_id: UserId,
+        money: Money,
+        tribes: &mut CustomMap<TribeId, Tribe>,
+        time: Time,
+    ) {
+        let tax = self
+            .tribe
+            .and_then(|tribe_id| tribes.get_mut(&tribe_id))
+            .map(|tribe| tribe.collect_tax(user_id, money, time))
+            .unwrap_or(0);
+        self.money += money - tax;
+        if tax > 0 {
+            self.log.add(time, LogMsg::TribeTax(tax));
+        }
+    }
+}
+
+impl State {
+    pub fn deposit_tribe_money(&mut self, user_id: UserId, money: Money) -> Option<()> {
+        let player = self.players.get_mut(&user_id)?;
+        let tribe = self.tribes.get_mut(&player.tribe?)?;
+        if money > 0 && player.money >= money {
+            player.money -= money;
+            tribe.treasury += money;
+            tribe.record(self.time, user_id, TribeTransaction::DepositMoney(money));
+        }
+        Some(())
+    }
+
+    pub fn deposit_tribe_items(&mut self, user_id: UserId, item: Item, qty: u64) -> Option<()> {
+        let player = self.players.get_mut(&user_id)?;
+        let tribe = self.tribes.get_mut(&player.tribe?)?;
+        let items = Bundle::new().add(item, qty);
+        if qty > 0 && player.inventory.items.remove_checked(items.clone()) {
+            tribe.storehouse.add_checked(items.clone());
+            tribe.record(self.time, user_id, TribeTransaction::DepositItems(items));
+        }
+        Some(())
+    }
+
+    // Only the chief can withdraw, either for themselves or for another member, so that a
+    // single officer can't empty the treasury.
+    pub fn withdraw_tribe_money(
+        &mut self,
+        user_id: UserId,
+        recipient_id: UserId,
+        money: Money,
+    ) -> Option<()> {
+        let tribe_id = self.players.get(&user_id)?.tribe?;
+        let recipient = self.players.get_mut(&recipient_id)?;
+        let tribe = self.tribes.get_mut(&tribe_id)?;
+        if recipient.tribe != Some(tribe_id) || tribe.role(user_id) != TribeRole::Chief {
+            return None;
+        }
+        if money > 0 && tribe.treasury >= money {
+            tribe.treasury -= money;
+            recipient.money += money;
+            tribe.record(
+                self.time,
+                user_id,
+                TribeTransaction::WithdrawMoney(recipient_id, money),
+            );
+        }
+        Some(())
+    }
+
+    pub fn withdraw_tribe_items(
+        &mut self,
+        user_id: UserId,
+        recipient_id: UserId,
+        item: Item,
+        qty: u64,
+    ) -> Option<()> {
+        let tribe_id = self.players.get(&user_id)?.tribe?;
+        let recipient = self.players.get_mut(&recipient_id)?;
+        let tribe = self.tribes.get_mut(&tribe_id)?;
+        if recipient.tribe != Some(tribe_id) || tribe.role(user_id) != TribeRole::Chief {
+            return None;
+        }
+        let items = Bundle::new().add(item, qty);
+        if qty > 0 && tribe.storehouse.remove_checked(items.clone()) {
+            recipient.inventory.add(items.clone(), self.time);
+            tribe.record(
+                self.time,
+                user_id,
+                TribeTransaction::WithdrawItems(recipient_id, items),
+            );
+        }
+        Some(())
+    }
+
+    pub fn set_tribe_tax(&mut self, user_id: UserId, percent: u64) -> Option<()> {
+        let tribe = self.tribes.get_mut(&self.players.get(&user_id)?.tribe?)?;
+        if tribe.role(user_id) != TribeRole::Chief || percent > MAX_TRIBE_TAX_PERCENT {
+            return None;
+        }
+        tribe.tax_percent = percent;
+        tribe.record(self.time, user_id, TribeTransaction::TaxChanged(percent));
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::players;
+
+    #[test]
+    fn only_the_chief_can_withdraw_from_the_treasury() {
+        let mut state = State {
+            players: players(2),
+            ..Default::default()
+        };
+        for player in state.players.values_mut() {
+            player.tribe = Some(0);
+        }
+        let tribe = state.tribes.get_mut(&0).unwrap();
+        tribe.chief = Some(UserId(0));
+        tribe.officers.insert(UserId(1));
+        tribe.treasury = 500;
+
+        assert!(state
+            .withdraw_tribe_money(UserId(1), UserId(1), 500)
+            .is_none());
+        assert_eq!(state.players.get(&UserId(1)).unwrap().money, 1000);
+        state.withdraw_tribe_money(UserId(0), UserId(1), 200);
+        assert_eq!(state.players.get(&UserId(1)).unwrap().money, 1200);
+        assert_eq!(state.tribes.get(&0).unwrap().treasury, 300);
+    }
+}
@@ -0,0 +1,434 @@
+use crate::{Dwarf, State, Territory, Time, Tribe, TribeId, UserId, ONE_DAY};
+use engine_shared::{utils::custom_map::CustomMap, GameId};
+use rand::RngCore;
+use rand_chacha::{rand_core::SeedableRng, ChaCha8Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use strum::Display;
+
+pub const MAX_TRIBE_NAME_LENGTH: usize = 30;
+pub const MAX_TRIBE_MOTD_LENGTH: usize = 500;
+pub const MAX_TRIBE_OFFICERS: usize = 3;
+// Members that haven't been online for this long can be kicked by the leaders.
+pub const KICK_INACTIVE_TIME: Time = ONE_DAY * 7;
+
+// Name and color of a tribe without a custom name, they are derived from the ids so that
+// every world has different tribes.
+pub fn default_tribe_name(tribe_id: TribeId, game_id: GameId) -> (String, String) {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}-{}", tribe_id, game_id).as_bytes());
+    let mut rng = ChaCha8Rng::from_seed(hasher.finalize().into());
+    let name = format!("{} Tribe", Dwarf::name(&mut rng));
+    let color = format!(
+        "rgb({}, {}, {})",
+        rng.next_u32() % 192,
+        rng.next_u32() % 192,
+        rng.next_u32() % 192
+    );
+    (name, color)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Hash, PartialEq, Eq, Display)]
+pub enum TribeRole {
+    Chief,
+    Officer,
+    Member,
+}
+
+impl TribeRole {
+    // Leaders can spend the shared points, kick inactive members and set the message of the day.
+    pub fn is_leader(self) -> bool {
+        matches!(self, TribeRole::Chief | TribeRole::Officer)
+    }
+}
+
+impl Tribe {
+    pub fn role(&self, user_id: UserId) -> TribeRole {
+        if self.chief == Some(user_id) {
+            TribeRole::Chief
+        } else if self.officers.contains(&user_id) {
+            TribeRole::Officer
+        } else {
+            TribeRole::Member
+        }
+    }
+
+    pub fn chief_votes_for(&self, candidate: UserId) -> usize {
+        self.chief_votes
+            .values()
+            .filter(|vote| **vote == candidate)
+            .count()
+    }
+
+    pub fn territory_votes_for(&self, territory: Territory) -> usize {
+        self.territory_votes
+            .values()
+            .filter(|vote| **vote == territory)
+            .count()
+    }
+
+    // The territory with the most votes, if there is no tie.
+    pub fn voted_territory(&self) -> Option<Territory> {
+        let (territory, max) = enum_iterator::all::<Territory>()
+            .map(|territory| (territory, self.territory_votes_for(territory)))
+            .max_by_key(|(_, votes)| *votes)?;
+
+        let unique = max > 0
+            && enum_iterator::all::<Territory>()
+                .filter(|territory| self.territory_votes_for(*territory) >= max)
+                .count()
+                == 1;
+
+        if unique {
+            Some(territory)
+        } else {
+            None
+        }
+    }
+
+    // A tribe without a chief gets the candidate with the most votes, a chief is replaced
+    // by a candidate that has the votes of the majority of the members.
+    fn elect_chief(&mut self, members: usize) {
+        let mut votes = CustomMap::<UserId, usize>::new();
+        for candidate in self.chief_votes.values() {
+            *votes.entry(*candidate).or_default() += 1;
+        }
+
+        if let Some((candidate, votes)) = votes
+            .iter()
+            .max_by_key(|(_, votes)| **votes)
+            .map(|(candidate, votes)| (*candidate, *votes))
+        {
+            if self.chief.is_none() || (self.chief != Some(candidate) && votes * 2 > members) {
+                self.officers.swap_remove(&candidate);
+                self.chief = Some(candidate);
+            }
+        }
+    }
+
+    pub(crate) fn remove_member(&mut self, user_id: UserId) {
+        if self.chief == Some(user_id) {
+            self.chief = None;
+        }
+        self.officers.swap_remove(&user_id);
+        self.chief_votes.swap_remove(&user_id);
+        self.chief_votes
+            .retain(|_, candidate| *candidate != user_id);
+        self.territory_votes.swap_remove(&user_id);
+    }
+}
+
+impl State {
+    pub fn tribe_members(&self, tribe_id: TribeId) -> Vec<UserId> {
+        self.players
+            .iter()
+            .filter(|(_, player)| player.tribe == Some(tribe_id))
+            .map(|(user_id, _)| *user_id)
+            .collect()
+    }
+
+    // The tribe of the user, if the user has at least the given role.
+    fn tribe_with_role(&mut self, user_id: UserId, required: TribeRole) -> Option<&mut Tribe> {
+        let tribe_id = self.players.get(&user_id)?.tribe?;
+        let tribe = self.tribes.get_mut(&tribe_id)?;
+        let allowed = match required {
+            TribeRole::Chief => tribe.role(user_id) == TribeRole::Chief,
+            TribeRole::Officer => tribe.role(user_id).is_leader(),
+            TribeRole::Member => true,
+        };
+        if allowed {
+            Some(tribe)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_tribe_name(&mut self, user_id: UserId, name: String) -> Option<()> {
+        let tribe = self.tribe_with_role(user_id, TribeRole::Chief)?;
+        let name = name.trim();
+        tribe.name = if name.is_empty() {
+            None
+        } else {
+            Some(name.chars().take(MAX_TRIBE_NAME_LENGTH).collect())
+        };
+        Some(())
+    }
+
+    pub fn set_tribe_motd(&mut self, user_id: UserId, motd: String) -> Option<()> {
+        let tribe = self.tribe_with_role(user_id, TribeRole::Officer)?;
+        tribe.motd = motd.trim().chars().take(MAX_TRIBE_MOTD_LENGTH).collect();
+        Some(())
+    }
+
+    // The chief appoints the officers and can hand over the leadership to another member.
+    pub fn set_tribe_role(
+        &mut self,
+        user_id: UserId,
+        member_id: UserId,
+        role: TribeRole,
+    ) -> Option<()> {
+        let tribe_id = self.players.get(&user_id)?.tribe?;
+        if user_id == member_id || self.players.get(&member_id)?.tribe != Some(tribe_id) {
+            return None;
+        }
+        let tribe = self.tribe_with_role(user_id, TribeRole::Chief)?;
+
+        match role {
+            TribeRole::Chief => {
+                tribe.officers.swap_remove(&member_id);
+                // The old chief stays an officer if there is room.
+                if tribe.officers.len() < MAX_TRIBE_OFFICERS {
+                    tribe.officers.insert(user_id);
+                }
+                tribe.chief = Some(member_id);
+            }
+            TribeRole::Officer => {
+                if tribe.officers.len() < MAX_TRIBE_OFFICERS {
+                    tribe.officers.insert(member_id);
+                }
+            }
+            TribeRole::Member => {
+                tribe.officers.swap_remove(&member_id);
+            }
+        }
+
+        Some(())
+    }
+
+    // Kicked members join the weakest tribe.
+    pub fn kick_tribe_member(&mut self, user_id: UserId, member_id: UserId) -> Option<()> {
+        let tribe_id = self.players.get(&user_id)?.tribe?;
+        let member = self.players.get(&member_id)?;
+        if member.tribe != Some(tribe_id)
+            || (self.time - member.last_online) / self.config.speed < KICK_INACTIVE_TIME
+        {
+            return None;
+        }
+        let new_tribe_id = self.weakest_tribe(Some(tribe_id))?;
+
+        let tribe = self.tribe_with_role(user_id, TribeRole::Officer)?;
+        // Officers can only kick members, the chief can also kick officers.
+        match (tribe.role(user_id), tribe.role(member_id)) {
+            (_, TribeRole::Member) | (TribeRole::Chief, TribeRole::Officer) => {}
+            _ => return None,
+        }
+        tribe.remove_member(member_id);
+
+        let member = self.players.get_mut(&member_id)?;
+        member.tribe = Some(new_tribe_id);
+        member.tribe_joined = self.time;
+        self.leave_battles(member_id);
+
+        Some(())
+    }
+
+    pub fn vote_chief(&mut self, user_id: UserId, candidate: Option<UserId>) -> Option<()> {
+        let tribe_id = self.players.get(&user_id)?.tribe?;
+        if let Some(candidate) = candidate {
+            if self.players.get(&candidate)?.tribe != Some(tribe_id) {
+                return None;
+            }
+        }
+        let members = self.tribe_members(tribe_id).len();
+
+        let tribe = self.tribes.get_mut(&tribe_id)?;
+        if let Some(candidate) = candidate {
+            tribe.chief_votes.insert(user_id, candidate);
+        } else {
+            tribe.chief_votes.swap_remove(&user_id);
+        }
+        tribe.elect_chief(members);
+
+        Some(())
+    }
+
+    pub fn vote_territory(&mut self, user_id: UserId, territory: Option<Territory>) -> Option<()> {
+        let tribe_id = self.players.get(&user_id)?.tribe?;
+        let tribe = self.tribes.get_mut(&tribe_id)?;
+        if let Some(territory) = territory {
+            tribe.territory_votes.insert(user_id, territory);
+        } else {
+            tribe.territory_votes.swap_remove(&user_id);
+        }
+        Some(())
+    }
+
+    pub fn donate_tribe_points(&mut self, user_id: UserId, points: u64) -> Option<()> {
+        let player = self.players.get_mut(&user_id)?;
+        let tribe = self.tribes.get_mut(&player.tribe?)?;
+        if points > 0 && player.tribe_points >= points {
+            player.tribe_points -= points;
+            tribe.points += points;
+        }
+        Some(())
+    }
+
+    pub fn spend_shared_tribe_points(
+        &mut self,
+        user_id: UserId,
+        territory: Territory,
+        points: u64,
+    ) -> Option<()> {
+        let tribe = self.tribe_with_role(user_id, TribeRole::Officer)?;
+        if points > 0 && tribe.points >= points {
+            tribe.points -= points;
+            *tribe.territories.entry(territory).or_default() += points;
+        }
+        Some(())
+    }
+
+    // Shared points that the leaders haven't spent go to the territory the members voted for.
+    pub(crate) fn spend_voted_tribe_points(&mut self) {
+        for tribe in self.tribes.values_mut() {
+            if let Some(territory) = tribe.voted_territory() {
+                *tribe.territories.entry(territory).or_default() += tribe.points;
+                tribe.points = 0;
+            }
+        }
+    }
+
+    // Sum of the levels of the active members, used to balance the tribes.
+    pub fn tribe_strength(&self, tribe_id: TribeId) -> u64 {
+        self.players
+            .values()
+            .filter(|player| {
+                player.is_active(self.time, self.config.speed) && player.tribe == Some(tribe_id)
+            })
+            .map(|player| player.base.curr_level)
+            .sum()
+    }
+
+    pub fn weakest_tribe(&self, except: Option<TribeId>) -> Option<TribeId> {
+        self.tribes
+            .keys()
+            .filter(|tribe_id| Some(**tribe_id) != except)
+            .min_by_key(|tribe_id| self.tribe_strength(**tribe_id))
+            .copied()
+    }
+
+    pub fn tribe_switch_time_left(&self, user_id: UserId) -> Option<Time> {
+        let player = self.players.get(&user_id)?;
+        let cooldown = self
+            .config
+            .tribe_switch_cooldown
+            .saturating_mul(self.config.speed);
+        Some(
+            player
+                .tribe_joined
+                .saturating_add(cooldown)
+                .saturating_sub(self.time),
+        )
+    }
+
+    // Players can only switch to a tribe that isn't stronger than their current one, so the
+    // tribes stay balanced.
+    pub fn can_switch_tribe(&self, user_id: UserId, tribe_id: TribeId) -> bool {
+        let Some(player) = self.players.get(&user_id) else {
+            return false;
+        };
+        let Some(old_tribe_id) = player.tribe else {
+            return false;
+        };
+
+        old_tribe_id != tribe_id
+            && self.tribes.contains_key(&tribe_id)
+            && self.tribe_switch_time_left(user_id) == Some(0)
+            && self.tribe_strength(tribe_id) + player.base.curr_level
+                <= self.tribe_strength(old_tribe_id)
+    }
+
+    pub fn switch_tribe(&mut self, user_id: UserId, tribe_id: TribeId) -> Option<()> {
+        if !self.can_switch_tribe(user_id, tribe_id) {
+            return None;
+        }
+
+        let player = self.players.get_mut(&user_id)?;
+        let old_tribe_id = player.tribe.replace(tribe_id)?;
+        player.tribe_joined = self.time;
+        player.tribe_chat_unread = false;
+        self.tribes.get_mut(&old_tribe_id)?.remove_member(user_id);
+        self.leave_battles(user_id);
+
+        Some(())
+    }
+
+    // The dwarfs don't fight for the old tribe anymore.
+    pub(crate) fn leave_battles(&mut self, user_id: UserId) {
+        if let Some(player) = self.players.get_mut(&user_id) {
+            for dwarf in player.dwarfs.values_mut() {
+                dwarf.participates_in_battle = None;
+            }
+        }
+        for battle in self.battles.values_mut() {
+            battle.fighters.swap_remove(&user_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tests::players, Battle, Territory, WorldConfig};
+
+    #[test]
+    fn tribe_leadership_keeps_the_officer_limit_and_releases_kicked_fighters() {
+        let mut state = State {
+            players: players(3),
+            ..Default::default()
+        };
+        for player in state.players.values_mut() {
+            player.tribe = Some(0);
+        }
+        let tribe = state.tribes.get_mut(&0).unwrap();
+        tribe.chief = Some(UserId(0));
+        for id in 0..MAX_TRIBE_OFFICERS as i64 {
+            tribe.officers.insert(UserId(100 + id));
+        }
+
+        state.set_tribe_role(UserId(0), UserId(1), TribeRole::Chief);
+        let tribe = state.tribes.get(&0).unwrap();
+        assert_eq!(tribe.chief, Some(UserId(1)));
+        assert!(tribe.officers.len() <= MAX_TRIBE_OFFICERS);
+
+        let player = state.players.get_mut(&UserId(2)).unwrap();
+        let dwarf_id = *player.dwarfs.keys().next().unwrap();
+        player
+            .dwarfs
+            .get_mut(&dwarf_id)
+            .unwrap()
+            .participates_in_battle = Some(0);
+        let mut battle = Battle::new(Territory::Forest, 0, None);
+        battle.fighters.insert(UserId(2), vec![dwarf_id]);
+        state.battles.insert(0, battle);
+        state.time = KICK_INACTIVE_TIME * state.config.speed;
+
+        state.kick_tribe_member(UserId(1), UserId(2));
+        let player = state.players.get(&UserId(2)).unwrap();
+        assert_ne!(player.tribe, Some(0));
+        assert!(player
+            .dwarfs
+            .get(&dwarf_id)
+            .unwrap()
+            .participates_in_battle
+            .is_none());
+        assert!(state.battles.get(&0).unwrap().fighters.is_empty());
+    }
+
+    #[test]
+    fn tribe_switch_cooldown_is_real_time() {
+        let mut state = State {
+            players: players(1),
+            ..State::new(WorldConfig {
+                speed: 20,
+                ..Default::default()
+            })
+        };
+        state.players.get_mut(&UserId(0)).unwrap().tribe = Some(0);
+        let cooldown = state.config.tribe_switch_cooldown;
+
+        state.time = cooldown;
+        assert_eq!(state.tribe_switch_time_left(UserId(0)), Some(cooldown * 19));
+        state.time = cooldown * 20;
+        assert_eq!(state.tribe_switch_time_left(UserId(0)), Some(0));
+    }
+}
@@ -0,0 +1,35 @@
+use crate::{Time, ONE_DAY};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Hash, PartialEq, Eq)]
+#[serde(default)]
+pub struct WorldConfig {
+    pub num_tribes: u64,
+    // Real time a player has to stay in a tribe before switching to another one.
+    pub tribe_switch_cooldown: Time,
+    // Ticks per second.
+    pub speed: u64,
+    pub max_level: u64,
+    pub join_tribe_level: u64,
+    pub improvement_duration: u32,
+    pub winner_num_premium_days: i64,
+    pub winner_tribe_num_premium_days: i64,
+    // Bots join while fewer players are active, off unless an admin enables them.
+    pub bot_fill_players: usize,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        WorldConfig {
+            num_tribes: 3,
+            tribe_switch_cooldown: ONE_DAY * 3,
+            speed: if cfg!(debug_assertions) { 20 } else { 1 },
+            max_level: 100,
+            join_tribe_level: 16,
+            improvement_duration: ONE_DAY as u32 * 5,
+            winner_num_premium_days: 30,
+            winner_tribe_num_premium_days: 7,
+            bot_fill_players: 0,
+        }
+    }
+}
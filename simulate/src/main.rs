@@ -0,0 +1,207 @@
+use engine_shared::{utils::custom_map::CustomMap, Event, State as _};
+use rand::{rngs::SmallRng, SeedableRng};
+use shared::{
+    BotKind, BotStrategy, ClientEvent, DwarfId, ServerEvent, State, Time, UserData, UserId,
+    WorldConfig, DEATH_AGE, ONE_DAY, ONE_HOUR,
+};
+use std::{env, process};
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+struct Config {
+    players: usize,
+    strategy: Option<BotKind>,
+    days: u64,
+    seed: u64,
+    sample_interval: Time,
+}
+
+impl Config {
+    fn from_args() -> Self {
+        let mut config = Config {
+            players: 20,
+            strategy: None,
+            days: 28,
+            seed: 0,
+            sample_interval: ONE_HOUR,
+        };
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args.next();
+            match (arg.as_str(), value) {
+                ("--strategy", Some(value)) => {
+                    config.strategy = enum_iterator::all::<BotKind>()
+                        .find(|bot_kind| bot_kind.to_string().replace(' ', "-").to_lowercase() == value);
+                    if config.strategy.is_none() {
+                        usage();
+                    }
+                }
+                (arg, Some(value)) => match (arg, value.parse::<u64>()) {
+                    ("--players", Ok(value)) => config.players = value as usize,
+                    ("--days", Ok(value)) => config.days = value,
+                    ("--seed", Ok(value)) => config.seed = value,
+                    ("--sample-hours", Ok(value)) => config.sample_interval = value.max(1) * ONE_HOUR,
+                    _ => usage(),
+                },
+                _ => usage(),
+            }
+        }
+
+        config
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: simulate [--players N] [--days N] [--seed N] [--sample-hours N] [--strategy greedy-crafter|quest-hunter|food-safe-idler]"
+    );
+    process::exit(1);
+}
+
+fn main() {
+    let config = Config::from_args();
+
+    if cfg!(debug_assertions) {
+        eprintln!("warning: debug builds start players with extra levels, use --release for balancing");
+    }
+
+    let mut rng = SmallRng::seed_from_u64(config.seed);
+    // One step per tick, so the simulated days don't depend on the build. The world bots
+    // are disabled to only measure the simulated players.
+    let mut state = State::new(WorldConfig {
+        speed: 1,
+        bot_fill_players: 0,
+        ..Default::default()
+    });
+
+    let joined = PrimitiveDateTime::new(
+        OffsetDateTime::UNIX_EPOCH.date(),
+        OffsetDateTime::UNIX_EPOCH.time(),
+    );
+    let user_data = (0..config.players as i64)
+        .map(|id| {
+            (
+                UserId(id),
+                UserData {
+                    username: format!("simulated-{id}"),
+                    premium: 0,
+                    games_won: 0,
+                    admin: false,
+                    guest: false,
+                    joined,
+                    referrer: None,
+                },
+            )
+        })
+        .collect::<CustomMap<UserId, UserData>>();
+
+    // The simulated players are regular players that are controlled by a bot strategy,
+    // so unlike the bots of a world they can win.
+    let strategies = user_data
+        .keys()
+        .enumerate()
+        .map(|(idx, user_id)| {
+            let bot_kind = config.strategy.unwrap_or_else(|| {
+                enum_iterator::all::<BotKind>().cycle().nth(idx).unwrap()
+            });
+            (*user_id, bot_kind)
+        })
+        .collect::<CustomMap<UserId, BotKind>>();
+
+    for user_id in strategies.keys() {
+        state.update(&mut rng, Event::ClientEvent(ClientEvent::Init, *user_id), &user_data);
+    }
+
+    let mut starvation_deaths = CustomMap::<UserId, u64>::new();
+    let mut winner = None;
+
+    println!("time,user_id,strategy,base_level,money,dwarfs,adult_dwarfs,food,starvation_deaths,winner,winner_time");
+
+    for tick in 0..config.days * ONE_DAY {
+        if tick % config.sample_interval == 0 {
+            for (user_id, bot_kind) in &strategies {
+                let events = state
+                    .players
+                    .get(user_id)
+                    .map(|player| bot_kind.events(*user_id, player, &state))
+                    .unwrap_or_default();
+                for event in events {
+                    state.update(&mut rng, Event::ClientEvent(event, *user_id), &user_data);
+                }
+            }
+
+            print_sample(&state, &strategies, &starvation_deaths, winner);
+        }
+
+        // Dwarfs that could starve, old dwarfs, fighters and sick dwarfs die of other causes.
+        let starving_dwarfs = state
+            .players
+            .iter()
+            .map(|(user_id, player)| {
+                (
+                    *user_id,
+                    player
+                        .dwarfs
+                        .iter()
+                        .filter(|(_, dwarf)| {
+                            dwarf.age_years() <= DEATH_AGE
+                                && !dwarf.released
+                                && dwarf.participates_in_battle.is_none()
+                                && dwarf.ailment.is_none()
+                        })
+                        .map(|(dwarf_id, _)| *dwarf_id)
+                        .collect::<Vec<DwarfId>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        state.update(&mut rng, Event::ServerEvent(ServerEvent::Tick), &user_data);
+
+        for (user_id, dwarf_ids) in starving_dwarfs {
+            if let Some(player) = state.players.get(&user_id) {
+                if player.base.food > 0 {
+                    continue;
+                }
+                let starved = dwarf_ids
+                    .iter()
+                    .filter(|dwarf_id| !player.dwarfs.contains_key(*dwarf_id))
+                    .count() as u64;
+                *starvation_deaths.entry(user_id).or_default() += starved;
+            }
+        }
+
+        if let Some(user_id) = state.winner() {
+            winner = Some((user_id, state.time));
+            print_sample(&state, &strategies, &starvation_deaths, winner);
+            break;
+        }
+    }
+}
+
+fn print_sample(
+    state: &State,
+    strategies: &CustomMap<UserId, BotKind>,
+    starvation_deaths: &CustomMap<UserId, u64>,
+    winner: Option<(UserId, Time)>,
+) {
+    for (user_id, player) in &state.players {
+        println!(
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            state.time,
+            user_id.0,
+            strategies
+                .get(user_id)
+                .or_else(|| state.bots.get(user_id))
+                .map(|bot_kind| bot_kind.to_string())
+                .unwrap_or_default(),
+            player.base.curr_level,
+            player.money,
+            player.dwarfs.len(),
+            player.dwarfs.values().filter(|dwarf| dwarf.is_adult()).count(),
+            player.base.food,
+            starvation_deaths.get(user_id).copied().unwrap_or_default(),
+            winner.map(|(user_id, _)| user_id.0.to_string()).unwrap_or_default(),
+            winner.map(|(_, time)| time.to_string()).unwrap_or_default(),
+        );
+    }
+}